@@ -0,0 +1,174 @@
+//! # Alertmanager
+//!
+//! ServiceAlerts can declare a maintenance window, during which their alerts
+//! are silenced. Rather than deleting a ServiceAlert to stop it paging, and
+//! then forgetting to restore it, the controller creates an Alertmanager
+//! silence that ends on its own.
+//!
+//! Silences are found again by their `createdBy` field, which is unique to
+//! each ServiceAlert, so no state has to be kept in Kubernetes.
+
+use chrono::{DateTime, Utc};
+use hyper::{
+    body, client::HttpConnector, header::CONTENT_TYPE, Body, Client, Method, Request, StatusCode,
+};
+use hyper_openssl::HttpsConnector;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use thiserror::Error;
+
+use crate::{config::Alertmanager, crd::PromDuration};
+
+use self::silence::PostSilenceResponse;
+pub use self::silence::*;
+
+mod silence;
+
+#[derive(Debug, Error)]
+pub enum AlertmanagerError {
+    #[error("Failed to set up TLS for Alertmanager client: {0}")]
+    Tls(#[from] openssl::error::ErrorStack),
+    #[error("Failed to build Alertmanager request: {0}")]
+    Request(#[from] hyper::http::Error),
+    #[error("Alertmanager request failed: {0}")]
+    Http(#[from] hyper::Error),
+    #[error("Alertmanager responded with {status}: {body}")]
+    Status { status: StatusCode, body: String },
+    #[error("Alertmanager request timed out after {0}")]
+    Timeout(PromDuration),
+    #[error("Failed to (de)serialise silences: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Client for the silences of the Alertmanager v2 API.
+#[derive(Clone, Debug)]
+pub struct AlertmanagerClient {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: String,
+    timeout: PromDuration,
+}
+
+impl AlertmanagerClient {
+    /// Creates a new [`AlertmanagerClient`] from the `alertmanager` section of
+    /// the Cactuar config, or [`None`] if no URL is configured.
+    pub fn new(config: &Alertmanager) -> Result<Option<Self>, AlertmanagerError> {
+        let Some(url) = &config.url else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            client: Client::builder().build(HttpsConnector::new()?),
+            url: url.trim_end_matches('/').to_string(),
+            timeout: config.timeout,
+        }))
+    }
+
+    /// Makes Alertmanager hold exactly the `desired` silence for `created_by`,
+    /// expiring any others. Returns the ID of the silence, if there is one.
+    pub async fn sync(
+        &self,
+        created_by: &str,
+        desired: Option<&Silence>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<String>, AlertmanagerError> {
+        let mut silence_id = None;
+
+        for existing in self.owned_silences(created_by).await? {
+            let Some(id) = existing.id.clone() else {
+                continue;
+            };
+            match desired {
+                Some(desired) if silence_id.is_none() && existing.satisfies(desired, now) => {
+                    silence_id = Some(id);
+                }
+                _ => {
+                    tracing::debug!(id, "Expiring stale silence");
+                    self.expire(&id).await?;
+                }
+            }
+        }
+
+        match (desired, silence_id) {
+            (Some(desired), None) => {
+                tracing::debug!("Creating maintenance silence");
+                Ok(Some(self.create(desired).await?))
+            }
+            (_, silence_id) => Ok(silence_id),
+        }
+    }
+
+    /// Expires every live silence created by `created_by`.
+    pub async fn expire_all(&self, created_by: &str) -> Result<(), AlertmanagerError> {
+        for existing in self.owned_silences(created_by).await? {
+            if let Some(id) = &existing.id {
+                self.expire(id).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the live silences created by `created_by`. Silences without an
+    /// ID can't be expired, and are left alone by callers.
+    async fn owned_silences(&self, created_by: &str) -> Result<Vec<Silence>, AlertmanagerError> {
+        let request = Request::get(format!("{0}/api/v2/silences", self.url)).body(Body::empty())?;
+        let body = self.send(request).await?;
+
+        let silences: Vec<Silence> = serde_json::from_slice(&body)?;
+        Ok(silences
+            .into_iter()
+            .filter(|silence| silence.created_by == created_by && silence.is_live())
+            .collect())
+    }
+
+    async fn create(&self, silence: &Silence) -> Result<String, AlertmanagerError> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{0}/api/v2/silences", self.url))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(silence)?))?;
+        let body = self.send(request).await?;
+
+        let response: PostSilenceResponse = serde_json::from_slice(&body)?;
+        Ok(response.silence_id)
+    }
+
+    async fn expire(&self, id: &str) -> Result<(), AlertmanagerError> {
+        let url = format!(
+            "{0}/api/v2/silence/{1}",
+            self.url,
+            utf8_percent_encode(id, NON_ALPHANUMERIC)
+        );
+        let request = Request::delete(url).body(Body::empty())?;
+
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Sends `request` and reads the response body, giving up after the
+    /// configured timeout.
+    async fn send(&self, request: Request<Body>) -> Result<body::Bytes, AlertmanagerError> {
+        let response = async { success_body(self.client.request(request).await?).await };
+
+        tokio::time::timeout(self.timeout.as_duration(), response)
+            .await
+            .map_err(|_| AlertmanagerError::Timeout(self.timeout))?
+    }
+}
+
+/// Reads the response body, turning any non-2xx response into an error.
+async fn success_body(response: hyper::Response<Body>) -> Result<body::Bytes, AlertmanagerError> {
+    let status = response.status();
+    let body = body::to_bytes(response.into_body()).await?;
+
+    if !status.is_success() {
+        return Err(AlertmanagerError::Status {
+            status,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        });
+    }
+
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests;
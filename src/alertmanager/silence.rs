@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{crd::Maintenance, prometheus::alert::PromAlerts};
+
+/// A silence, as read from and written to the Alertmanager v2 API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Silence {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub matchers: Vec<Matcher>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub created_by: String,
+    pub comment: String,
+    /// Only set by Alertmanager.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SilenceStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Matcher {
+    pub name: String,
+    pub value: String,
+    pub is_regex: bool,
+    #[serde(default = "default_is_equal")]
+    pub is_equal: bool,
+}
+
+// Older Alertmanagers leave `isEqual` out, as they only support equality
+fn default_is_equal() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SilenceStatus {
+    pub state: SilenceState,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SilenceState {
+    Active,
+    Pending,
+    Expired,
+}
+
+/// Response to creating a silence.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct PostSilenceResponse {
+    #[serde(rename = "silenceID")]
+    pub silence_id: String,
+}
+
+impl Silence {
+    /// Builds the silence covering the rules in `alerts` for a maintenance
+    /// window, or [`None`] if there is nothing left to silence, either because
+    /// the window has ended or because there are no rules.
+    pub fn for_maintenance(
+        maintenance: &Maintenance,
+        alerts: &PromAlerts,
+        created_by: &str,
+        now: DateTime<Utc>,
+    ) -> Option<Self> {
+        if maintenance.end <= now {
+            return None;
+        }
+
+        Some(Silence {
+            id: None,
+            matchers: silence_matchers(alerts)?,
+            starts_at: maintenance.start,
+            ends_at: maintenance.end,
+            created_by: created_by.to_string(),
+            comment: maintenance.reason.clone(),
+            status: None,
+        })
+    }
+
+    /// Whether Alertmanager still considers this silence, i.e. it has not
+    /// expired.
+    pub fn is_live(&self) -> bool {
+        !matches!(
+            self.status,
+            Some(SilenceStatus {
+                state: SilenceState::Expired
+            })
+        )
+    }
+
+    /// Whether an existing silence already does what `desired` would.
+    /// Alertmanager moves the start of silences created in the past to the
+    /// time they were created, so once `desired` has started, any later start
+    /// is accepted.
+    pub fn satisfies(&self, desired: &Silence, now: DateTime<Utc>) -> bool {
+        let started = desired.starts_at <= now && self.starts_at >= desired.starts_at;
+
+        self.matchers == desired.matchers
+            && self.ends_at == desired.ends_at
+            && self.comment == desired.comment
+            && (started || self.starts_at == desired.starts_at)
+    }
+}
+
+/// Matches every alert generated from `alerts`: any of the rule names, along
+/// with the labels every rule shares, such as the owner, external labels and
+/// the `service_alert` label that keeps the silence to a single ServiceAlert.
+/// Returns [`None`] if there are no rules to match.
+pub fn silence_matchers(alerts: &PromAlerts) -> Option<Vec<Matcher>> {
+    let rules: Vec<_> = alerts
+        .groups
+        .iter()
+        .flat_map(|group| group.rules.iter())
+        .collect();
+    let first = rules.first()?;
+
+    let mut names: Vec<String> = rules.iter().map(|rule| regex_escape(&rule.alert)).collect();
+    names.sort();
+    names.dedup();
+
+    let mut shared = BTreeMap::from([
+        (String::from("owner"), first.labels.owner.clone()),
+        (String::from("source"), first.labels.source.clone()),
+    ]);
+    shared.extend(first.labels.extra.clone());
+    shared.retain(|key, value| {
+        rules.iter().all(|rule| match key.as_str() {
+            "owner" => &rule.labels.owner == value,
+            "source" => &rule.labels.source == value,
+            _ => rule.labels.extra.get(key) == Some(value),
+        })
+    });
+
+    let mut matchers = vec![Matcher {
+        name: String::from("alertname"),
+        value: names.join("|"),
+        is_regex: true,
+        is_equal: true,
+    }];
+    matchers.extend(shared.into_iter().map(|(name, value)| Matcher {
+        name,
+        value,
+        is_regex: false,
+        is_equal: true,
+    }));
+
+    Some(matchers)
+}
+
+/// Escapes the characters that have a meaning in Alertmanager's RE2 regexes.
+fn regex_escape(value: &str) -> String {
+    value
+        .chars()
+        .fold(String::with_capacity(value.len()), |mut escaped, c| {
+            if r"\.+*?()|[]{}^$".contains(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            escaped
+        })
+}
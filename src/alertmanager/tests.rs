@@ -0,0 +1,386 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing, Json, Router,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use color_eyre::Result;
+use pretty_assertions::assert_eq;
+
+use crate::{
+    alertmanager::{
+        silence_matchers, AlertmanagerClient, AlertmanagerError, Matcher, Silence, SilenceState,
+        SilenceStatus,
+    },
+    config::Alertmanager,
+    crd::{Maintenance, ServiceAlert, ServiceAlertSpec},
+    prometheus::alert::{
+        AlertGroup, AlertRules, Annotations, Labels, PromAlerts, PrometheusSeverity,
+    },
+};
+
+const CREATED_BY: &str = "servicealert.cactuar.rs/services/example";
+
+/// In-memory stand-in for the Alertmanager silences API.
+#[derive(Clone, Default)]
+struct StandIn {
+    silences: Arc<Mutex<Vec<Silence>>>,
+}
+
+impl StandIn {
+    fn states(&self) -> Vec<(String, SilenceState)> {
+        self.silences
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|silence| {
+                (
+                    silence.created_by.clone(),
+                    silence.status.as_ref().unwrap().state,
+                )
+            })
+            .collect()
+    }
+}
+
+async fn list_silences(State(stand_in): State<StandIn>) -> Json<Vec<Silence>> {
+    Json(stand_in.silences.lock().unwrap().clone())
+}
+
+async fn create_silence(
+    State(stand_in): State<StandIn>,
+    Json(mut silence): Json<Silence>,
+) -> Json<BTreeMap<String, String>> {
+    let mut silences = stand_in.silences.lock().unwrap();
+    let id = format!("silence-{0}", silences.len());
+
+    // Alertmanager starts silences from the past when they are created
+    let now = Utc::now();
+    let state = if silence.starts_at <= now {
+        silence.starts_at = now;
+        SilenceState::Active
+    } else {
+        SilenceState::Pending
+    };
+    silence.id = Some(id.clone());
+    silence.status = Some(SilenceStatus { state });
+    silences.push(silence);
+
+    Json(BTreeMap::from([(String::from("silenceID"), id)]))
+}
+
+async fn expire_silence(State(stand_in): State<StandIn>, Path(id): Path<String>) -> StatusCode {
+    let mut silences = stand_in.silences.lock().unwrap();
+    match silences
+        .iter_mut()
+        .find(|silence| silence.id.as_deref() == Some(id.as_str()))
+    {
+        Some(silence) => {
+            silence.status = Some(SilenceStatus {
+                state: SilenceState::Expired,
+            });
+            StatusCode::OK
+        }
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+/// Serves the stand-in on a random local port, returning a client for it.
+fn serve(stand_in: StandIn) -> Result<AlertmanagerClient> {
+    let router = Router::new()
+        .route(
+            "/api/v2/silences",
+            routing::get(list_silences).post(create_silence),
+        )
+        .route("/api/v2/silence/:id", routing::delete(expire_silence))
+        .with_state(stand_in);
+
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(router.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    Ok(AlertmanagerClient::new(&Alertmanager {
+        url: Some(format!("http://{addr}/")),
+        ..Alertmanager::default()
+    })?
+    .expect("url is configured"))
+}
+
+fn rule(alert: &str, extra: &[(&str, &str)]) -> AlertRules {
+    AlertRules {
+        alert: alert.into(),
+        expr: String::from("vector(1) > 0"),
+        for_: "5m".into(),
+        keep_firing_for: None,
+        labels: Labels {
+            severity: PrometheusSeverity::Warning,
+            source: "cloud".into(),
+            owner: "foo".into(),
+            extra: extra
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        },
+        annotations: Annotations {
+            summary: "summary".into(),
+            description: "description".into(),
+            extra: BTreeMap::new(),
+        },
+    }
+}
+
+fn alerts() -> PromAlerts {
+    PromAlerts {
+        groups: vec![AlertGroup {
+            name: String::from("Replica Alerts"),
+            interval: None,
+            limit: None,
+            rules: vec![
+                rule("ReplicaCountLow", &[("cluster", "eu-west-1")]),
+                rule(
+                    "ReplicaCountLow (critical)",
+                    &[("cluster", "eu-west-1"), ("team", "payments")],
+                ),
+            ],
+        }],
+    }
+}
+
+fn maintenance(start: DateTime<Utc>, end: DateTime<Utc>) -> Maintenance {
+    Maintenance {
+        start,
+        end,
+        reason: String::from("Database upgrade"),
+    }
+}
+
+#[test]
+fn test_silence_matchers_cover_rules_and_shared_labels() {
+    let matchers = silence_matchers(&alerts()).expect("there are rules");
+
+    let matcher = |name: &str, value: &str, is_regex| Matcher {
+        name: name.into(),
+        value: value.into(),
+        is_regex,
+        is_equal: true,
+    };
+    assert_eq!(
+        matchers,
+        vec![
+            matcher(
+                "alertname",
+                r"ReplicaCountLow|ReplicaCountLow \(critical\)",
+                true
+            ),
+            matcher("cluster", "eu-west-1", false),
+            matcher("owner", "foo", false),
+            matcher("source", "cloud", false),
+        ]
+    );
+}
+
+#[test]
+fn test_silence_matchers_keep_to_one_service_alert() -> Result<()> {
+    // Both belong to the same owner and have the same rule names
+    let service_alert = |name: &str| -> Result<ServiceAlert> {
+        let spec: ServiceAlertSpec = serde_json::from_value(serde_json::json!({
+            "commonLabels": {"owner": "foo", "origin": "cloud"},
+            "deploymentName": name,
+            "alerts": {},
+        }))?;
+        let mut service_alert = ServiceAlert::new(name, spec);
+        service_alert.metadata.namespace = Some("services".into());
+        Ok(service_alert)
+    };
+    let checkout =
+        silence_matchers(&alerts().with_service_alert_label(&service_alert("checkout")?))
+            .expect("there are rules");
+    let billing = silence_matchers(&alerts().with_service_alert_label(&service_alert("billing")?))
+        .expect("there are rules");
+
+    let identity = |matchers: &[Matcher]| {
+        matchers
+            .iter()
+            .find(|matcher| matcher.name == "service_alert")
+            .map(|matcher| (matcher.value.clone(), matcher.is_regex))
+    };
+    assert_eq!(
+        identity(&checkout),
+        Some((String::from("services/checkout"), false))
+    );
+    assert_eq!(
+        identity(&billing),
+        Some((String::from("services/billing"), false))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_silence_for_maintenance() {
+    let start = Utc.with_ymd_and_hms(2023, 6, 1, 20, 0, 0).unwrap();
+    let end = start + Duration::hours(2);
+    let window = maintenance(start, end);
+
+    let silence = Silence::for_maintenance(&window, &alerts(), CREATED_BY, start)
+        .expect("window has not ended");
+    assert_eq!(silence.starts_at, start);
+    assert_eq!(silence.ends_at, end);
+    assert_eq!(silence.comment, "Database upgrade");
+
+    assert_eq!(
+        Silence::for_maintenance(&window, &alerts(), CREATED_BY, end),
+        None
+    );
+    assert_eq!(
+        Silence::for_maintenance(&window, &PromAlerts { groups: vec![] }, CREATED_BY, start),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_sync_creates_silence_once() -> Result<()> {
+    let stand_in = StandIn::default();
+    let alertmanager = serve(stand_in.clone())?;
+
+    let now = Utc::now();
+    let window = maintenance(now - Duration::hours(1), now + Duration::hours(1));
+    let silence = Silence::for_maintenance(&window, &alerts(), CREATED_BY, now);
+
+    let first = alertmanager.sync(CREATED_BY, silence.as_ref(), now).await?;
+    let second = alertmanager.sync(CREATED_BY, silence.as_ref(), now).await?;
+
+    assert_eq!(first, Some(String::from("silence-0")));
+    assert_eq!(first, second);
+    assert_eq!(
+        stand_in.states(),
+        vec![(String::from(CREATED_BY), SilenceState::Active)]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sync_replaces_changed_silence() -> Result<()> {
+    let stand_in = StandIn::default();
+    let alertmanager = serve(stand_in.clone())?;
+
+    let now = Utc::now();
+    let start = now + Duration::hours(1);
+    let silence = Silence::for_maintenance(
+        &maintenance(start, start + Duration::hours(1)),
+        &alerts(),
+        CREATED_BY,
+        now,
+    );
+    alertmanager.sync(CREATED_BY, silence.as_ref(), now).await?;
+
+    let extended = Silence::for_maintenance(
+        &maintenance(start, start + Duration::hours(3)),
+        &alerts(),
+        CREATED_BY,
+        now,
+    );
+    let id = alertmanager
+        .sync(CREATED_BY, extended.as_ref(), now)
+        .await?;
+
+    assert_eq!(id, Some(String::from("silence-1")));
+    assert_eq!(
+        stand_in.states(),
+        vec![
+            (String::from(CREATED_BY), SilenceState::Expired),
+            (String::from(CREATED_BY), SilenceState::Pending),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sync_expires_only_own_silences() -> Result<()> {
+    let stand_in = StandIn::default();
+    let alertmanager = serve(stand_in.clone())?;
+
+    let now = Utc::now();
+    let window = maintenance(now, now + Duration::hours(1));
+    let other = "servicealert.cactuar.rs/services/other";
+    for created_by in [CREATED_BY, other] {
+        let silence = Silence::for_maintenance(&window, &alerts(), created_by, now);
+        alertmanager.sync(created_by, silence.as_ref(), now).await?;
+    }
+
+    assert_eq!(alertmanager.sync(CREATED_BY, None, now).await?, None);
+    alertmanager.expire_all(CREATED_BY).await?;
+
+    assert_eq!(
+        stand_in.states(),
+        vec![
+            (String::from(CREATED_BY), SilenceState::Expired),
+            (String::from(other), SilenceState::Active),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_silences_without_id_are_skipped() -> Result<()> {
+    let stand_in = StandIn::default();
+    let alertmanager = serve(stand_in.clone())?;
+
+    let now = Utc::now();
+    let window = maintenance(now, now + Duration::hours(1));
+    let mut silence = Silence::for_maintenance(&window, &alerts(), CREATED_BY, now)
+        .expect("window has not ended");
+    silence.status = Some(SilenceStatus {
+        state: SilenceState::Active,
+    });
+    stand_in.silences.lock().unwrap().push(silence);
+
+    // Expiring it would send a DELETE without an ID, which is rejected
+    assert_eq!(alertmanager.sync(CREATED_BY, None, now).await?, None);
+    alertmanager.expire_all(CREATED_BY).await?;
+
+    assert_eq!(
+        stand_in.states(),
+        vec![(String::from(CREATED_BY), SilenceState::Active)]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_gives_up_on_slow_responses() -> Result<()> {
+    let router = Router::new().route(
+        "/api/v2/silences",
+        routing::get(|| async {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            Json(Vec::<Silence>::new())
+        }),
+    );
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(router.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    let client = AlertmanagerClient::new(&Alertmanager {
+        url: Some(format!("http://{addr}/")),
+        timeout: std::time::Duration::from_millis(100).into(),
+    })?
+    .expect("url is configured");
+
+    assert!(matches!(
+        client.expire_all(CREATED_BY).await,
+        Err(AlertmanagerError::Timeout(_))
+    ));
+
+    Ok(())
+}
@@ -22,7 +22,7 @@ async fn main() -> Result<()> {
     logging::install_observability(subscriber)?;
 
     // Start kubernetes controller
-    let control_future = controller_future().await;
+    let control_future = controller_future(&config).await;
     tokio::task::Builder::new()
         .name("K8s Controller")
         .spawn(control_future)?;
@@ -1,15 +1,30 @@
 //! # CRD Generator
 //!
 //! This binary simply generates and prints the Kubernetes Custom Resource
-//! Definition for Cactuar.
+//! Definitions for Cactuar, separated as YAML documents.
 //!
 //! Run `cargo make helm-crd` to run this binary and export the CRD
 //! specification to the Helm chart directory.
 
 use kube::CustomResourceExt;
 
-use cactuar::crd::ServiceAlert;
+use cactuar::crd::{
+    ClusterServiceAlert, ClusterServiceAlertTemplate, ServiceAlert, ServiceAlertDefaults,
+    ServiceAlertTemplate,
+};
 
 fn main() {
-    print!("{}", serde_yaml::to_string(&ServiceAlert::crd()).unwrap())
+    let crds = [
+        ServiceAlert::crd(),
+        ClusterServiceAlert::crd(),
+        ServiceAlertTemplate::crd(),
+        ClusterServiceAlertTemplate::crd(),
+        ServiceAlertDefaults::crd(),
+    ];
+    let documents: Vec<String> = crds
+        .iter()
+        .map(|crd| serde_yaml::to_string(crd).unwrap())
+        .collect();
+
+    print!("{}", documents.join("---\n"))
 }
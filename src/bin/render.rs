@@ -0,0 +1,70 @@
+//! # Render
+//!
+//! This binary renders a `ServiceAlert` manifest into the Prometheus rule file
+//! Cactuar would generate for it, along with a `promtool` test document that
+//! checks the rules fire when their thresholds are crossed. Alerts that can't
+//! be tested are reported as warnings. The manifest is
+//! also written out with its `profile` expanded, to show the alerts it stands
+//! for. Templates live in the cluster, so they are not resolved here. Alerts
+//! without a window use the `prometheus.window` of the Cactuar config, see
+//! [`cactuar::config`].
+//!
+//! ```bash
+//! cargo run --bin render -- example-custom-resource.yaml target/rules
+//! promtool check rules target/rules/*.rules.yaml
+//! promtool test rules target/rules/*.test.yaml
+//! ```
+
+use std::{env, fs, path::PathBuf};
+
+use color_eyre::{eyre::eyre, Result};
+use kube::ResourceExt;
+
+use cactuar::{
+    config::CactuarConfig,
+    crd::ServiceAlert,
+    prometheus::{alert::PromAlerts, promtool::PromToolTests},
+};
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    let mut args = env::args().skip(1);
+    let (manifest, output_dir) = match (args.next(), args.next()) {
+        (Some(manifest), Some(output_dir)) => (manifest, PathBuf::from(output_dir)),
+        _ => return Err(eyre!("usage: render <service-alert.yaml> <output-dir>")),
+    };
+
+    let mut service_alert: ServiceAlert = serde_yaml::from_str(&fs::read_to_string(manifest)?)?;
+    let name = service_alert.name_any();
+
+    if service_alert.spec.template.is_some() {
+        eprintln!("warning: templates are not resolved, only the profile is expanded");
+    }
+    let config = CactuarConfig::new()?;
+    service_alert.spec = service_alert
+        .spec
+        .expanded(None)
+        .with_window(config.prometheus.window);
+
+    let rule_file = format!("{name}.rules.yaml");
+    let test_file = format!("{name}.test.yaml");
+    let expanded_file = format!("{name}.expanded.yaml");
+
+    let rules = PromAlerts::try_from(service_alert.spec.clone())?;
+    let tests = PromToolTests::generate(&service_alert.spec, &rule_file)?;
+    for unsupported in PromToolTests::unsupported(&service_alert.spec)? {
+        eprintln!("warning: no promtool tests for {unsupported}");
+    }
+
+    fs::create_dir_all(&output_dir)?;
+    fs::write(output_dir.join(&rule_file), serde_yaml::to_string(&rules)?)?;
+    fs::write(output_dir.join(&test_file), serde_yaml::to_string(&tests)?)?;
+    fs::write(
+        output_dir.join(expanded_file),
+        serde_yaml::to_string(&service_alert)?,
+    )?;
+
+    println!("{}", output_dir.join(test_file).display());
+    Ok(())
+}
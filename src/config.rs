@@ -29,16 +29,65 @@
 //! [http]
 //! address = "0.0.0.0"
 //! port = 8080
+//!
+//! [output]
+//! backend = "ruler" # or "configMap", "vmRule", "grafana"
+//! aggregate = false # configMap only, share ConfigMaps within a namespace
+//! shards = 1
+//!
+//! [ruler]
+//! url = "http://mimir-ruler.monitoring.svc/prometheus"
+//! tenant = "platform"
+//! namespace = "cactuar"
+//! timeout = "10s" # per request
+//!
+//! [grafana]
+//! folder = "Cactuar"
+//! datasource = "prometheus"
+//! org = 1
+//!
+//! [cluster]
+//! name = "eu-west-1"
+//! label = "cluster"
+//! selectors = false # set when Prometheus sees several clusters, e.g. Thanos
+//!
+//! [cluster.labels]
+//! region = "eu"
+//!
+//! [alertmanager]
+//! url = "http://alertmanager.monitoring.svc:9093"
+//! timeout = "10s"
+//!
+//! [prometheus]
+//! url = "http://prometheus.monitoring.svc:9090"
+//! verify = true # check that generated rules are loaded after applying
+//! refresh = "1m" # show pending and firing alerts in status, this often
+//! timeout = "10s"
+//! window = "2m" # for alerts without one, at least four scrape intervals
+//!
+//! [discovery]
+//! enabled = false # create ServiceAlerts for annotated Deployments, opt-in
 //! ```
 
 use std::{
+    collections::BTreeMap,
     fmt::Debug,
     net::{IpAddr, SocketAddr},
+    time::Duration,
 };
 
 use config::Config;
 use serde::Deserialize;
 
+use crate::{
+    crd::{PromDuration, DEFAULT_WINDOW},
+    prometheus::external_labels::{is_valid_label_name, RESERVED_LABELS},
+};
+
+/// How long requests to the ruler, Alertmanager and Prometheus may take by
+/// default, including reading the response.
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Default, Debug, Deserialize)]
 #[serde(default)]
 /// Forms the tree structure for CactuarConfig. This implementation relies on
@@ -50,6 +99,13 @@ use serde::Deserialize;
 /// implementation of it.
 pub struct CactuarConfig {
     pub http: HTTP,
+    pub output: Output,
+    pub ruler: Ruler,
+    pub grafana: GrafanaAlerting,
+    pub cluster: Cluster,
+    pub alertmanager: Alertmanager,
+    pub discovery: Discovery,
+    pub prometheus: Prometheus,
 }
 
 #[derive(Debug, Deserialize)]
@@ -75,6 +131,207 @@ impl Default for HTTP {
     }
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Output {
+    pub backend: OutputBackend,
+    /// Write the rules of every `ServiceAlert` in a namespace into a few
+    /// shared `ConfigMaps`, rather than one each. Only supported by the
+    /// `configMap` backend.
+    pub aggregate: bool,
+    /// Number of shared `ConfigMaps` per namespace when aggregating.
+    pub shards: u32,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            backend: OutputBackend::default(),
+            aggregate: false,
+            shards: 1,
+        }
+    }
+}
+
+/// Where generated rules are written to, see [`crate::output`].
+#[derive(Default, Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OutputBackend {
+    #[default]
+    ConfigMap,
+    #[serde(rename = "vmRule")]
+    VMRule,
+    Ruler,
+    Grafana,
+}
+
+/// Connection details for a Mimir or Cortex ruler, used when the `ruler`
+/// output backend is selected.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Ruler {
+    /// Ruler URL including its Prometheus HTTP prefix, e.g. `/prometheus` for
+    /// Mimir or `/api/prom` for Cortex.
+    pub url: Option<String>,
+    /// Sent as the `X-Scope-OrgID` header, leave unset if the ruler does not
+    /// have multi-tenancy enabled.
+    pub tenant: Option<String>,
+    /// Ruler namespace that all generated rule groups are stored under.
+    pub namespace: String,
+    /// How long each request to the ruler may take.
+    pub timeout: PromDuration,
+}
+
+impl Default for Ruler {
+    fn default() -> Self {
+        Self {
+            url: None,
+            tenant: None,
+            namespace: String::from("cactuar"),
+            timeout: DEFAULT_REQUEST_TIMEOUT.into(),
+        }
+    }
+}
+
+/// Options for rendering Grafana-managed alert rules, used when the `grafana`
+/// output backend is selected.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GrafanaAlerting {
+    /// Grafana folder that all generated rule groups are stored in.
+    pub folder: String,
+    /// UID of the Prometheus datasource that alert queries run against.
+    pub datasource: String,
+    /// Grafana organisation the rules belong to.
+    pub org: i64,
+}
+
+impl Default for GrafanaAlerting {
+    fn default() -> Self {
+        Self {
+            folder: String::from("Cactuar"),
+            datasource: String::from("prometheus"),
+            org: 1,
+        }
+    }
+}
+
+/// Identifies the cluster that Cactuar runs in, for alerts that are federated
+/// into a central Alertmanager. See [`crate::prometheus::external_labels`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Cluster {
+    /// Name of the cluster, added to every generated rule under `label`.
+    pub name: Option<String>,
+    /// Label that holds the cluster name.
+    pub label: String,
+    /// Extra labels added to every generated rule. Labels set by a
+    /// ServiceAlert itself take precedence.
+    pub labels: BTreeMap<String, String>,
+    /// Also match the cluster name and labels in the selectors of generated
+    /// expressions, for when Prometheus sees series from several clusters.
+    pub selectors: bool,
+}
+
+impl Default for Cluster {
+    fn default() -> Self {
+        Self {
+            name: None,
+            label: String::from("cluster"),
+            labels: BTreeMap::new(),
+            selectors: false,
+        }
+    }
+}
+
+impl Cluster {
+    /// Checks that the cluster label and external labels are valid Prometheus
+    /// label names, and that none of them is a label Cactuar sets itself.
+    pub fn validate(&self) -> Result<(), config::ConfigError> {
+        for name in self.labels.keys().chain([&self.label]) {
+            if !is_valid_label_name(name) {
+                return Err(config::ConfigError::Message(format!(
+                    "cluster label `{name}` is not a valid Prometheus label name"
+                )));
+            }
+            if RESERVED_LABELS.contains(&name.as_str()) {
+                return Err(config::ConfigError::Message(format!(
+                    "cluster label `{name}` is reserved, Cactuar sets it on every rule"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Alertmanager that maintenance windows are silenced in, see
+/// [`crate::alertmanager`].
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Alertmanager {
+    /// Alertmanager URL, leave unset to ignore maintenance windows.
+    pub url: Option<String>,
+    /// How long each request to Alertmanager may take.
+    pub timeout: PromDuration,
+}
+
+impl Default for Alertmanager {
+    fn default() -> Self {
+        Self {
+            url: None,
+            timeout: DEFAULT_REQUEST_TIMEOUT.into(),
+        }
+    }
+}
+
+/// Prometheus, or a compatible ruler, that generated rules are evaluated by,
+/// see [`crate::prometheus::api`].
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Prometheus {
+    /// Prometheus URL, including any HTTP prefix, e.g. `/prometheus` for
+    /// Mimir. Leave unset to skip anything that queries Prometheus.
+    pub url: Option<String>,
+    /// Check that generated rules are loaded and healthy after applying them.
+    pub verify: bool,
+    /// How often pending and firing alerts are refreshed in the status of
+    /// each ServiceAlert, with one query for all of them. Leave unset to not
+    /// show them.
+    pub refresh: Option<PromDuration>,
+    /// How long each request to Prometheus may take.
+    pub timeout: PromDuration,
+    /// Evaluation window of alerts that don't set one. Defaults to four
+    /// times the 30s scrape interval Cactuar otherwise assumes.
+    pub window: PromDuration,
+}
+
+impl Default for Prometheus {
+    fn default() -> Self {
+        Self {
+            url: None,
+            verify: true,
+            refresh: None,
+            timeout: DEFAULT_REQUEST_TIMEOUT.into(),
+            window: DEFAULT_WINDOW.into(),
+        }
+    }
+}
+
+/// Creating ServiceAlerts for Deployments annotated with a profile, see
+/// [`crate::kubernetes::discovery`].
+///
+/// This is opt-in, as it writes ServiceAlerts into every namespace with an
+/// annotated Deployment. Besides watching Deployments, which workload
+/// selectors need anyway, it requires `create`, `patch` and `delete` on
+/// `servicealerts.cactuar.rs` in those namespaces, and `create` on
+/// `events.k8s.io` events.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Discovery {
+    pub enabled: bool,
+}
+
 impl CactuarConfig {
     /// Create a new [`CactuarConfig`]. This function merges default config
     /// values, config file values, and environment variables, please refer to
@@ -85,6 +342,9 @@ impl CactuarConfig {
             .add_source(config::Environment::default().separator("_"))
             .build()?;
 
-        builder.try_deserialize()
+        let config: CactuarConfig = builder.try_deserialize()?;
+        config.cluster.validate()?;
+
+        Ok(config)
     }
 }
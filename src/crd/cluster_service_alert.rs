@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Alerts, CommonLabels, Profile, PromDuration, ServiceAlertSpec, TemplateRef, WorkloadSelector,
+};
+
+/// Labels the ServiceAlerts generated for a [`ClusterServiceAlert`] with its
+/// name, so that rule objects can be traced back to the policy.
+pub const CLUSTER_SERVICE_ALERT_LABEL: &str = "cactuar.rs/cluster-service-alert";
+/// Prometheus label carrying [`CLUSTER_SERVICE_ALERT_LABEL`] on the generated
+/// rules, which may not contain dots or slashes.
+pub const CLUSTER_SERVICE_ALERT_RULE_LABEL: &str = "cluster_service_alert";
+
+/// A platform-wide policy, applied as a ServiceAlert of the same name in every
+/// namespace matched by `namespaceSelector`. The ServiceAlerts are owned by the
+/// policy and reconciled like any other.
+#[derive(CustomResource, Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[kube(
+    group = "cactuar.rs",
+    version = "v1",
+    kind = "ClusterServiceAlert",
+    shortname = "clusteralert",
+    status = "ClusterServiceAlertStatus"
+)]
+pub struct ClusterServiceAlertSpec {
+    /// Namespaces the policy applies to, every namespace if left out.
+    #[serde(default)]
+    pub namespace_selector: NamespaceSelector,
+    /// Deployments the policy applies to within each namespace.
+    pub workload_selector: WorkloadSelector,
+    #[serde(default)]
+    pub common_labels: CommonLabels,
+    pub interval: Option<PromDuration>,
+    pub limit: Option<u32>,
+    pub profile: Option<Profile>,
+    /// Template that alerts are taken from, usually a
+    /// `ClusterServiceAlertTemplate`.
+    pub template: Option<TemplateRef>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default)]
+    pub alerts: Alerts,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NamespaceSelector {
+    /// Labels a namespace must carry to be selected.
+    #[serde(default)]
+    pub match_labels: BTreeMap<String, String>,
+}
+
+impl NamespaceSelector {
+    /// Renders the selector in the format expected by the Kubernetes API.
+    pub fn label_selector(&self) -> String {
+        self.match_labels
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterServiceAlertStatus {
+    /// Namespaces a ServiceAlert was generated in during the last reconcile.
+    pub namespaces: Option<Vec<String>>,
+}
+
+impl ClusterServiceAlertSpec {
+    /// The spec of the ServiceAlert generated in each matched namespace.
+    pub fn service_alert_spec(&self) -> ServiceAlertSpec {
+        ServiceAlertSpec {
+            common_labels: self.common_labels.clone(),
+            deployment_name: String::new(),
+            workload_ref: None,
+            workload_selector: Some(self.workload_selector.clone()),
+            interval: self.interval,
+            limit: self.limit,
+            maintenance: None,
+            profile: self.profile,
+            template: self.template.clone(),
+            annotations: self.annotations.clone(),
+            alerts: self.alerts.clone(),
+        }
+    }
+}
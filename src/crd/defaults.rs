@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{Alerts, CommonLabels, ServiceAlertSpec};
+
+/// Name of the `ServiceAlertDefaults` that applies to its namespace, others
+/// are ignored.
+pub const DEFAULTS_NAME: &str = "default";
+
+/// Defaults for every ServiceAlert in a namespace, saving teams from repeating
+/// the same labels and thresholds. Only the `ServiceAlertDefaults` named
+/// `default` applies.
+#[derive(CustomResource, Debug, Default, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
+#[kube(
+    group = "cactuar.rs",
+    version = "v1",
+    kind = "ServiceAlertDefaults",
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAlertDefaultsSpec {
+    /// Labels used where a ServiceAlert leaves its `commonLabels` out.
+    #[serde(default)]
+    pub common_labels: CommonLabels,
+    /// Severity of alert configs without a `severity` label.
+    pub severity: Option<String>,
+    /// Annotations added to every generated rule, unless the ServiceAlert
+    /// sets the same annotation.
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    /// Alerts that ServiceAlerts start from, overridden by their profile,
+    /// template and own alerts.
+    #[serde(default)]
+    pub alerts: Alerts,
+}
+
+impl ServiceAlertSpec {
+    /// Returns a copy of this spec with the defaults of its namespace filled
+    /// in. Anything set on the spec itself takes precedence.
+    pub fn with_defaults(&self, defaults: &ServiceAlertDefaultsSpec) -> Self {
+        let mut spec = self.clone();
+
+        let labels = &mut spec.common_labels;
+        if labels.owner.is_empty() {
+            labels.owner = defaults.common_labels.owner.clone();
+        }
+        if labels.origin.is_empty() {
+            labels.origin = defaults.common_labels.origin.clone();
+        }
+        labels.extra = defaults
+            .common_labels
+            .extra
+            .clone()
+            .into_iter()
+            .chain(self.common_labels.extra.clone())
+            .collect();
+
+        spec.annotations = defaults
+            .annotations
+            .clone()
+            .into_iter()
+            .chain(self.annotations.clone())
+            .collect();
+
+        spec.alerts = self.alerts.over_template(&defaults.alerts);
+        if let Some(severity) = &defaults.severity {
+            let alerts = &mut spec.alerts;
+            let network = [&mut alerts.grpc, &mut alerts.rest]
+                .into_iter()
+                .flatten()
+                .flat_map(|configs| configs.values_mut().flatten());
+            let replica = alerts
+                .replica
+                .iter_mut()
+                .flat_map(|configs| configs.values_mut().flatten());
+            let custom = alerts.custom.iter_mut().flatten();
+
+            network
+                .chain(replica)
+                .map(|config| &mut config.with_labels)
+                .chain(custom.map(|custom| &mut custom.with_labels))
+                .for_each(|labels| {
+                    labels
+                        .entry(String::from("severity"))
+                        .or_insert_with(|| severity.clone());
+                });
+        }
+
+        spec
+    }
+}
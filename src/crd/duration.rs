@@ -0,0 +1,129 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, StringValidation},
+    JsonSchema,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// Prometheus duration units, largest first, used when rendering a
+/// [`PromDuration`]. Weeks and years are left out, as `14d` reads better in an
+/// alert rule than `2w`.
+/// Rough shape of the durations [`duration_str`] accepts, one or more terms of
+/// a whole number and a unit, at least one of them other than zero. Used by
+/// Kubernetes to reject malformed durations before the controller sees them.
+const DURATION_PATTERN: &str =
+    r"^\s*([0-9]+[a-zA-Zµ]+\s*)*0*[1-9][0-9]*[a-zA-Zµ]+\s*([0-9]+[a-zA-Zµ]+\s*)*$";
+
+const PROMETHEUS_UNITS: [(&str, u128); 5] = [
+    ("d", 24 * 60 * 60 * 1000),
+    ("h", 60 * 60 * 1000),
+    ("m", 60 * 1000),
+    ("s", 1000),
+    ("ms", 1),
+];
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("invalid duration `{input}`, expected something like `5m` or `1h30m`: {reason}")]
+pub struct DurationError {
+    input: String,
+    reason: String,
+}
+
+/// A duration parsed from a human-readable string with [`duration_str`], such
+/// as `5m`, `1h30m` or `90s`. It is always rendered using Prometheus duration
+/// syntax, so `90s` becomes `1m30s`, regardless of how it was written.
+///
+/// Prometheus durations only go down to milliseconds, so anything smaller is
+/// truncated. Durations that come out as zero are rejected, since a zero
+/// evaluation window or interval isn't valid PromQL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PromDuration(Duration);
+
+impl PromDuration {
+    pub fn as_duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl From<Duration> for PromDuration {
+    fn from(value: Duration) -> Self {
+        Self(value)
+    }
+}
+
+impl FromStr for PromDuration {
+    type Err = DurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |reason: String| DurationError {
+            input: s.to_string(),
+            reason,
+        };
+
+        let duration = duration_str::parse(s.trim()).map_err(|err| error(err.to_string()))?;
+        if duration.as_millis() == 0 {
+            return Err(error(String::from("must be at least 1ms")));
+        }
+
+        Ok(PromDuration(duration))
+    }
+}
+
+impl Display for PromDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut remaining = self.0.as_millis();
+        if remaining == 0 {
+            return write!(f, "0s");
+        }
+
+        for (unit, millis) in PROMETHEUS_UNITS {
+            if remaining >= millis {
+                write!(f, "{}{unit}", remaining / millis)?;
+                remaining %= millis;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for PromDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PromDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        input.parse().map_err(de::Error::custom)
+    }
+}
+
+// Durations are strings as far as Kubernetes is concerned. The pattern catches
+// most mistakes when the resource is applied, parsing happens when the
+// controller deserialises it.
+impl JsonSchema for PromDuration {
+    fn schema_name() -> String {
+        String::from("PromDuration")
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some(String::from(DURATION_PATTERN)),
+                ..StringValidation::default()
+            })),
+            ..SchemaObject::default()
+        }
+        .into()
+    }
+}
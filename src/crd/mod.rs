@@ -1,8 +1,20 @@
 // mod prom_rule;
+mod cluster_service_alert;
+mod defaults;
+mod duration;
+mod profile;
+mod schedule;
 mod service_alert;
+mod template;
 
 // pub use prom_rule::*;
+pub use cluster_service_alert::*;
+pub use defaults::*;
+pub use duration::*;
+pub use profile::*;
+pub use schedule::*;
 pub use service_alert::*;
+pub use template::*;
 
 #[cfg(test)]
 mod tests;
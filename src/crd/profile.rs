@@ -0,0 +1,78 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{AlertConfig, Alerts, NetworkAlert, Operation, ReplicaAlert};
+
+/// Curated sets of golden-signal alerts, for services that are happy with
+/// sensible defaults. Entries in `alerts`, or in a template, override the
+/// profile's entries of the same type.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Profile {
+    /// Error rate and latency of gRPC traffic, and available replicas.
+    GrpcStandard,
+    /// Error rate and latency of REST traffic, and available replicas.
+    HttpStandard,
+    /// Available replicas only, with enough slack for workloads that are
+    /// restarted between runs.
+    Batch,
+}
+
+impl Profile {
+    /// Expands the profile into the alerts it stands for.
+    pub fn alerts(&self) -> Alerts {
+        match self {
+            Profile::GrpcStandard => Alerts {
+                grpc: Some(network_alerts()),
+                replica: Some(replica_alerts(Duration::from_secs(5 * 60))),
+                ..Alerts::default()
+            },
+            Profile::HttpStandard => Alerts {
+                rest: Some(network_alerts()),
+                replica: Some(replica_alerts(Duration::from_secs(5 * 60))),
+                ..Alerts::default()
+            },
+            Profile::Batch => Alerts {
+                replica: Some(replica_alerts(Duration::from_secs(30 * 60))),
+                ..Alerts::default()
+            },
+        }
+    }
+}
+
+fn network_alerts() -> BTreeMap<NetworkAlert, Vec<AlertConfig>> {
+    BTreeMap::from([
+        (
+            NetworkAlert::ErrorPercent,
+            vec![
+                config(Operation::MoreThan, 5.0, 5 * 60, "warning"),
+                config(Operation::MoreThan, 10.0, 5 * 60, "critical"),
+            ],
+        ),
+        (
+            NetworkAlert::LatencyMillisecondsP99,
+            vec![config(Operation::MoreThan, 1000.0, 10 * 60, "warning")],
+        ),
+    ])
+}
+
+fn replica_alerts(for_: Duration) -> BTreeMap<ReplicaAlert, Vec<AlertConfig>> {
+    BTreeMap::from([(
+        ReplicaAlert::Count,
+        vec![config(Operation::EqualTo, 0.0, for_.as_secs(), "critical")],
+    )])
+}
+
+fn config(operation: Operation, value: f32, for_secs: u64, severity: &str) -> AlertConfig {
+    AlertConfig {
+        operation,
+        value,
+        for_: Duration::from_secs(for_secs).into(),
+        window: None,
+        keep_firing_for: None,
+        schedule: None,
+        with_labels: BTreeMap::from([(String::from("severity"), severity.to_string())]),
+    }
+}
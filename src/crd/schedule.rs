@@ -0,0 +1,124 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono_tz::Tz;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+/// Restricts when an alert may fire. Every field that is set has to match, so
+/// `hours` and `weekdays` together describe business hours.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveSchedule {
+    /// Hours of the day the alert is active during.
+    pub hours: Option<HourRange>,
+    /// Days of the week the alert is active on. Leave out to be active on
+    /// every day, an empty list is rejected.
+    #[schemars(length(min = 1))]
+    pub weekdays: Option<Vec<Weekday>>,
+    /// IANA timezone that `hours` and `weekdays` are in, defaults to UTC.
+    ///
+    /// Rules only hold the timezone's UTC offset at the time they were
+    /// rendered. They are rendered again when the offset changes, so around a
+    /// daylight saving change the schedule is off by the shift until
+    /// Prometheus reloads the rules.
+    pub timezone: Option<Timezone>,
+}
+
+/// Range of hours on a 24 hour clock, from the start of `start` until the start
+/// of `end`. Ranges that end before they start wrap around midnight, so
+/// `start: 22, end: 6` covers the night.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema, PartialEq, Eq)]
+pub struct HourRange {
+    #[schemars(range(max = 23))]
+    pub start: u8,
+    #[schemars(range(max = 24))]
+    pub end: u8,
+}
+
+// Kubernetes enums start with an upper case letter
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema, PartialEq, Eq, Hash)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    /// Number of the day, as returned by PromQL's `day_of_week()`.
+    pub fn number(&self) -> u8 {
+        match self {
+            Weekday::Sunday => 0,
+            Weekday::Monday => 1,
+            Weekday::Tuesday => 2,
+            Weekday::Wednesday => 3,
+            Weekday::Thursday => 4,
+            Weekday::Friday => 5,
+            Weekday::Saturday => 6,
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("unknown timezone `{0}`, expected an IANA name like `Europe/London`")]
+pub struct TimezoneError(String);
+
+/// An IANA timezone, such as `Europe/London`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timezone(Tz);
+
+impl Timezone {
+    pub fn as_tz(&self) -> Tz {
+        self.0
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = TimezoneError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse()
+            .map(Timezone)
+            .map_err(|_| TimezoneError(s.to_string()))
+    }
+}
+
+impl Display for Timezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.name())
+    }
+}
+
+impl Serialize for Timezone {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        input.parse().map_err(de::Error::custom)
+    }
+}
+
+// Timezones are strings as far as Kubernetes is concerned, parsing happens
+// when the controller deserialises the resource.
+impl JsonSchema for Timezone {
+    fn schema_name() -> String {
+        String::from("Timezone")
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
@@ -1,14 +1,31 @@
 use std::hash::Hash;
-use std::{collections::HashMap, fmt::Display};
+use std::time::Duration;
+use std::{collections::BTreeMap, fmt::Display};
 
+use chrono::{DateTime, Utc};
 use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use super::{ActiveSchedule, Profile, PromDuration, TemplateRef};
+
 pub const API_GROUP: &str = "cactuar.rs";
 pub const API_VERSION: &str = "v1";
 pub const KIND: &str = "ServiceAlert";
 pub const FINALIZER_NAME: &str = "servicealert.cactuar.rs";
+/// Prometheus label naming the ServiceAlert a rule was generated for, as
+/// `namespace/name`.
+pub const SERVICE_ALERT_RULE_LABEL: &str = "service_alert";
+
+/// Scrape interval that Cactuar assumes Prometheus is configured with when
+/// picking a default evaluation window.
+pub const DEFAULT_SCRAPE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `rate()` needs at least two samples to produce a result, the usual advice is
+/// to use a range of at least four scrape intervals to tolerate missed scrapes.
+/// Clusters scraping at another interval set their own default, see
+/// [`crate::config::Prometheus`].
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(4 * DEFAULT_SCRAPE_INTERVAL.as_secs());
 
 #[derive(CustomResource, Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -18,37 +35,227 @@ pub const FINALIZER_NAME: &str = "servicealert.cactuar.rs";
     kind = "ServiceAlert",
     shortname = "alert",
     status = "ServiceAlertStatus",
-    namespaced
+    namespaced,
+    printcolumn = r#"{"name":"Ready","type":"string","jsonPath":".status.conditions[?(@.type==\"Ready\")].status"}"#,
+    printcolumn = r#"{"name":"Rules","type":"integer","jsonPath":".status.ruleCount"}"#,
+    printcolumn = r#"{"name":"Output","type":"string","jsonPath":".status.output"}"#,
+    printcolumn = r#"{"name":"Error","type":"string","priority":1,"jsonPath":".status.lastError"}"#,
+    printcolumn = r#"{"name":"Age","type":"date","jsonPath":".metadata.creationTimestamp"}"#
 )]
 pub struct ServiceAlertSpec {
+    /// May be left out when the namespace's `ServiceAlertDefaults` supply
+    /// the owner and origin.
+    #[serde(default)]
     pub common_labels: CommonLabels,
+    /// Deployment that alerts are generated for. Leave empty when using
+    /// `workloadRef` or `workloadSelector` instead.
+    #[serde(default)]
     pub deployment_name: String,
+    /// Workload that alerts are generated for, for workloads other than
+    /// Deployments. Takes the place of `deploymentName`.
+    pub workload_ref: Option<WorkloadRef>,
+    /// Selects the Deployments that alerts are generated for by label, as an
+    /// alternative to `deploymentName`. Matching Deployments are resolved on
+    /// every reconcile.
+    pub workload_selector: Option<WorkloadSelector>,
+    /// How often Prometheus evaluates the generated rule groups, defaults to
+    /// the global evaluation interval.
+    pub interval: Option<PromDuration>,
+    /// Limits the number of alerts a single generated rule may produce, `0`
+    /// means no limit.
+    pub limit: Option<u32>,
+    /// Planned maintenance, during which the generated alerts are silenced in
+    /// Alertmanager.
+    pub maintenance: Option<Maintenance>,
+    /// Built-in set of alerts to start from, e.g. `grpc-standard`.
+    pub profile: Option<Profile>,
+    /// Template that alerts are taken from. Entries in `alerts` override the
+    /// template's entries of the same type.
+    pub template: Option<TemplateRef>,
+    /// Annotations added to every generated rule, besides the generated
+    /// summary and description. Values may use Prometheus templating, e.g.
+    /// `{{ $labels.deployment }}`.
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default)]
     pub alerts: Alerts,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+pub struct Maintenance {
+    /// Start of the maintenance window, as an RFC 3339 timestamp.
+    #[schemars(with = "String")]
+    pub start: DateTime<Utc>,
+    /// End of the maintenance window, as an RFC 3339 timestamp. The silence
+    /// expires on its own at this time.
+    #[schemars(with = "String")]
+    pub end: DateTime<Utc>,
+    /// Shown as the comment of the silence.
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkloadSelector {
+    /// Labels a Deployment must carry to be selected, every Deployment if
+    /// left empty.
+    #[serde(default)]
+    pub match_labels: BTreeMap<String, String>,
+    /// Puts the rules of every selected workload into shared rule groups,
+    /// rather than a set of rule groups per workload.
+    #[serde(default)]
+    pub aggregate: bool,
+}
+
+impl WorkloadSelector {
+    /// Renders the selector in the format expected by the Kubernetes API,
+    /// e.g. `app=foo,tier=backend`.
+    pub fn label_selector(&self) -> String {
+        self.match_labels
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Whether a workload carrying `labels` is selected.
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.match_labels
+            .iter()
+            .all(|(key, value)| labels.get(key) == Some(value))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+pub struct WorkloadRef {
+    pub kind: WorkloadKind,
+    pub name: String,
+}
+
+/// Kinds of workload that alerts can be generated for. Network alerts only
+/// depend on the workload name, but replica alerts read a different series
+/// for each kind.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, JsonSchema, PartialEq, Eq, Hash)]
+pub enum WorkloadKind {
+    #[default]
+    Deployment,
+    StatefulSet,
+    DaemonSet,
+    /// An Argo Rollout, `argoproj.io/v1alpha1`.
+    Rollout,
+}
+
+impl ServiceAlertSpec {
+    /// Name of the workload alerts are generated for.
+    pub fn workload_name(&self) -> &str {
+        match &self.workload_ref {
+            Some(workload_ref) if self.deployment_name.is_empty() => &workload_ref.name,
+            _ => &self.deployment_name,
+        }
+    }
+
+    /// Kind of the workload alerts are generated for, Deployments unless a
+    /// `workloadRef` says otherwise.
+    pub fn workload_kind(&self) -> WorkloadKind {
+        self.workload_ref
+            .as_ref()
+            .map(|workload_ref| workload_ref.kind)
+            .unwrap_or_default()
+    }
+
+    /// Returns a copy of this spec with its profile and the alerts of its
+    /// template merged in, in that order of precedence, so that it no longer
+    /// depends on either. `template` holds the alerts of the referenced
+    /// template, if any.
+    pub fn expanded(&self, template: Option<&Alerts>) -> Self {
+        let mut base = self
+            .profile
+            .map(|profile| profile.alerts())
+            .unwrap_or_default();
+        if let Some(template) = template {
+            base = template.over_template(&base);
+        }
+
+        Self {
+            profile: None,
+            template: None,
+            alerts: self.alerts.over_template(&base),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this spec with `window` as the evaluation window of
+    /// every network alert that doesn't set one. Replica alerts don't use a
+    /// window.
+    pub fn with_window(&self, window: PromDuration) -> Self {
+        let mut spec = self.clone();
+        let alerts = &mut spec.alerts;
+        [&mut alerts.grpc, &mut alerts.rest]
+            .into_iter()
+            .flatten()
+            .flat_map(|configs| configs.values_mut().flatten())
+            .for_each(|config| {
+                config.window.get_or_insert(window);
+            });
+
+        spec
+    }
+
+    /// Returns a copy of this spec targeting a single workload, as resolved
+    /// from the `workloadSelector`.
+    pub fn for_workload(&self, workload: &str) -> Self {
+        Self {
+            deployment_name: workload.to_string(),
+            ..self.clone()
+        }
+    }
+}
+
 // Since the metrics are different for different protocols, we must map each Alerts enum
 // to a different expression string in prometheus land.
 // e.g.
 // REST + ErrorPercent uses the istio_requests_total         istio standard metric
 // gRPC + ErrorPercent uses the istio_request_messages_total istio standard metric
-#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
 pub struct Alerts {
     #[serde(rename = "gRPC")]
-    pub grpc: Option<HashMap<NetworkAlert, Vec<AlertConfig>>>,
+    pub grpc: Option<BTreeMap<NetworkAlert, Vec<AlertConfig>>>,
     #[serde(rename = "REST")]
-    pub rest: Option<HashMap<NetworkAlert, Vec<AlertConfig>>>,
-    pub replica: Option<HashMap<ReplicaAlert, Vec<AlertConfig>>>,
+    pub rest: Option<BTreeMap<NetworkAlert, Vec<AlertConfig>>>,
+    pub replica: Option<BTreeMap<ReplicaAlert, Vec<AlertConfig>>>,
+    pub custom: Option<Vec<CustomAlert>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+impl Alerts {
+    /// Schedules of every alert that has one.
+    pub fn schedules(&self) -> impl Iterator<Item = &ActiveSchedule> {
+        let network = [&self.grpc, &self.rest]
+            .into_iter()
+            .flatten()
+            .flat_map(|alerts| alerts.values());
+        let replica = self.replica.iter().flat_map(|alerts| alerts.values());
+        network
+            .chain(replica)
+            .flatten()
+            .filter_map(|conf| conf.schedule.as_ref())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
 pub struct CommonLabels {
+    #[serde(default)]
     pub owner: String,
+    #[serde(default)]
     pub origin: String,
     #[serde(flatten)]
-    pub extra: HashMap<String, String>,
+    pub extra: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq, Hash)]
+// Alerts are generated in the order these variants are declared in, so that
+// rendered rules stay the same between reconciles.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
 #[serde(rename_all = "camelCase")]
 pub enum NetworkAlert {
     ErrorPercent,
@@ -72,7 +279,9 @@ impl Display for NetworkAlert {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq, Hash)]
+#[derive(
+    Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
 #[serde(rename_all = "camelCase")]
 pub enum ReplicaAlert {
     Count,
@@ -84,8 +293,52 @@ pub struct AlertConfig {
     pub operation: Operation,
     pub value: f32,
     #[serde(rename = "for")]
-    pub for_: String, // want to be able to specify like 3m 4s
-    pub with_labels: HashMap<String, String>,
+    pub for_: PromDuration,
+    /// Range of data used to evaluate the alert, e.g. the range passed to
+    /// `rate()`. This is independent of `for`, which only controls how long
+    /// the expression must be true before the alert fires.
+    pub window: Option<PromDuration>,
+    /// How long an alert keeps firing after its expression stops being true,
+    /// useful for dampening flapping alerts.
+    pub keep_firing_for: Option<PromDuration>,
+    /// Only lets the alert fire at certain times, e.g. during business hours.
+    pub schedule: Option<ActiveSchedule>,
+    /// Labels of the generated rule, `severity` defaults to the namespace's
+    /// default severity, or `warning`.
+    #[serde(default)]
+    pub with_labels: BTreeMap<String, String>,
+}
+
+impl AlertConfig {
+    /// Returns the configured evaluation window, or [`DEFAULT_WINDOW`] if none
+    /// was given, or filled in by [`ServiceAlertSpec::with_window`].
+    pub fn window(&self) -> PromDuration {
+        self.window.unwrap_or_else(|| DEFAULT_WINDOW.into())
+    }
+}
+
+/// Escape hatch for alerts that can't be expressed by Cactuar's alert enums.
+/// The expression is passed through to Prometheus as-is, but the resulting rule
+/// still receives the common labels of the [`ServiceAlertSpec`].
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomAlert {
+    pub alert: String,
+    pub expr: String,
+    #[serde(rename = "for")]
+    pub for_: PromDuration,
+    pub keep_firing_for: Option<PromDuration>,
+    /// Labels of the rule, `severity` defaults to the namespace's default
+    /// severity, or `warning`.
+    #[serde(default)]
+    pub with_labels: BTreeMap<String, String>,
+    pub annotations: CustomAnnotations,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+pub struct CustomAnnotations {
+    pub summary: String,
+    pub description: String,
 }
 
 // #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
@@ -118,9 +371,180 @@ pub enum Severity {
 }
 
 /// The status object of `StatusAlerter`
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ServiceAlertStatus {
     pub last_reconciled_at: Option<String>,
     pub reconciliation_expires_at: Option<String>,
+    /// Generation of the spec that the status describes.
+    pub observed_generation: Option<i64>,
+    /// `Ready`, `Valid`, `TargetFound`, `RulesApplied` and `RulesLoaded`
+    /// conditions.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// Object or rule groups that the generated rules were written to.
+    pub output: Option<String>,
+    pub rule_count: Option<u32>,
+    pub group_count: Option<u32>,
+    /// Error of the last failed reconcile, cleared once reconciling succeeds.
+    pub last_error: Option<String>,
+    /// Workloads that alerts were generated for during the last reconcile.
+    pub workloads: Option<Vec<String>>,
+    /// Maintenance window that alerts are currently, or will soon be,
+    /// silenced for.
+    pub maintenance: Option<MaintenanceStatus>,
+    /// Generated alerts that are currently pending or firing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_alerts: Option<ActiveAlertsStatus>,
+    /// Hash of the rules last written, to tell when they changed.
+    pub rules_hash: Option<String>,
+    /// When the generated rules last changed. Prometheus is given some time
+    /// after this to load them before they are reported as not loaded.
+    #[schemars(with = "Option<String>")]
+    pub rules_changed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveAlertsStatus {
+    pub pending: u32,
+    pub firing: u32,
+    /// The longest active alerts, firing before pending, capped at
+    /// [`ACTIVE_ALERTS_LISTED`] entries.
+    pub alerts: Vec<ActiveAlertStatus>,
+    /// When the active alerts last changed.
+    pub changed_at: String,
+}
+
+impl ActiveAlertsStatus {
+    /// Whether the same alerts are active, regardless of when they were seen.
+    pub fn same_alerts(&self, other: &ActiveAlertsStatus) -> bool {
+        self.pending == other.pending && self.firing == other.firing && self.alerts == other.alerts
+    }
+}
+
+/// Number of alerts listed in [`ActiveAlertsStatus`], to keep the status
+/// compact when many workloads alert at once.
+pub const ACTIVE_ALERTS_LISTED: usize = 10;
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveAlertStatus {
+    pub alert: String,
+    /// `pending` or `firing`.
+    pub state: String,
+    pub severity: Option<String>,
+    /// When the alert became pending.
+    pub active_since: Option<String>,
+}
+
+/// A standard Kubernetes status condition.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    #[serde(rename = "type")]
+    pub type_: ConditionType,
+    pub status: ConditionStatus,
+    /// CamelCase reason for the last transition.
+    pub reason: String,
+    pub message: String,
+    #[schemars(with = "String")]
+    pub last_transition_time: DateTime<Utc>,
+    pub observed_generation: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, PartialEq, Eq)]
+pub enum ConditionType {
+    /// Every other condition is true.
+    Ready,
+    /// The spec, along with its template and defaults, produced rules.
+    Valid,
+    /// The workloads alerted on exist.
+    TargetFound,
+    /// The rules were written to the output.
+    RulesApplied,
+    /// Prometheus loaded the rules, and they evaluate without errors. Only
+    /// set when rule verification is configured.
+    RulesLoaded,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, PartialEq, Eq)]
+pub enum ConditionStatus {
+    True,
+    False,
+    Unknown,
+}
+
+impl From<bool> for ConditionStatus {
+    fn from(value: bool) -> Self {
+        if value {
+            ConditionStatus::True
+        } else {
+            ConditionStatus::False
+        }
+    }
+}
+
+impl ServiceAlertStatus {
+    /// Sets a condition, keeping its transition time if the status did not
+    /// change.
+    pub fn set_condition(&mut self, mut condition: Condition) {
+        match self
+            .conditions
+            .iter_mut()
+            .find(|existing| existing.type_ == condition.type_)
+        {
+            Some(existing) => {
+                if existing.status == condition.status {
+                    condition.last_transition_time = existing.last_transition_time;
+                }
+                *existing = condition;
+            }
+            None => self.conditions.push(condition),
+        }
+    }
+
+    /// Removes a condition that no longer applies.
+    pub fn remove_condition(&mut self, type_: ConditionType) {
+        self.conditions.retain(|condition| condition.type_ != type_);
+    }
+
+    /// Records the hash of the rules just written, returning whether they
+    /// changed less than `grace` ago and may not be loaded by Prometheus yet.
+    pub fn rules_written(
+        &mut self,
+        rules_hash: String,
+        now: DateTime<Utc>,
+        grace: chrono::Duration,
+    ) -> bool {
+        if self.rules_hash.as_ref() != Some(&rules_hash) {
+            self.rules_hash = Some(rules_hash);
+            self.rules_changed_at = Some(now);
+        }
+
+        self.rules_changed_at
+            .is_none_or(|changed_at| now - changed_at < grace)
+    }
+
+    /// Looks up the status of a condition.
+    pub fn condition(&self, type_: ConditionType) -> Option<ConditionStatus> {
+        self.conditions
+            .iter()
+            .find(|condition| condition.type_ == type_)
+            .map(|condition| condition.status)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceStatus {
+    #[schemars(with = "String")]
+    pub start: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub end: DateTime<Utc>,
+    pub reason: String,
+    /// Whether the window has started.
+    pub active: bool,
+    /// ID of the Alertmanager silence covering the window.
+    pub silence_id: Option<String>,
 }
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::Alerts;
+
+/// Alerts shared by every ServiceAlert in a namespace that references this
+/// template.
+#[derive(CustomResource, Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
+#[kube(
+    group = "cactuar.rs",
+    version = "v1",
+    kind = "ServiceAlertTemplate",
+    namespaced
+)]
+pub struct ServiceAlertTemplateSpec {
+    pub alerts: Alerts,
+}
+
+/// Alerts shared by ServiceAlerts in any namespace that reference this
+/// template.
+#[derive(CustomResource, Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq)]
+#[kube(
+    group = "cactuar.rs",
+    version = "v1",
+    kind = "ClusterServiceAlertTemplate"
+)]
+pub struct ClusterServiceAlertTemplateSpec {
+    pub alerts: Alerts,
+}
+
+/// Template that a ServiceAlert takes its alerts from.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema, PartialEq, Eq)]
+pub struct TemplateRef {
+    #[serde(default)]
+    pub kind: TemplateKind,
+    pub name: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, JsonSchema, PartialEq, Eq)]
+pub enum TemplateKind {
+    /// A template in the namespace of the ServiceAlert.
+    #[default]
+    ServiceAlertTemplate,
+    ClusterServiceAlertTemplate,
+}
+
+impl Alerts {
+    /// Merges these alerts over the alerts of a template. Each alert type,
+    /// e.g. `REST.errorPercent`, replaces the template's configs for that type
+    /// as a whole, and custom alerts replace template alerts with the same
+    /// name. An empty list removes an alert type from the template.
+    pub fn over_template(&self, template: &Alerts) -> Alerts {
+        fn merge<K: Ord + Clone, V: Clone>(
+            overrides: &Option<BTreeMap<K, V>>,
+            template: &Option<BTreeMap<K, V>>,
+        ) -> Option<BTreeMap<K, V>> {
+            match (overrides, template) {
+                (None, template) => template.clone(),
+                (overrides, None) => overrides.clone(),
+                (Some(overrides), Some(template)) => {
+                    let mut merged = template.clone();
+                    merged.extend(overrides.clone());
+                    Some(merged)
+                }
+            }
+        }
+
+        let custom = match (&self.custom, &template.custom) {
+            (None, template) => template.clone(),
+            (overrides, None) => overrides.clone(),
+            (Some(overrides), Some(template)) => Some(
+                template
+                    .iter()
+                    .filter(|alert| !overrides.iter().any(|o| o.alert == alert.alert))
+                    .chain(overrides)
+                    .cloned()
+                    .collect(),
+            ),
+        };
+
+        Alerts {
+            grpc: merge(&self.grpc, &template.grpc),
+            rest: merge(&self.rest, &template.rest),
+            replica: merge(&self.replica, &template.replica),
+            custom,
+        }
+    }
+}
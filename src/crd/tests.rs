@@ -1,14 +1,19 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+use chrono::{TimeZone, Timelike, Utc};
 use pretty_assertions::assert_eq;
 
-use crate::crd::service_alert::*;
+use crate::crd::{
+    service_alert::*, ActiveSchedule, Profile, PromDuration, ServiceAlertDefaultsSpec,
+    TemplateKind, TemplateRef, Weekday,
+};
 
 const SERIALIZED_YAML_SPEC: &str = r#"
 commonLabels:
   origin: cloud
   owner: foo
 deploymentName: best-service-eu
+interval: 30s
 alerts:
   REST:
     latencyMillisecondsP99:
@@ -20,6 +25,7 @@ alerts:
       - operation: MoreThan
         value: 50
         for: 2m
+        keepFiringFor: 5m
         withLabels:
           severity: critical
   gRPC:
@@ -27,6 +33,7 @@ alerts:
       - operation: MoreThan
         value: 10 # %
         for: 3m
+        window: 5m
         withLabels:
           severity: warning
   replica:
@@ -41,6 +48,15 @@ alerts:
         for: 1m
         withLabels:
           severity: critical
+  custom:
+    - alert: QueueBacklog
+      expr: sum(queue_depth{app="best-service-eu"}) > 100
+      for: 10m
+      withLabels:
+        severity: warning
+      annotations:
+        summary: Queue backlog
+        description: Queue depth is {{ $value }}
 "#;
 
 #[test]
@@ -49,30 +65,44 @@ fn test_serialisation_happy_path() -> color_eyre::Result<()> {
         common_labels: CommonLabels {
             owner: String::from("foo"),
             origin: String::from("cloud"),
-            extra: HashMap::new(),
+            extra: BTreeMap::new(),
         },
         deployment_name: String::from("best-service-eu"),
+        workload_ref: None,
+        workload_selector: None,
+        maintenance: None,
+        profile: None,
+        template: None,
+        annotations: BTreeMap::new(),
+        interval: Some("30s".parse()?),
+        limit: None,
         alerts: Alerts {
-            grpc: Some(HashMap::from([(
+            grpc: Some(BTreeMap::from([(
                 NetworkAlert::ErrorPercent,
                 vec![AlertConfig {
                     operation: Operation::MoreThan,
                     value: 10_f32,
-                    for_: String::from("3m"),
-                    with_labels: HashMap::from([(
+                    for_: "3m".parse()?,
+                    window: Some("5m".parse()?),
+                    keep_firing_for: None,
+                    schedule: None,
+                    with_labels: BTreeMap::from([(
                         String::from("severity"),
                         String::from("warning"),
                     )]),
                 }],
             )])),
-            rest: Some(HashMap::from([(
+            rest: Some(BTreeMap::from([(
                 NetworkAlert::LatencyMillisecondsP99,
                 vec![
                     AlertConfig {
                         operation: Operation::MoreThan,
                         value: 20_f32,
-                        for_: String::from("5m"),
-                        with_labels: HashMap::from([(
+                        for_: "5m".parse()?,
+                        window: None,
+                        keep_firing_for: None,
+                        schedule: None,
+                        with_labels: BTreeMap::from([(
                             String::from("severity"),
                             String::from("warning"),
                         )]),
@@ -80,22 +110,28 @@ fn test_serialisation_happy_path() -> color_eyre::Result<()> {
                     AlertConfig {
                         operation: Operation::MoreThan,
                         value: 50_f32,
-                        for_: String::from("2m"),
-                        with_labels: HashMap::from([(
+                        for_: "2m".parse()?,
+                        window: None,
+                        keep_firing_for: Some("5m".parse()?),
+                        schedule: None,
+                        with_labels: BTreeMap::from([(
                             String::from("severity"),
                             String::from("critical"),
                         )]),
                     },
                 ],
             )])),
-            replica: Some(HashMap::from([(
+            replica: Some(BTreeMap::from([(
                 ReplicaAlert::Count,
                 vec![
                     AlertConfig {
                         operation: Operation::LessThan,
                         value: 3_f32,
-                        for_: String::from("5m"),
-                        with_labels: HashMap::from([(
+                        for_: "5m".parse()?,
+                        window: None,
+                        keep_firing_for: None,
+                        schedule: None,
+                        with_labels: BTreeMap::from([(
                             String::from("severity"),
                             String::from("warning"),
                         )]),
@@ -103,14 +139,28 @@ fn test_serialisation_happy_path() -> color_eyre::Result<()> {
                     AlertConfig {
                         operation: Operation::EqualTo,
                         value: 0 as f32,
-                        for_: String::from("1m"),
-                        with_labels: HashMap::from([(
+                        for_: "1m".parse()?,
+                        window: None,
+                        keep_firing_for: None,
+                        schedule: None,
+                        with_labels: BTreeMap::from([(
                             String::from("severity"),
                             String::from("critical"),
                         )]),
                     },
                 ],
             )])),
+            custom: Some(vec![CustomAlert {
+                alert: String::from("QueueBacklog"),
+                expr: String::from(r#"sum(queue_depth{app="best-service-eu"}) > 100"#),
+                for_: "10m".parse()?,
+                keep_firing_for: None,
+                with_labels: BTreeMap::from([(String::from("severity"), String::from("warning"))]),
+                annotations: CustomAnnotations {
+                    summary: String::from("Queue backlog"),
+                    description: String::from("Queue depth is {{ $value }}"),
+                },
+            }]),
         },
     };
 
@@ -118,3 +168,366 @@ fn test_serialisation_happy_path() -> color_eyre::Result<()> {
     assert_eq!(yaml_repr, rust_repr);
     Ok(())
 }
+
+#[test]
+fn test_duration_normalised_to_prometheus_syntax() -> color_eyre::Result<()> {
+    let cases = [
+        ("5m", "5m"),
+        ("90s", "1m30s"),
+        ("1h 30m", "1h30m"),
+        ("2day", "2d"),
+        ("1500ms", "1s500ms"),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(input.parse::<PromDuration>()?.to_string(), expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_duration_rejects_invalid_input() {
+    for input in ["5 min", "five minutes", "5", "", "0s", "0m 0s", "500us"] {
+        assert!(input.parse::<PromDuration>().is_err(), "accepted `{input}`");
+    }
+}
+
+#[test]
+fn test_duration_schema_pattern_matches_parser() -> color_eyre::Result<()> {
+    let schema = serde_json::to_value(schemars::schema_for!(PromDuration))?;
+    let pattern = regex::Regex::new(schema["pattern"].as_str().unwrap_or_default())?;
+
+    for input in ["5m", "90s", "1h 30m", "2day", "1500ms", "0h5m"] {
+        assert!(pattern.is_match(input), "rejected `{input}`");
+        input.parse::<PromDuration>()?;
+    }
+    for input in ["5 min", "five minutes", "5", "", "0s", "0m 0s"] {
+        assert!(!pattern.is_match(input), "accepted `{input}`");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_duration_fails_deserialisation() {
+    let yaml = SERIALIZED_YAML_SPEC.replacen("for: 5m", "for: 5 min", 1);
+    let err = serde_yaml::from_str::<ServiceAlertSpec>(&yaml).unwrap_err();
+    assert!(err.to_string().contains("invalid duration `5 min`"));
+}
+
+#[test]
+fn test_workload_selector_deserialisation() -> color_eyre::Result<()> {
+    let spec: ServiceAlertSpec = serde_yaml::from_str(
+        r#"
+commonLabels:
+  origin: cloud
+  owner: foo
+workloadSelector:
+  matchLabels:
+    app.kubernetes.io/part-of: best-service
+    tier: backend
+alerts: {}
+"#,
+    )?;
+
+    assert!(spec.deployment_name.is_empty());
+
+    let selector = spec.workload_selector.unwrap();
+    assert!(!selector.aggregate);
+    assert_eq!(
+        selector.label_selector(),
+        "app.kubernetes.io/part-of=best-service,tier=backend"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_schedule_rejects_unknown_timezone() -> color_eyre::Result<()> {
+    let schedule = |timezone: &str| {
+        serde_yaml::from_str::<ActiveSchedule>(&format!(
+            "hours: {{start: 9, end: 17}}\nweekdays: [Monday, Friday]\ntimezone: {timezone}"
+        ))
+    };
+
+    let schedule_in_london = schedule("Europe/London")?;
+    assert_eq!(
+        schedule_in_london.weekdays,
+        Some(vec![Weekday::Monday, Weekday::Friday])
+    );
+    assert_eq!(
+        schedule_in_london.timezone.map(|tz| tz.to_string()),
+        Some(String::from("Europe/London"))
+    );
+
+    assert!(schedule("Europe/Atlantis").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_alerts_override_template_entries() -> color_eyre::Result<()> {
+    let template: Alerts = serde_yaml::from_str(
+        r#"
+REST:
+  errorPercent:
+    - {operation: MoreThan, value: 5, for: 5m, withLabels: {severity: warning}}
+  latencyMillisecondsP99:
+    - {operation: MoreThan, value: 500, for: 5m, withLabels: {severity: warning}}
+replica:
+  count:
+    - {operation: LessThan, value: 2, for: 5m, withLabels: {severity: warning}}
+custom:
+  - alert: QueueBacklog
+    expr: sum(queue_depth) > 100
+    for: 10m
+    withLabels: {severity: warning}
+    annotations: {summary: Queue backlog, description: Queue is backed up}
+"#,
+    )?;
+    let overrides: Alerts = serde_yaml::from_str(
+        r#"
+REST:
+  errorPercent:
+    - {operation: MoreThan, value: 1, for: 5m, withLabels: {severity: critical}}
+replica:
+  count: []
+custom:
+  - alert: QueueBacklog
+    expr: sum(queue_depth) > 1000
+    for: 10m
+    withLabels: {severity: warning}
+    annotations: {summary: Queue backlog, description: Queue is backed up}
+"#,
+    )?;
+
+    let merged = overrides.over_template(&template);
+
+    let rest = merged.rest.unwrap();
+    assert_eq!(rest[&NetworkAlert::ErrorPercent][0].value, 1.0);
+    assert_eq!(rest[&NetworkAlert::LatencyMillisecondsP99][0].value, 500.0);
+    assert!(merged.replica.unwrap()[&ReplicaAlert::Count].is_empty());
+    assert_eq!(merged.grpc, None);
+
+    let custom = merged.custom.unwrap();
+    assert_eq!(custom.len(), 1);
+    assert_eq!(custom[0].expr, "sum(queue_depth) > 1000");
+
+    Ok(())
+}
+
+#[test]
+fn test_template_ref_defaults_to_namespaced_kind() -> color_eyre::Result<()> {
+    let spec: ServiceAlertSpec = serde_yaml::from_str(
+        r#"
+commonLabels:
+  origin: cloud
+  owner: foo
+deploymentName: best-service-eu
+template:
+  name: standard-http
+"#,
+    )?;
+
+    assert_eq!(
+        spec.template,
+        Some(TemplateRef {
+            kind: TemplateKind::ServiceAlertTemplate,
+            name: String::from("standard-http"),
+        })
+    );
+    assert_eq!(spec.alerts, Alerts::default());
+
+    Ok(())
+}
+
+#[test]
+fn test_profile_expands_under_template_and_explicit_alerts() -> color_eyre::Result<()> {
+    let spec: ServiceAlertSpec = serde_yaml::from_str(
+        r#"
+commonLabels:
+  origin: cloud
+  owner: foo
+deploymentName: best-service-eu
+profile: grpc-standard
+alerts:
+  gRPC:
+    errorPercent:
+      - {operation: MoreThan, value: 1, for: 5m, withLabels: {severity: critical}}
+"#,
+    )?;
+    let template: Alerts = serde_yaml::from_str(
+        r#"
+replica:
+  count:
+    - {operation: LessThan, value: 2, for: 5m, withLabels: {severity: warning}}
+"#,
+    )?;
+
+    let expanded = spec.expanded(Some(&template));
+    assert_eq!(expanded.profile, None);
+
+    let grpc = expanded.alerts.grpc.unwrap();
+    assert_eq!(grpc[&NetworkAlert::ErrorPercent].len(), 1);
+    assert_eq!(grpc[&NetworkAlert::ErrorPercent][0].value, 1.0);
+    assert_eq!(
+        grpc[&NetworkAlert::LatencyMillisecondsP99],
+        Profile::GrpcStandard.alerts().grpc.unwrap()[&NetworkAlert::LatencyMillisecondsP99]
+    );
+    assert_eq!(
+        expanded.alerts.replica.unwrap()[&ReplicaAlert::Count][0].operation,
+        Operation::LessThan
+    );
+    assert_eq!(expanded.alerts.rest, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_namespace_defaults_fill_in_spec() -> color_eyre::Result<()> {
+    let defaults: ServiceAlertDefaultsSpec = serde_yaml::from_str(
+        r#"
+commonLabels:
+  owner: payments
+  origin: cloud
+  team: checkout
+severity: critical
+annotations:
+  runbook_url: https://runbooks.example.com/{{ $labels.alertname }}
+alerts:
+  replica:
+    count:
+      - {operation: EqualTo, value: 0, for: 5m}
+"#,
+    )?;
+    let spec: ServiceAlertSpec = serde_yaml::from_str(
+        r#"
+commonLabels:
+  origin: on-prem
+deploymentName: best-service-eu
+alerts:
+  REST:
+    errorPercent:
+      - {operation: MoreThan, value: 5, for: 5m, withLabels: {severity: warning}}
+"#,
+    )?;
+
+    let spec = spec.with_defaults(&defaults);
+
+    assert_eq!(spec.common_labels.owner, "payments");
+    assert_eq!(spec.common_labels.origin, "on-prem");
+    assert_eq!(
+        spec.common_labels.extra,
+        BTreeMap::from([(String::from("team"), String::from("checkout"))])
+    );
+    assert!(spec.annotations.contains_key("runbook_url"));
+
+    let severity = |configs: &[AlertConfig]| configs[0].with_labels["severity"].clone();
+    assert_eq!(
+        severity(&spec.alerts.rest.unwrap()[&NetworkAlert::ErrorPercent]),
+        "warning"
+    );
+    assert_eq!(
+        severity(&spec.alerts.replica.unwrap()[&ReplicaAlert::Count]),
+        "critical"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_configured_window_fills_in_unset_windows() -> color_eyre::Result<()> {
+    let spec: ServiceAlertSpec = serde_yaml::from_str(
+        r#"
+commonLabels:
+  owner: payments
+  origin: cloud
+deploymentName: best-service-eu
+alerts:
+  REST:
+    errorPercent:
+      - {operation: MoreThan, value: 5, for: 5m}
+      - {operation: MoreThan, value: 10, for: 5m, window: 10m}
+"#,
+    )?;
+    let windows = |spec: &ServiceAlertSpec| -> Vec<String> {
+        spec.alerts.rest.as_ref().unwrap()[&NetworkAlert::ErrorPercent]
+            .iter()
+            .map(|config| config.window().to_string())
+            .collect()
+    };
+    assert_eq!(windows(&spec), vec!["2m", "10m"]);
+
+    let spec = spec.with_window("1m".parse::<PromDuration>()?);
+    assert_eq!(windows(&spec), vec!["1m", "10m"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_condition_keeps_transition_time_while_unchanged() {
+    let condition = |status: ConditionStatus, hour: u32, reason: &str| Condition {
+        type_: ConditionType::Ready,
+        status,
+        reason: reason.into(),
+        message: String::new(),
+        last_transition_time: Utc.with_ymd_and_hms(2023, 6, 1, hour, 0, 0).unwrap(),
+        observed_generation: Some(1),
+    };
+
+    let mut status = ServiceAlertStatus::default();
+    status.set_condition(condition(ConditionStatus::True, 10, "Reconciled"));
+    status.set_condition(condition(ConditionStatus::True, 11, "Reconciled"));
+    assert_eq!(status.conditions.len(), 1);
+    assert_eq!(status.conditions[0].last_transition_time.hour(), 10);
+
+    status.set_condition(condition(ConditionStatus::False, 12, "OutputFailed"));
+    assert_eq!(status.conditions[0].last_transition_time.hour(), 12);
+    assert_eq!(
+        status.condition(ConditionType::Ready),
+        Some(ConditionStatus::False)
+    );
+    assert_eq!(status.condition(ConditionType::Valid), None);
+}
+
+#[test]
+fn test_rules_loading_until_grace_period_passes() {
+    let at = |minute: u32| Utc.with_ymd_and_hms(2023, 6, 1, 10, minute, 0).unwrap();
+    let grace = chrono::Duration::minutes(3);
+
+    let mut status = ServiceAlertStatus::default();
+    assert!(status.rules_written("a".into(), at(0), grace));
+    assert!(status.rules_written("a".into(), at(2), grace));
+    assert!(!status.rules_written("a".into(), at(3), grace));
+    assert_eq!(status.rules_changed_at, Some(at(0)));
+
+    // Changed rules restart the grace period
+    assert!(status.rules_written("b".into(), at(10), grace));
+    assert_eq!(status.rules_changed_at, Some(at(10)));
+    assert!(!status.rules_written("b".into(), at(14), grace));
+}
+
+#[test]
+fn test_crd_has_printer_columns() {
+    use kube::CustomResourceExt;
+
+    let columns: Vec<String> = ServiceAlert::crd().spec.versions[0]
+        .additional_printer_columns
+        .iter()
+        .flatten()
+        .map(|column| column.name.clone())
+        .collect();
+
+    assert_eq!(columns, vec!["Ready", "Rules", "Output", "Error", "Age"]);
+}
+
+#[test]
+fn test_example_manifest_parses() {
+    let example: ServiceAlert =
+        serde_yaml::from_str(include_str!("../../example-custom-resource.yaml"))
+            .expect("example manifest should be a valid ServiceAlert");
+
+    let grpc = example.spec.alerts.grpc.expect("example has gRPC alerts");
+    assert!(grpc.contains_key(&NetworkAlert::TrafficPerSecond));
+}
@@ -0,0 +1,384 @@
+use std::collections::BTreeMap;
+
+use kube::ResourceExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    crd::{AlertConfig, NetworkAlert, Operation, ServiceAlert, ServiceAlertSpec},
+    prometheus::{
+        alert::PrometheusSeverity,
+        network_signals::{grpc_signal, http_signal},
+        replica_alerts::{replica_series, replica_signal},
+    },
+};
+
+/// Label the Grafana sidecar (as deployed by `kube-prometheus-stack`) watches
+/// for when discovering dashboard `ConfigMaps`.
+pub const DASHBOARD_LABEL: &str = "grafana_dashboard";
+pub const DASHBOARD_LABEL_VALUE: &str = "1";
+
+/// Grafana dashboard UIDs may be at most 40 characters long.
+const MAX_UID_LENGTH: usize = 40;
+
+/// Lets Grafana pick a range that always contains enough samples for `rate()`,
+/// in place of the evaluation window used by the alert itself.
+const RATE_INTERVAL: &str = "$__rate_interval";
+
+const PANEL_WIDTH: u32 = 12;
+const PANEL_HEIGHT: u32 = 8;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Dashboard {
+    pub uid: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub schema_version: u32,
+    pub time: TimeRange,
+    pub templating: Templating,
+    pub panels: Vec<Panel>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TimeRange {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Templating {
+    pub list: Vec<Variable>,
+}
+
+/// Dashboard variable used to select the Prometheus datasource, so that the
+/// dashboard doesn't depend on the name of any particular datasource.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub label: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub query: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Panel {
+    pub id: u32,
+    pub title: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub grid_pos: GridPos,
+    pub datasource: Datasource,
+    pub targets: Vec<Target>,
+    pub field_config: FieldConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct GridPos {
+    pub h: u32,
+    pub w: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Datasource {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub uid: String,
+}
+
+impl Default for Datasource {
+    fn default() -> Self {
+        Self {
+            type_: String::from("prometheus"),
+            uid: String::from("${datasource}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Target {
+    pub ref_id: String,
+    pub expr: String,
+    pub legend_format: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FieldConfig {
+    pub defaults: FieldDefaults,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FieldDefaults {
+    pub unit: String,
+    pub thresholds: Thresholds,
+    pub custom: CustomFieldConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Thresholds {
+    pub mode: String,
+    pub steps: Vec<ThresholdStep>,
+}
+
+/// Grafana thresholds are steps, the first of which must have no value and
+/// acts as the base colour.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ThresholdStep {
+    pub color: String,
+    pub value: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomFieldConfig {
+    pub thresholds_style: ThresholdsStyle,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ThresholdsStyle {
+    pub mode: String,
+}
+
+impl From<&ServiceAlert> for Dashboard {
+    fn from(service_alert: &ServiceAlert) -> Self {
+        Dashboard::for_workloads(
+            service_alert,
+            &[service_alert.spec.workload_name().to_string()],
+        )
+    }
+}
+
+impl Dashboard {
+    /// Builds a dashboard plotting the alerted signals of every workload a
+    /// [`ServiceAlert`] resolved to, repeating panels for each workload.
+    pub fn for_workloads(service_alert: &ServiceAlert, workloads: &[String]) -> Self {
+        let name = service_alert.name_any();
+        let namespace = service_alert.namespace().unwrap_or_default();
+        let spec = &service_alert.spec;
+
+        let mut panels: Vec<PanelSource> = Vec::new();
+        for workload in workloads {
+            let mut workload_panels = workload_panels(&spec.for_workload(workload));
+            if workloads.len() > 1 {
+                for panel in &mut workload_panels {
+                    panel.title = format!("{workload}: {0}", panel.title);
+                }
+            }
+            panels.append(&mut workload_panels);
+        }
+
+        // Custom expressions already contain their comparison, so they can only
+        // be plotted as-is, without any threshold lines.
+        if let Some(custom_alerts) = &spec.alerts.custom {
+            custom_alerts.iter().for_each(|custom| {
+                panels.push(PanelSource {
+                    title: custom.alert.clone(),
+                    unit: "none",
+                    expr: custom.expr.clone(),
+                    legend: String::new(),
+                    thresholds: threshold_steps(&[]),
+                })
+            });
+        }
+
+        Dashboard {
+            uid: dashboard_uid(&namespace, &name),
+            title: format!("{0} / {1}", namespace, workloads.join(", ")),
+            tags: vec![String::from("cactuar"), spec.common_labels.owner.clone()],
+            schema_version: 38,
+            time: TimeRange {
+                from: String::from("now-6h"),
+                to: String::from("now"),
+            },
+            templating: Templating {
+                list: vec![Variable {
+                    name: String::from("datasource"),
+                    label: String::from("Datasource"),
+                    type_: String::from("datasource"),
+                    query: String::from("prometheus"),
+                }],
+            },
+            panels: panels
+                .into_iter()
+                .enumerate()
+                .map(|(i, source)| source.into_panel(i))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<Dashboard> for BTreeMap<String, String> {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: Dashboard) -> Result<Self, Self::Error> {
+        let identifier = format!("{}.json", value.uid);
+        let json_string = serde_json::to_string_pretty(&value)?;
+
+        Ok(BTreeMap::from([(identifier, json_string)]))
+    }
+}
+
+/// Everything that differs between panels, before they are laid out.
+struct PanelSource {
+    title: String,
+    unit: &'static str,
+    expr: String,
+    legend: String,
+    thresholds: Vec<ThresholdStep>,
+}
+
+impl PanelSource {
+    /// Lays panels out in a grid, two panels wide.
+    fn into_panel(self, index: usize) -> Panel {
+        let index = index as u32;
+
+        Panel {
+            id: index + 1,
+            title: self.title,
+            type_: String::from("timeseries"),
+            grid_pos: GridPos {
+                h: PANEL_HEIGHT,
+                w: PANEL_WIDTH,
+                x: (index % 2) * PANEL_WIDTH,
+                y: (index / 2) * PANEL_HEIGHT,
+            },
+            datasource: Datasource::default(),
+            targets: vec![Target {
+                ref_id: String::from("A"),
+                expr: self.expr,
+                legend_format: self.legend,
+            }],
+            field_config: FieldConfig {
+                defaults: FieldDefaults {
+                    unit: String::from(self.unit),
+                    thresholds: Thresholds {
+                        mode: String::from("absolute"),
+                        steps: self.thresholds,
+                    },
+                    custom: CustomFieldConfig {
+                        thresholds_style: ThresholdsStyle {
+                            mode: String::from("line"),
+                        },
+                    },
+                },
+            },
+        }
+    }
+}
+
+/// Panels plotting the signals of the network and replica alerts of a single
+/// workload.
+fn workload_panels(spec: &ServiceAlertSpec) -> Vec<PanelSource> {
+    let mut panels: Vec<PanelSource> = Vec::new();
+
+    if let Some(replica_alerts) = &spec.alerts.replica {
+        replica_alerts.values().for_each(|configs| {
+            panels.push(PanelSource {
+                title: String::from("Replicas"),
+                unit: "none",
+                expr: replica_signal(spec),
+                legend: format!("{{{{{0}}}}}", replica_series(spec).1),
+                thresholds: threshold_steps(configs),
+            })
+        });
+    }
+
+    if let Some(rest_alerts) = &spec.alerts.rest {
+        rest_alerts.iter().for_each(|(key, configs)| {
+            panels.push(network_panel("HTTP", key, configs, spec, http_signal))
+        });
+    }
+
+    if let Some(grpc_alerts) = &spec.alerts.grpc {
+        grpc_alerts.iter().for_each(|(key, configs)| {
+            panels.push(network_panel("gRPC", key, configs, spec, grpc_signal))
+        });
+    }
+
+    panels
+}
+
+fn network_panel(
+    protocol: &str,
+    network_alert: &NetworkAlert,
+    configs: &[AlertConfig],
+    spec: &ServiceAlertSpec,
+    signal: fn(&NetworkAlert, &ServiceAlertSpec, &str) -> String,
+) -> PanelSource {
+    let unit = match network_alert {
+        NetworkAlert::ErrorPercent => "percent",
+        NetworkAlert::TrafficPerSecond => "reqps",
+        NetworkAlert::LatencyMillisecondsP50
+        | NetworkAlert::LatencyMillisecondsP90
+        | NetworkAlert::LatencyMillisecondsP95
+        | NetworkAlert::LatencyMillisecondsP99 => "ms",
+    };
+
+    PanelSource {
+        title: format!("{protocol} {network_alert}"),
+        unit,
+        expr: signal(network_alert, spec, RATE_INTERVAL),
+        legend: String::from("{{destination_workload}}"),
+        thresholds: threshold_steps(configs),
+    }
+}
+
+/// Builds a threshold step for each configured alert, coloured by severity.
+/// Grafana expects steps in ascending order of value, each colouring the
+/// values from its own up to the next.
+///
+/// Alerts usually fire above their threshold, so values start out green. When
+/// every alert fires below its threshold instead, the colours are flipped:
+/// values start out in the colour of the lowest threshold, and turn green
+/// above the highest.
+fn threshold_steps(configs: &[AlertConfig]) -> Vec<ThresholdStep> {
+    let mut thresholds: Vec<(f32, String)> = configs
+        .iter()
+        .map(|conf| {
+            let color = match PrometheusSeverity::from(&conf.with_labels) {
+                PrometheusSeverity::Warning => String::from("orange"),
+                PrometheusSeverity::Critical | PrometheusSeverity::Page => String::from("red"),
+            };
+            (conf.value, color)
+        })
+        .collect();
+    thresholds.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let below = !configs.is_empty()
+        && configs
+            .iter()
+            .all(|conf| conf.operation == Operation::LessThan);
+    let (values, mut colors): (Vec<f32>, Vec<String>) = thresholds.into_iter().unzip();
+    match below {
+        true => colors.push(String::from("green")),
+        false => colors.insert(0, String::from("green")),
+    }
+
+    // The first colour has no lower bound
+    std::iter::once(None)
+        .chain(values.into_iter().map(Some))
+        .zip(colors)
+        .map(|(value, color)| ThresholdStep { color, value })
+        .collect()
+}
+
+/// Dashboard UIDs must be unique within a Grafana instance, and stable across
+/// reconciliations so that Grafana updates the dashboard rather than adding a
+/// new one. Cutting long names down to size would make names with a shared
+/// prefix collide, so the UID is a hash of the namespace and name instead.
+pub fn dashboard_uid(namespace: &str, name: &str) -> String {
+    let digest = Sha256::digest(format!("{namespace}/{name}"));
+
+    digest
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>()
+        .chars()
+        .take(MAX_UID_LENGTH)
+        .collect()
+}
@@ -0,0 +1,10 @@
+//! # Grafana
+//!
+//! Alongside its Prometheus rules, Cactuar generates a Grafana dashboard for
+//! every `ServiceAlert`, plotting each alerted signal with its thresholds. The
+//! dashboard is written to a `ConfigMap` that the Grafana sidecar picks up.
+
+pub mod dashboard;
+
+#[cfg(test)]
+mod tests;
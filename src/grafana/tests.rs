@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use color_eyre::Result;
+use pretty_assertions::assert_eq;
+
+use crate::{
+    crd::{
+        AlertConfig, Alerts, CommonLabels, NetworkAlert, Operation, ReplicaAlert, ServiceAlert,
+        ServiceAlertSpec,
+    },
+    grafana::dashboard::*,
+};
+
+fn alert_config(value: f32, severity: &str) -> Result<AlertConfig> {
+    Ok(AlertConfig {
+        operation: Operation::MoreThan,
+        value,
+        for_: "5m".parse()?,
+        window: None,
+        keep_firing_for: None,
+        schedule: None,
+        with_labels: BTreeMap::from([("severity".into(), severity.into())]),
+    })
+}
+
+fn test_service_alert() -> Result<ServiceAlert> {
+    let mut service_alert = ServiceAlert::new(
+        "example-service-alert",
+        ServiceAlertSpec {
+            common_labels: CommonLabels {
+                owner: "foo".into(),
+                origin: "cloud".into(),
+                extra: BTreeMap::new(),
+            },
+            deployment_name: "best-service-eu".into(),
+            workload_ref: None,
+            workload_selector: None,
+            maintenance: None,
+            profile: None,
+            template: None,
+            annotations: BTreeMap::new(),
+            interval: None,
+            limit: None,
+            alerts: Alerts {
+                grpc: Some(BTreeMap::from([(
+                    NetworkAlert::ErrorPercent,
+                    vec![
+                        alert_config(10_f32, "critical")?,
+                        alert_config(5_f32, "warning")?,
+                    ],
+                )])),
+                rest: None,
+                replica: None,
+                custom: None,
+            },
+        },
+    );
+    service_alert.metadata.namespace = Some("services".into());
+
+    Ok(service_alert)
+}
+
+#[test]
+fn test_dashboard_plots_signal_with_thresholds() -> Result<()> {
+    let dashboard = Dashboard::from(&test_service_alert()?);
+
+    assert_eq!(
+        dashboard.uid,
+        dashboard_uid("services", "example-service-alert")
+    );
+    assert_eq!(dashboard.panels.len(), 1);
+
+    let panel = &dashboard.panels[0];
+    assert_eq!(panel.title, "gRPC Error %");
+    assert_eq!(panel.field_config.defaults.unit, "percent");
+    assert!(panel.targets[0].expr.contains("[$__rate_interval]"));
+    assert!(!panel.targets[0].expr.contains('>'));
+    assert_eq!(
+        panel.field_config.defaults.thresholds.steps,
+        vec![
+            ThresholdStep {
+                color: "green".into(),
+                value: None,
+            },
+            ThresholdStep {
+                color: "orange".into(),
+                value: Some(5_f32),
+            },
+            ThresholdStep {
+                color: "red".into(),
+                value: Some(10_f32),
+            },
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_dashboard_config_map_data() -> Result<()> {
+    let data = BTreeMap::try_from(Dashboard::from(&test_service_alert()?))?;
+    let key = format!(
+        "{0}.json",
+        dashboard_uid("services", "example-service-alert")
+    );
+    let json = &data[&key];
+
+    let parsed: Dashboard = serde_json::from_str(json)?;
+    assert_eq!(parsed, Dashboard::from(&test_service_alert()?));
+
+    Ok(())
+}
+
+#[test]
+fn test_dashboard_uid_unique_for_long_names() {
+    let prefix = "a-very-long-service-alert-name-shared-by-both";
+    let first = dashboard_uid("services", &format!("{prefix}-checkout"));
+    let second = dashboard_uid("services", &format!("{prefix}-billing"));
+
+    assert_ne!(first, second);
+    assert_eq!(first.len(), 40);
+    // Stable across reconciliations
+    assert_eq!(
+        first,
+        dashboard_uid("services", &format!("{prefix}-checkout"))
+    );
+    // The namespace is part of the identity
+    assert_ne!(
+        dashboard_uid("services", "checkout"),
+        dashboard_uid("payments", "checkout")
+    );
+}
+
+#[test]
+fn test_thresholds_flipped_for_alerts_firing_below() -> Result<()> {
+    let below = |value, severity| -> Result<AlertConfig> {
+        Ok(AlertConfig {
+            operation: Operation::LessThan,
+            ..alert_config(value, severity)?
+        })
+    };
+    let mut service_alert = test_service_alert()?;
+    service_alert.spec.alerts = Alerts {
+        replica: Some(BTreeMap::from([(
+            ReplicaAlert::Count,
+            vec![below(3_f32, "warning")?, below(1_f32, "critical")?],
+        )])),
+        ..Alerts::default()
+    };
+
+    let dashboard = Dashboard::from(&service_alert);
+    assert_eq!(
+        dashboard.panels[0].field_config.defaults.thresholds.steps,
+        vec![
+            ThresholdStep {
+                color: "red".into(),
+                value: None,
+            },
+            ThresholdStep {
+                color: "orange".into(),
+                value: Some(1_f32),
+            },
+            ThresholdStep {
+                color: "green".into(),
+                value: Some(3_f32),
+            },
+        ]
+    );
+
+    Ok(())
+}
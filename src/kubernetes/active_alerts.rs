@@ -0,0 +1,138 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Utc;
+use futures::{future::BoxFuture, FutureExt};
+use kube::{
+    api::{Api, Patch, PatchParams},
+    Client,
+};
+use serde_json::json;
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    crd::{ActiveAlertsStatus, ServiceAlert, API_GROUP, API_VERSION, KIND},
+    prometheus::{
+        alert::PromAlerts,
+        api::{active_alerts_status, PrometheusClient},
+    },
+};
+
+const FIELD_MANAGER: &str = "cactuar.rs/active-alerts";
+
+/// ServiceAlert namespace and name.
+type Key = (String, String);
+
+/// Rules last generated for each ServiceAlert, recorded by the reconciler so
+/// that active alerts can be matched to them without generating them again.
+#[derive(Clone, Debug, Default)]
+pub struct GeneratedAlerts(Arc<Mutex<BTreeMap<Key, PromAlerts>>>);
+
+impl GeneratedAlerts {
+    pub fn insert(&self, namespace: &str, name: &str, alerts: PromAlerts) {
+        self.lock()
+            .insert((namespace.to_string(), name.to_string()), alerts);
+    }
+
+    pub fn remove(&self, namespace: &str, name: &str) {
+        self.lock()
+            .remove(&(namespace.to_string(), name.to_string()));
+    }
+
+    fn snapshot(&self) -> BTreeMap<Key, PromAlerts> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<Key, PromAlerts>> {
+        // The map is only ever replaced entry by entry, so it is still usable
+        // if another thread panicked while holding the lock
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Builds a future that asks Prometheus for active alerts every `refresh`, and
+/// updates `status.activeAlerts` of every ServiceAlert whose alerts changed.
+/// One query serves every ServiceAlert, and the rest of the status is left to
+/// the reconciler.
+pub fn active_alerts_future(
+    client: Client,
+    prometheus: PrometheusClient,
+    generated: GeneratedAlerts,
+    refresh: Duration,
+) -> BoxFuture<'static, ()> {
+    async move {
+        let mut interval = tokio::time::interval(refresh);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut reported = BTreeMap::new();
+
+        loop {
+            interval.tick().await;
+            refresh_active_alerts(&client, &prometheus, &generated, &mut reported).await;
+        }
+    }
+    .boxed()
+}
+
+async fn refresh_active_alerts(
+    client: &Client,
+    prometheus: &PrometheusClient,
+    generated: &GeneratedAlerts,
+    reported: &mut BTreeMap<Key, ActiveAlertsStatus>,
+) {
+    tracing::debug!("Checking for active alerts");
+    let active = match prometheus.alerts().await {
+        Ok(active) => active,
+        // Leave the last known alerts in place until Prometheus is back
+        Err(error) => {
+            tracing::warn!(%error, "Failed to check for active alerts");
+            return;
+        }
+    };
+
+    let now = Utc::now().format("%Y-%m-%dT%H:%M:%S").to_string();
+    let generated_alerts = generated.snapshot();
+    reported.retain(|key, _| generated_alerts.contains_key(key));
+
+    for ((namespace, name), alerts) in generated_alerts {
+        let key = (namespace, name);
+        let status = active_alerts_status(&alerts, &active, now.clone());
+        if reported
+            .get(&key)
+            .is_some_and(|previous| previous.same_alerts(&status))
+        {
+            continue;
+        }
+
+        let (namespace, name) = &key;
+        let api: Api<ServiceAlert> = Api::namespaced(client.clone(), namespace);
+        let patch = json!({
+            "apiVersion": format!("{API_GROUP}/{API_VERSION}"),
+            "kind": KIND,
+            "status": { "activeAlerts": status },
+        });
+        match api
+            .patch_status(
+                name,
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(patch),
+            )
+            .await
+        {
+            Ok(_) => {
+                reported.insert(key, status);
+            }
+            // Deleted without the reconciler noticing
+            Err(kube::Error::Api(response)) if response.code == 404 => {
+                generated.remove(namespace, name);
+            }
+            Err(error) => {
+                tracing::warn!(%error, namespace, name, "Failed to update active alerts");
+            }
+        }
+    }
+}
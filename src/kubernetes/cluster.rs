@@ -0,0 +1,186 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use k8s_openapi::{api::core::v1::Namespace, apimachinery::pkg::apis::meta::v1::ObjectMeta};
+use kube::{
+    api::{Api, DeleteParams, ListParams, Patch, PatchParams},
+    runtime::{controller::Action, reflector::ObjectRef, watcher, Controller},
+    Client, Resource, ResourceExt,
+};
+use serde_json::json;
+use thiserror::Error;
+
+use crate::crd::{
+    ClusterServiceAlert, ClusterServiceAlertStatus, ServiceAlert, API_GROUP, API_VERSION,
+    CLUSTER_SERVICE_ALERT_LABEL,
+};
+
+use super::discovery::{is_owned_by, MANAGED_LABEL, MANAGED_LABEL_VALUE};
+
+const FIELD_MANAGER: &str = "cactuar.rs/cluster";
+const SUCCESSFUL_REQUEUE_DURATION: u64 = 5 * 60;
+const FAIL_REQUEUE_DURATION: u64 = 10;
+
+#[derive(Debug, Error)]
+pub enum ClusterError {
+    #[error("MissingObjectKey: {0}")]
+    MissingObjectKey(&'static str),
+    #[error(transparent)]
+    Kube(#[from] kube::Error),
+}
+
+/// Builds a [`Controller`] future that applies every `ClusterServiceAlert` as
+/// a ServiceAlert in each namespace it selects. Policies are reconciled again
+/// when one of their ServiceAlerts changes, or when namespaces come and go.
+pub fn cluster_future(client: Client) -> BoxFuture<'static, ()> {
+    let controller = Controller::new(
+        Api::<ClusterServiceAlert>::all(client.clone()),
+        watcher::Config::default(),
+    );
+
+    // Namespaced objects can't be found from a cluster-scoped owner reference
+    // by `owns`, so generated ServiceAlerts are mapped back through their
+    // label instead.
+    let controller = controller.watches(
+        Api::<ServiceAlert>::all(client.clone()),
+        watcher::Config::default().labels(CLUSTER_SERVICE_ALERT_LABEL),
+        |service_alert| {
+            service_alert
+                .labels()
+                .get(CLUSTER_SERVICE_ALERT_LABEL)
+                .map(|name| ObjectRef::new(name))
+        },
+    );
+
+    let store = controller.store();
+    controller
+        .watches(
+            Api::<Namespace>::all(client.clone()),
+            watcher::Config::default(),
+            move |_| {
+                store
+                    .state()
+                    .iter()
+                    .map(|policy| ObjectRef::from_obj(policy.as_ref()))
+                    .collect::<Vec<_>>()
+            },
+        )
+        .run(reconcile, error_policy, Arc::new(client))
+        .for_each(|_| futures::future::ready(()))
+        .boxed()
+}
+
+#[tracing::instrument(skip_all, fields(policy.metadata.name))]
+async fn reconcile(
+    policy: Arc<ClusterServiceAlert>,
+    client: Arc<Client>,
+) -> Result<Action, ClusterError> {
+    let client = client.as_ref().clone();
+    let name = policy.name_any();
+    let uid = policy
+        .uid()
+        .ok_or_else(|| ClusterError::MissingObjectKey("uid"))?;
+
+    let namespace_params =
+        ListParams::default().labels(&policy.spec.namespace_selector.label_selector());
+    let mut namespaces: Vec<String> = Api::<Namespace>::all(client.clone())
+        .list(&namespace_params)
+        .await?
+        .into_iter()
+        .map(|namespace| namespace.name_any())
+        .collect();
+    namespaces.sort();
+
+    let mut applied = Vec::new();
+    for namespace in &namespaces {
+        let api: Api<ServiceAlert> = Api::namespaced(client.clone(), namespace);
+        if let Some(existing) = api.get_opt(&name).await? {
+            if !is_owned_by(&existing, &uid) {
+                tracing::warn!(namespace, "ServiceAlert of the same name exists, skipping");
+                continue;
+            }
+        }
+
+        tracing::debug!(namespace, "Applying ServiceAlert");
+        api.patch(
+            &name,
+            &PatchParams::apply(FIELD_MANAGER).force(),
+            &Patch::Apply(&child_service_alert(&policy, namespace)?),
+        )
+        .await?;
+        applied.push(namespace.clone());
+    }
+
+    // ServiceAlerts left in namespaces that are no longer selected
+    let owned_params =
+        ListParams::default().labels(&format!("{CLUSTER_SERVICE_ALERT_LABEL}={name}"));
+    for stale in Api::<ServiceAlert>::all(client.clone())
+        .list(&owned_params)
+        .await?
+        .into_iter()
+        .filter(|service_alert| is_owned_by(service_alert, &uid))
+        .filter(|service_alert| !applied.contains(&service_alert.namespace().unwrap_or_default()))
+    {
+        let namespace = stale.namespace().unwrap_or_default();
+        tracing::info!(
+            namespace,
+            "Namespace no longer selected, deleting ServiceAlert"
+        );
+        Api::<ServiceAlert>::namespaced(client.clone(), &namespace)
+            .delete(&name, &DeleteParams::default())
+            .await?;
+    }
+
+    Api::<ClusterServiceAlert>::all(client)
+        .patch_status(
+            &name,
+            &PatchParams::apply(API_GROUP).force(),
+            &Patch::Apply(json!({
+                "apiVersion": format!("{API_GROUP}/{API_VERSION}"),
+                "kind": "ClusterServiceAlert",
+                "status": ClusterServiceAlertStatus {
+                    namespaces: Some(applied),
+                },
+            })),
+        )
+        .await?;
+
+    Ok(Action::requeue(Duration::from_secs(
+        SUCCESSFUL_REQUEUE_DURATION,
+    )))
+}
+
+fn error_policy(
+    _policy: Arc<ClusterServiceAlert>,
+    error: &ClusterError,
+    _client: Arc<Client>,
+) -> Action {
+    tracing::error!(%error, FAIL_REQUEUE_DURATION, "ClusterServiceAlert failed, re-queueing");
+    Action::requeue(Duration::from_secs(FAIL_REQUEUE_DURATION))
+}
+
+/// Builds the ServiceAlert a policy generates in `namespace`, labelled with
+/// the policy's name and owned by it.
+pub fn child_service_alert(
+    policy: &ClusterServiceAlert,
+    namespace: &str,
+) -> Result<ServiceAlert, ClusterError> {
+    let owner_reference = policy
+        .controller_owner_ref(&())
+        .ok_or_else(|| ClusterError::MissingObjectKey("uid"))?;
+
+    Ok(ServiceAlert {
+        metadata: ObjectMeta {
+            name: Some(policy.name_any()),
+            namespace: Some(namespace.to_string()),
+            labels: Some(BTreeMap::from([
+                (MANAGED_LABEL.to_string(), MANAGED_LABEL_VALUE.to_string()),
+                (CLUSTER_SERVICE_ALERT_LABEL.to_string(), policy.name_any()),
+            ])),
+            owner_references: Some(vec![owner_reference]),
+            ..ObjectMeta::default()
+        },
+        spec: policy.spec.service_alert_spec(),
+        status: None,
+    })
+}
@@ -1,32 +1,74 @@
 use std::sync::Arc;
 
 use futures::{future::BoxFuture, FutureExt, StreamExt};
-use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::api::{apps::v1::Deployment, core::v1::ConfigMap};
 
 use kube::{
     api::{Api, ListParams},
     client::Client,
-    runtime::{controller::Controller, events::Reporter, watcher},
+    runtime::{
+        controller::Controller,
+        events::Reporter,
+        reflector::{ObjectRef, Store},
+        watcher,
+    },
+    ResourceExt,
 };
 
 use uuid::Uuid;
 
-use crate::crd::{ServiceAlert, FINALIZER_NAME};
+use crate::{
+    alertmanager::AlertmanagerClient,
+    config::CactuarConfig,
+    crd::{
+        ClusterServiceAlertTemplate, ServiceAlert, ServiceAlertDefaults, ServiceAlertTemplate,
+        TemplateKind, FINALIZER_NAME,
+    },
+    output::{vm_rule::VMRule, Output},
+    prometheus::api::PrometheusClient,
+};
 
+use super::active_alerts::{active_alerts_future, GeneratedAlerts};
+use super::cluster::cluster_future;
+use super::discovery::discovery_future;
 use super::reconciler::{self, Context};
 
 /// Builds a [`Controller`] future that controls `ServiceAlerts` that own
 /// `ConfigMaps`. To begin controlling Kubernetes resources, the caller should
 /// `.await` the returned future, or spawn it on an executor, such as
 /// [`tokio::task`].
-pub async fn controller_future() -> BoxFuture<'static, ()> {
+pub async fn controller_future(config: &CactuarConfig) -> BoxFuture<'static, ()> {
     let client = Client::try_default().await.expect("create client");
+    let output = Output::new(config).expect("create rule output");
+    let alertmanager =
+        AlertmanagerClient::new(&config.alertmanager).expect("create alertmanager client");
+    let active_alerts_refresh = config
+        .prometheus
+        .refresh
+        .map(|refresh| refresh.as_duration());
+    let prometheus = match config.prometheus.verify || active_alerts_refresh.is_some() {
+        true => PrometheusClient::new(&config.prometheus).expect("create prometheus client"),
+        false => None,
+    };
+    let active_alerts = prometheus
+        .clone()
+        .zip(active_alerts_refresh)
+        .map(|(prometheus, refresh)| (prometheus, refresh, GeneratedAlerts::default()));
     let context = Arc::new(Context {
         client: client.clone(),
         reporter: Reporter {
             controller: FINALIZER_NAME.into(),
             instance: Some(Uuid::new_v4().to_string()),
         },
+        output: output.clone(),
+        cluster: config.cluster.clone(),
+        alertmanager,
+        prometheus,
+        verify_rules: config.prometheus.verify,
+        window: config.prometheus.window,
+        generated_alerts: active_alerts
+            .as_ref()
+            .map(|(_, _, generated)| generated.clone()),
     });
 
     let service_alerter_api = Api::<ServiceAlert>::all(client.clone());
@@ -46,10 +88,135 @@ pub async fn controller_future() -> BoxFuture<'static, ()> {
         .await
         .expect("is the crd installed? please run: `cargo run --bin crdgen | kubectl apply -f -`");
 
-    // All good. Box the future for the client to `.await`
-    Controller::new(service_alerter_api, watcher::Config::default())
-        .owns(config_map_api, watcher::Config::default())
+    // VMRules are only watched when they are used, as the CRD is only
+    // installed in clusters running the VictoriaMetrics operator.
+    let mut controller = Controller::new(service_alerter_api, watcher::Config::default())
+        .owns(config_map_api, watcher::Config::default());
+    if let Output::VMRule = output {
+        let vm_rule_api = Api::<VMRule>::all(client.clone());
+        controller = controller.owns(vm_rule_api, watcher::Config::default());
+    }
+
+    // ServiceAlerts are reconciled again whenever a template they reference
+    // changes, found through the controller's own cache of ServiceAlerts.
+    let store = controller.store();
+    controller = controller.watches(
+        Api::<ServiceAlertTemplate>::all(client.clone()),
+        watcher::Config::default(),
+        move |template| {
+            dependents(
+                &store,
+                TemplateKind::ServiceAlertTemplate,
+                &template.name_any(),
+                template.namespace().as_deref(),
+            )
+        },
+    );
+    let store = controller.store();
+    controller = controller.watches(
+        Api::<ClusterServiceAlertTemplate>::all(client.clone()),
+        watcher::Config::default(),
+        move |template| {
+            dependents(
+                &store,
+                TemplateKind::ClusterServiceAlertTemplate,
+                &template.name_any(),
+                None,
+            )
+        },
+    );
+
+    // Defaults apply to every ServiceAlert in their namespace.
+    let store = controller.store();
+    controller = controller.watches(
+        Api::<ServiceAlertDefaults>::all(client.clone()),
+        watcher::Config::default(),
+        move |defaults| {
+            store
+                .state()
+                .iter()
+                .filter(|service_alert| service_alert.namespace() == defaults.namespace())
+                .map(|service_alert| ObjectRef::from_obj(service_alert.as_ref()))
+                .collect::<Vec<_>>()
+        },
+    );
+
+    // Workload selectors resolve to Deployments, so ServiceAlerts are
+    // reconciled again when a Deployment starts or stops matching them.
+    let store = controller.store();
+    controller = controller.watches(
+        Api::<Deployment>::all(client.clone()),
+        watcher::Config::default(),
+        move |deployment| {
+            store
+                .state()
+                .iter()
+                .filter(|service_alert| watches_deployment(service_alert, &deployment))
+                .map(|service_alert| ObjectRef::from_obj(service_alert.as_ref()))
+                .collect::<Vec<_>>()
+        },
+    );
+
+    let controller = controller
         .run(reconciler::reconcile, reconciler::error_policy, context)
-        .for_each(|_| futures::future::ready(()))
-        .boxed()
+        .for_each(|_| futures::future::ready(()));
+
+    // ClusterServiceAlerts and annotated Deployments are turned into
+    // ServiceAlerts by controllers of their own, running alongside.
+    let mut controllers = vec![controller.boxed(), cluster_future(client.clone())];
+    if config.discovery.enabled {
+        controllers.push(discovery_future(client.clone()));
+    }
+    if let Some((prometheus, refresh, generated)) = active_alerts {
+        controllers.push(active_alerts_future(client, prometheus, generated, refresh));
+    }
+
+    // All good. Box the future for the client to `.await`
+    futures::future::join_all(controllers).map(|_| ()).boxed()
+}
+
+/// Finds the ServiceAlerts referencing a template. Namespaced templates are
+/// only visible to ServiceAlerts in the same namespace.
+fn dependents(
+    store: &Store<ServiceAlert>,
+    kind: TemplateKind,
+    name: &str,
+    namespace: Option<&str>,
+) -> Vec<ObjectRef<ServiceAlert>> {
+    store
+        .state()
+        .iter()
+        .filter(|service_alert| {
+            service_alert
+                .spec
+                .template
+                .as_ref()
+                .is_some_and(|template| template.kind == kind && template.name == name)
+                && (namespace.is_none() || service_alert.namespace().as_deref() == namespace)
+        })
+        .map(|service_alert| ObjectRef::from_obj(service_alert.as_ref()))
+        .collect()
+}
+
+/// Whether a change to `deployment` can change what `service_alert` generates:
+/// the Deployment is in its namespace, and either matches its workload
+/// selector, or was one of its workloads during the last reconcile, so that
+/// Deployments that stop matching or are deleted are dropped too.
+pub(crate) fn watches_deployment(service_alert: &ServiceAlert, deployment: &Deployment) -> bool {
+    if service_alert.namespace() != deployment.namespace() {
+        return false;
+    }
+
+    let selected = service_alert
+        .spec
+        .workload_selector
+        .as_ref()
+        .is_some_and(|selector| selector.matches(deployment.labels()));
+    let previous = service_alert
+        .status
+        .as_ref()
+        .and_then(|status| status.workloads.as_ref())
+        .is_some_and(|workloads| workloads.contains(&deployment.name_any()));
+
+    selected || previous
 }
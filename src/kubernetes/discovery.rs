@@ -0,0 +1,205 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use k8s_openapi::{api::apps::v1::Deployment, apimachinery::pkg::apis::meta::v1::ObjectMeta};
+use kube::{
+    api::{Api, DeleteParams, Patch, PatchParams},
+    runtime::{
+        controller::Action,
+        events::{Event, EventType, Recorder, Reporter},
+        watcher, Controller,
+    },
+    Client, Resource, ResourceExt,
+};
+use thiserror::Error;
+
+use crate::crd::{Alerts, CommonLabels, Profile, ServiceAlert, ServiceAlertSpec};
+
+/// Profile of the managed ServiceAlert, e.g. `grpc-standard`.
+pub const PROFILE_ANNOTATION: &str = "cactuar.rs/profile";
+/// Owner label of the managed ServiceAlert.
+pub const OWNER_ANNOTATION: &str = "cactuar.rs/owner";
+/// Origin label of the managed ServiceAlert, defaults to [`DEFAULT_ORIGIN`].
+pub const ORIGIN_ANNOTATION: &str = "cactuar.rs/origin";
+pub const DEFAULT_ORIGIN: &str = "cloud";
+
+/// Marks ServiceAlerts that Cactuar generated, rather than ones written by
+/// hand.
+pub const MANAGED_LABEL: &str = "app.kubernetes.io/managed-by";
+pub const MANAGED_LABEL_VALUE: &str = "cactuar";
+
+const FIELD_MANAGER: &str = "cactuar.rs/discovery";
+const FAIL_REQUEUE_DURATION: u64 = 10;
+
+#[derive(Debug, Error)]
+pub enum DiscoveryError {
+    #[error("MissingObjectKey: {0}")]
+    MissingObjectKey(&'static str),
+    #[error("Unknown profile annotation: {0}")]
+    UnknownProfile(String),
+    #[error(transparent)]
+    Kube(#[from] kube::Error),
+}
+
+/// Builds a [`Controller`] future that creates a managed ServiceAlert for every
+/// Deployment annotated with a profile and owner, and deletes it again when
+/// the annotations are removed. The managed ServiceAlert is owned by the
+/// Deployment, so it is garbage collected along with it.
+pub fn discovery_future(client: Client) -> BoxFuture<'static, ()> {
+    Controller::new(
+        Api::<Deployment>::all(client.clone()),
+        watcher::Config::default(),
+    )
+    .owns(
+        Api::<ServiceAlert>::all(client.clone()),
+        watcher::Config::default(),
+    )
+    .run(reconcile, error_policy, Arc::new(client))
+    .for_each(|_| futures::future::ready(()))
+    .boxed()
+}
+
+#[tracing::instrument(skip_all, fields(deployment.metadata.name, deployment.metadata.namespace))]
+async fn reconcile(
+    deployment: Arc<Deployment>,
+    client: Arc<Client>,
+) -> Result<Action, DiscoveryError> {
+    let namespace = deployment
+        .namespace()
+        .ok_or_else(|| DiscoveryError::MissingObjectKey("namespace"))?;
+    let name = deployment.name_any();
+    let api: Api<ServiceAlert> = Api::namespaced(client.as_ref().clone(), &namespace);
+
+    let uid = deployment
+        .uid()
+        .ok_or_else(|| DiscoveryError::MissingObjectKey("uid"))?;
+
+    let existing = api.get_opt(&name).await?;
+    if existing.as_ref().is_some_and(|sa| !is_owned_by(sa, &uid)) {
+        tracing::debug!("ServiceAlert of the same name already exists, skipping");
+        return Ok(Action::await_change());
+    }
+
+    let managed = match managed_service_alert(&deployment) {
+        // Only an edit of the annotation can fix this, so tell whoever owns
+        // the Deployment and wait for it rather than retrying
+        Err(DiscoveryError::UnknownProfile(profile)) => {
+            tracing::warn!(profile, "Unknown profile annotation");
+            Recorder::new(
+                client.as_ref().clone(),
+                Reporter {
+                    controller: FIELD_MANAGER.into(),
+                    instance: None,
+                },
+                deployment.object_ref(&()),
+            )
+            .publish(Event {
+                type_: EventType::Warning,
+                reason: "UnknownProfile".into(),
+                note: Some(format!(
+                    "Unknown {PROFILE_ANNOTATION} annotation {profile:?}, expected one of \
+                     grpc-standard, http-standard or batch"
+                )),
+                action: "Discovering".into(),
+                secondary: None,
+            })
+            .await?;
+            return Ok(Action::await_change());
+        }
+        managed => managed?,
+    };
+
+    match managed {
+        Some(service_alert) => {
+            tracing::debug!("Applying managed ServiceAlert");
+            api.patch(
+                &name,
+                &PatchParams::apply(FIELD_MANAGER).force(),
+                &Patch::Apply(&service_alert),
+            )
+            .await?;
+        }
+        None if existing.is_some() => {
+            tracing::info!("Annotations removed, deleting managed ServiceAlert");
+            api.delete(&name, &DeleteParams::default()).await?;
+        }
+        None => {}
+    }
+
+    Ok(Action::await_change())
+}
+
+fn error_policy(
+    _deployment: Arc<Deployment>,
+    error: &DiscoveryError,
+    _client: Arc<Client>,
+) -> Action {
+    tracing::error!(%error, FAIL_REQUEUE_DURATION, "Discovery failed, re-queueing");
+    Action::requeue(Duration::from_secs(FAIL_REQUEUE_DURATION))
+}
+
+/// Whether a ServiceAlert was generated for the object with `uid`, as opposed
+/// to written by hand or generated for something else.
+pub(crate) fn is_owned_by(service_alert: &ServiceAlert, uid: &str) -> bool {
+    service_alert
+        .owner_references()
+        .iter()
+        .any(|owner| owner.uid == uid)
+}
+
+/// Builds the ServiceAlert managed for a Deployment, or [`None`] if the
+/// Deployment does not carry both the profile and owner annotations.
+pub fn managed_service_alert(
+    deployment: &Deployment,
+) -> Result<Option<ServiceAlert>, DiscoveryError> {
+    let annotations = deployment.annotations();
+    let (Some(profile), Some(owner)) = (
+        annotations.get(PROFILE_ANNOTATION),
+        annotations.get(OWNER_ANNOTATION),
+    ) else {
+        return Ok(None);
+    };
+
+    let profile: Profile = serde_json::from_value(serde_json::Value::from(profile.as_str()))
+        .map_err(|_| DiscoveryError::UnknownProfile(profile.clone()))?;
+    let owner_reference = deployment
+        .controller_owner_ref(&())
+        .ok_or_else(|| DiscoveryError::MissingObjectKey("uid"))?;
+
+    let name = deployment.name_any();
+    let spec = ServiceAlertSpec {
+        common_labels: CommonLabels {
+            owner: owner.clone(),
+            origin: annotations
+                .get(ORIGIN_ANNOTATION)
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_ORIGIN.to_string()),
+            extra: BTreeMap::new(),
+        },
+        deployment_name: name.clone(),
+        workload_ref: None,
+        workload_selector: None,
+        interval: None,
+        limit: None,
+        maintenance: None,
+        profile: Some(profile),
+        template: None,
+        annotations: BTreeMap::new(),
+        alerts: Alerts::default(),
+    };
+
+    Ok(Some(ServiceAlert {
+        metadata: ObjectMeta {
+            name: Some(name),
+            namespace: deployment.namespace(),
+            labels: Some(BTreeMap::from([(
+                MANAGED_LABEL.to_string(),
+                MANAGED_LABEL_VALUE.to_string(),
+            )])),
+            owner_references: Some(vec![owner_reference]),
+            ..ObjectMeta::default()
+        },
+        spec,
+        status: None,
+    }))
+}
@@ -15,3 +15,17 @@ pub mod reconciler;
 
 /// Contains the actual reconciliation operations.
 pub mod operations;
+
+/// Creates managed ServiceAlerts for Deployments annotated with a profile.
+pub mod discovery;
+
+/// Applies cluster-wide `ClusterServiceAlert` policies as ServiceAlerts in the
+/// namespaces they select.
+pub mod cluster;
+
+/// Keeps the pending and firing alerts in the status of every ServiceAlert up
+/// to date, with one query to Prometheus for all of them.
+pub mod active_alerts;
+
+#[cfg(test)]
+mod tests;
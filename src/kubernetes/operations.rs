@@ -1,22 +1,35 @@
 use std::{collections::BTreeMap, sync::Arc};
 
-use chrono::Utc;
-use k8s_openapi::api::core::v1::ConfigMap;
+use chrono::{DateTime, Utc};
+use k8s_openapi::api::{
+    apps::v1::{DaemonSet, Deployment, StatefulSet},
+    core::v1::ConfigMap,
+};
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
 use kube::{
-    api::{Api, Patch, PatchParams, ResourceExt},
+    api::{Api, ApiResource, DynamicObject, ListParams, Patch, PatchParams, ResourceExt},
+    core::GroupVersionKind,
     runtime::{
         controller::Action,
         events::{Event, EventType, Recorder},
     },
-    Resource,
+    Client, Resource,
 };
 use serde_json::json;
 use thiserror::Error;
 use tokio::time::Duration;
 
-use crate::crd::{ServiceAlert, ServiceAlertStatus, API_GROUP, API_VERSION, FINALIZER_NAME, KIND};
-use crate::prometheus::alert::PromAlerts;
+use crate::alertmanager::{AlertmanagerError, Silence};
+use crate::crd::{
+    ClusterServiceAlertTemplate, Condition, ConditionStatus, ConditionType, MaintenanceStatus,
+    ServiceAlert, ServiceAlertDefaults, ServiceAlertSpec, ServiceAlertStatus, ServiceAlertTemplate,
+    TemplateKind, WorkloadKind, API_GROUP, API_VERSION, DEFAULTS_NAME, FINALIZER_NAME, KIND,
+};
+use crate::grafana::dashboard::{Dashboard, DASHBOARD_LABEL, DASHBOARD_LABEL_VALUE};
+use crate::output::{
+    apply_if_changed, content_hash, inherited_labels, OutputError, CONTENT_HASH_ANNOTATION,
+};
+use crate::prometheus::{alert::PromAlerts, api::RuleVerification, schedule::next_offset_change};
 
 use super::reconciler::Context;
 
@@ -26,13 +39,51 @@ pub enum OperationError {
     ConfigMapCreationFailed(#[source] kube::Error),
     #[error("MissingObjectKey: {0}")]
     MissingObjectKey(&'static str),
+    #[error("InvalidSpec: {0}")]
+    InvalidSpec(&'static str),
+    #[error("MissingTemplate: {0}")]
+    MissingTemplate(String),
     #[error(transparent)]
     Kube(#[from] kube::Error),
     #[error(transparent)]
+    Output(#[from] OutputError),
+    #[error(transparent)]
+    Alertmanager(#[from] AlertmanagerError),
+    #[error(transparent)]
     Other(#[from] color_eyre::Report),
 }
 
+impl OperationError {
+    /// CamelCase reason for status conditions.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            OperationError::ConfigMapCreationFailed(_) => "ConfigMapCreationFailed",
+            OperationError::MissingObjectKey(_) => "MissingObjectKey",
+            OperationError::InvalidSpec(_) => "InvalidSpec",
+            OperationError::MissingTemplate(_) => "MissingTemplate",
+            OperationError::Kube(_) => "KubernetesError",
+            OperationError::Output(_) => "OutputFailed",
+            OperationError::Alertmanager(_) => "AlertmanagerFailed",
+            OperationError::Other(_) => "GenerationFailed",
+        }
+    }
+
+    /// Whether the error lies with the spec, rather than with writing rules.
+    pub fn is_invalid_spec(&self) -> bool {
+        matches!(
+            self,
+            OperationError::InvalidSpec(_)
+                | OperationError::MissingTemplate(_)
+                | OperationError::Other(_)
+        )
+    }
+}
+
 const SUCCESSFUL_REQUEUE_DURATION: u64 = 5 * 60;
+const VERIFY_REQUEUE_DURATION: u64 = 30;
+/// How long Prometheus is given to load changed rules, as the config reloader
+/// only sees a changed `ConfigMap` once the kubelet has synced it.
+const RULES_LOAD_GRACE_PERIOD: i64 = 3 * 60;
 
 impl ServiceAlert {
     // Reconcile (for non-finalizer related changes)
@@ -42,49 +93,337 @@ impl ServiceAlert {
         let namespace = self
             .namespace()
             .ok_or_else(|| OperationError::MissingObjectKey("namespace"))?;
+        let service_alert_api: Api<ServiceAlert> = Api::namespaced(ctx.client.clone(), &namespace);
+        let ps = PatchParams::apply(API_GROUP).force();
+
+        let (status, rerender_at) = match self.reconcile_rules(&ctx, &namespace).await {
+            Ok(reconciled) => reconciled,
+            Err(error) => {
+                // The failure is what the status is most useful for, so it is
+                // recorded before handing the error to the error policy.
+                let patch = Patch::Apply(self.generate_status_patch(self.failed_status(&error)));
+                if let Err(status_error) = service_alert_api.patch_status(&name, &ps, &patch).await
+                {
+                    tracing::warn!(%status_error, "Failed to record error in status");
+                }
+                return Err(error);
+            }
+        };
+
+        let loaded = status.condition(ConditionType::RulesLoaded);
+        let previously_loaded = self
+            .status
+            .as_ref()
+            .and_then(|status| status.condition(ConditionType::RulesLoaded));
+        let loaded_message = status
+            .conditions
+            .iter()
+            .find(|condition| condition.type_ == ConditionType::RulesLoaded)
+            .map(|condition| condition.message.clone());
+
+        tracing::debug!("Updating ServiceAlert status");
+        service_alert_api
+            .patch_status(
+                &name,
+                &ps,
+                &Patch::Apply(self.generate_status_patch(status)),
+            )
+            .await?;
+
+        if loaded != previously_loaded {
+            let event = match loaded {
+                Some(ConditionStatus::True) => Some((EventType::Normal, "RulesLoaded")),
+                Some(ConditionStatus::False) => Some((EventType::Warning, "RulesNotLoaded")),
+                _ => None,
+            };
+            if let Some((type_, reason)) = event {
+                Recorder::new(
+                    ctx.client.clone(),
+                    ctx.reporter.clone(),
+                    self.object_ref(&()),
+                )
+                .publish(Event {
+                    type_,
+                    reason: reason.into(),
+                    note: loaded_message,
+                    action: "Verifying".into(),
+                    secondary: None,
+                })
+                .await?;
+            }
+        }
+
+        // Prometheus takes a while to reload rules, so check back sooner while
+        // they are not loaded yet
+        let requeue = match loaded.is_some_and(|loaded| loaded != ConditionStatus::True) {
+            true => {
+                tracing::info!("Reconciliation successful, rules not loaded yet");
+                Duration::from_secs(VERIFY_REQUEUE_DURATION)
+            }
+            // If no events were received, check back every 5 minutes
+            false => {
+                tracing::info!("Reconciliation successful");
+                Duration::from_secs(SUCCESSFUL_REQUEUE_DURATION)
+            }
+        };
+
+        // Scheduled rules hold a fixed UTC offset, so render them again as
+        // soon as their timezone changes it
+        let requeue = match rerender_at.and_then(|at| (at - Utc::now()).to_std().ok()) {
+            Some(rerender) => {
+                tracing::debug!(?rerender, "Timezone offset changes before the requeue");
+                rerender.min(requeue)
+            }
+            None => requeue,
+        };
+
+        Ok(Action::requeue(requeue))
+    }
+
+    /// Generates and writes the rules, dashboard and maintenance silence,
+    /// returning the status describing them, and when the rules need to be
+    /// rendered again because a schedule's timezone changes its UTC offset.
+    async fn reconcile_rules(
+        &self,
+        ctx: &Context,
+        namespace: &str,
+    ) -> Result<(ServiceAlertStatus, Option<DateTime<Utc>>), OperationError> {
+        let name = self.name_any();
         let owner_references = self
             .controller_owner_ref(&())
             .ok_or_else(|| OperationError::MissingObjectKey("owner_references"))?;
 
-        let service_alert_api: Api<ServiceAlert> = Api::namespaced(ctx.client.clone(), &namespace);
-        let config_map_api: Api<ConfigMap> = Api::namespaced(ctx.client.clone(), &namespace);
+        let config_map_api: Api<ConfigMap> = Api::namespaced(ctx.client.clone(), namespace);
 
-        let prom_alert = PromAlerts::try_from(self.spec.clone())?;
+        let workloads = self.workloads(&ctx.client, namespace).await?;
+        let target_found = self
+            .target_found(&ctx.client, namespace, &workloads)
+            .await?;
+        let mut expanded = self.expanded(&ctx.client, namespace).await?;
+        expanded.spec = expanded.spec.with_window(ctx.window);
+        let prom_alert = PromAlerts::for_workloads(&expanded.spec, &workloads)?
+            .with_external_labels(&ctx.cluster)
+            .with_policy_label(self)
+            .with_service_alert_label(self);
+        let dashboard = Dashboard::for_workloads(&expanded, &workloads);
 
-        tracing::debug!("Generating ConfigMap");
-        let cm = ConfigMap {
+        let now = Utc::now();
+        let rerender_at = next_offset_change(
+            expanded.spec.alerts.schedules(),
+            now,
+            chrono::Duration::seconds(SUCCESSFUL_REQUEUE_DURATION as i64),
+        );
+        let silence = match &self.spec.maintenance {
+            // Alertmanager rejects these, so there is no point retrying
+            Some(maintenance) if maintenance.start >= maintenance.end => {
+                return Err(OperationError::InvalidSpec(
+                    "maintenance must start before it ends",
+                ));
+            }
+            Some(maintenance) => {
+                Silence::for_maintenance(maintenance, &prom_alert, &self.silence_creator(), now)
+            }
+            None => None,
+        };
+
+        let group_count = prom_alert.groups.len() as u32;
+        let rule_count = prom_alert
+            .groups
+            .iter()
+            .map(|group| group.rules.len() as u32)
+            .sum::<u32>();
+        let output = ctx.output.object_name(self)?;
+        let verify = match (&ctx.prometheus, ctx.output.loaded_location(self)?) {
+            (Some(prometheus), Some(location)) if ctx.verify_rules => {
+                Some((prometheus, location, prom_alert.clone()))
+            }
+            _ => None,
+        };
+        if let Some(generated) = &ctx.generated_alerts {
+            generated.insert(namespace, &name, prom_alert.clone());
+        }
+
+        let rules_hash = content_hash(&prom_alert)?;
+        ctx.output.apply(&ctx.client, self, prom_alert).await?;
+
+        tracing::debug!("Generating dashboard ConfigMap");
+        let dashboard_data = BTreeMap::try_from(dashboard)?;
+        let dashboard_cm = ConfigMap {
             metadata: ObjectMeta {
-                name: Some(name.clone()),
-                namespace: Some(namespace),
-                // This label is what allows prometheus to pick up the configMap
-                labels: Some(BTreeMap::from([("rules".into(), "prom-rule".into())])),
+                name: Some(format!("{name}-dashboard")),
+                namespace: Some(namespace.to_string()),
+                // This label is what allows the Grafana sidecar to pick up the
+                // configMap
+                labels: Some(
+                    inherited_labels(self)
+                        .into_iter()
+                        .chain([(DASHBOARD_LABEL.into(), DASHBOARD_LABEL_VALUE.into())])
+                        .collect(),
+                ),
+                annotations: Some(BTreeMap::from([(
+                    CONTENT_HASH_ANNOTATION.into(),
+                    content_hash(&dashboard_data)?,
+                )])),
                 owner_references: Some(vec![owner_references]),
                 ..ObjectMeta::default()
             },
-            data: Some(BTreeMap::try_from(prom_alert)?),
+            data: Some(dashboard_data),
             ..Default::default()
         };
 
-        tracing::debug!("Patching ConfigMap");
-        config_map_api
-            .patch(
-                &name,
-                &PatchParams::apply(FINALIZER_NAME),
-                &Patch::Apply(&cm),
-            )
-            .await?;
+        tracing::debug!("Patching dashboard ConfigMap");
+        apply_if_changed(&config_map_api, &dashboard_cm).await?;
 
-        tracing::debug!("Updating ServiceAlert status");
-        let ps = PatchParams::apply(API_GROUP).force();
-        service_alert_api
-            .patch_status(&name, &ps, &Patch::Apply(self.generate_status_patch()))
-            .await?;
+        let maintenance = self.sync_maintenance(ctx, silence.as_ref(), now).await?;
 
-        // If no events were received, check back every 5 minutes
-        tracing::info!("Reconciliation successful");
-        Ok(Action::requeue(Duration::from_secs(
-            SUCCESSFUL_REQUEUE_DURATION,
-        )))
+        let mut status = self.status.clone().unwrap_or_default();
+        status.last_reconciled_at = Some(now.format("%Y-%m-%dT%H:%M:%S").to_string());
+        status.reconciliation_expires_at = Some(
+            (now + chrono::Duration::seconds(SUCCESSFUL_REQUEUE_DURATION as i64))
+                .format("%Y-%m-%dT%H:%M:%S")
+                .to_string(),
+        );
+        status.observed_generation = self.metadata.generation;
+        status.rule_count = Some(rule_count);
+        status.group_count = Some(group_count);
+        status.last_error = None;
+        status.maintenance = maintenance;
+        let loading = status.rules_written(
+            rules_hash,
+            now,
+            chrono::Duration::seconds(RULES_LOAD_GRACE_PERIOD),
+        );
+
+        status.set_condition(self.condition(
+            ConditionType::Valid,
+            ConditionStatus::True,
+            "RulesGenerated",
+            format!("Generated {rule_count} rules in {group_count} groups"),
+        ));
+        status.set_condition(match target_found {
+            true => self.condition(
+                ConditionType::TargetFound,
+                ConditionStatus::True,
+                "WorkloadsFound",
+                format!("Found {0}", workloads.join(", ")),
+            ),
+            false => self.condition(
+                ConditionType::TargetFound,
+                ConditionStatus::False,
+                "WorkloadsNotFound",
+                String::from("No workloads alerted on exist"),
+            ),
+        });
+        status.set_condition(self.condition(
+            ConditionType::RulesApplied,
+            ConditionStatus::True,
+            "Applied",
+            format!("Rules written to {output}"),
+        ));
+
+        match verify {
+            Some((prometheus, location, generated)) => {
+                tracing::debug!("Verifying rules are loaded");
+                status.set_condition(match prometheus.rules().await {
+                    Ok(loaded) => {
+                        let verification = RuleVerification::new(&generated, &location, &loaded);
+                        // Recently written rules are likely still on their way
+                        // to Prometheus, so they aren't reported as missing yet
+                        let (loaded, reason) = match (verification.is_ok(), loading) {
+                            (true, _) => (ConditionStatus::True, "Loaded"),
+                            (false, true) => (ConditionStatus::Unknown, "Pending"),
+                            (false, false) => (ConditionStatus::False, "NotLoaded"),
+                        };
+                        self.condition(
+                            ConditionType::RulesLoaded,
+                            loaded,
+                            reason,
+                            verification.summary(),
+                        )
+                    }
+                    Err(error) => self.condition(
+                        ConditionType::RulesLoaded,
+                        ConditionStatus::Unknown,
+                        "PrometheusUnavailable",
+                        error.to_string(),
+                    ),
+                });
+            }
+            None => status.remove_condition(ConditionType::RulesLoaded),
+        }
+
+        // Ready follows the first condition that is not met
+        let unmet = status
+            .conditions
+            .iter()
+            .find(|condition| {
+                condition.type_ != ConditionType::Ready
+                    && condition.status == ConditionStatus::False
+            })
+            .cloned();
+        status.set_condition(match unmet {
+            Some(unmet) => self.condition(
+                ConditionType::Ready,
+                ConditionStatus::False,
+                &unmet.reason,
+                unmet.message,
+            ),
+            None => self.condition(
+                ConditionType::Ready,
+                ConditionStatus::True,
+                "Reconciled",
+                String::from("Alerts are generated and applied"),
+            ),
+        });
+
+        status.output = Some(output);
+        status.workloads = Some(workloads);
+
+        Ok((status, rerender_at))
+    }
+
+    /// Returns the previous status, updated to describe a failed reconcile.
+    fn failed_status(&self, error: &OperationError) -> ServiceAlertStatus {
+        let mut status = self.status.clone().unwrap_or_default();
+        status.observed_generation = self.metadata.generation;
+        status.last_error = Some(error.to_string());
+
+        let reason = error.reason();
+        if error.is_invalid_spec() {
+            status.set_condition(self.condition(
+                ConditionType::Valid,
+                ConditionStatus::False,
+                reason,
+                error.to_string(),
+            ));
+        }
+        for type_ in [ConditionType::RulesApplied, ConditionType::Ready] {
+            status.set_condition(self.condition(
+                type_,
+                ConditionStatus::False,
+                reason,
+                error.to_string(),
+            ));
+        }
+
+        status
+    }
+
+    fn condition(
+        &self,
+        type_: ConditionType,
+        status: ConditionStatus,
+        reason: &str,
+        message: String,
+    ) -> Condition {
+        Condition {
+            type_,
+            status,
+            reason: reason.to_string(),
+            message,
+            last_transition_time: Utc::now(),
+            observed_generation: self.metadata.generation,
+        }
     }
 
     // Reconcile with finalize cleanup (the object was deleted)
@@ -92,6 +431,16 @@ impl ServiceAlert {
     pub async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action, OperationError> {
         tracing::debug!("Deleting ServiceAlert");
 
+        ctx.output.delete(&ctx.client, self).await?;
+        if let Some(generated) = &ctx.generated_alerts {
+            generated.remove(&self.namespace().unwrap_or_default(), &self.name_any());
+        }
+
+        if let Some(alertmanager) = &ctx.alertmanager {
+            tracing::debug!("Expiring maintenance silences");
+            alertmanager.expire_all(&self.silence_creator()).await?;
+        }
+
         let recorder = Recorder::new(
             ctx.client.clone(),
             ctx.reporter.clone(),
@@ -112,8 +461,179 @@ impl ServiceAlert {
         Ok(Action::await_change())
     }
 
+    /// Creates or expires the Alertmanager silence for the maintenance window,
+    /// returning the window to show in the status while it has not ended.
+    async fn sync_maintenance(
+        &self,
+        ctx: &Context,
+        silence: Option<&Silence>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<MaintenanceStatus>, OperationError> {
+        let silence_id = match &ctx.alertmanager {
+            Some(alertmanager) => {
+                tracing::debug!("Syncing maintenance silence");
+                alertmanager
+                    .sync(&self.silence_creator(), silence, now)
+                    .await?
+            }
+            None => {
+                if silence.is_some() {
+                    tracing::warn!("Alertmanager is not configured, ignoring maintenance window");
+                }
+                None
+            }
+        };
+
+        Ok(self
+            .spec
+            .maintenance
+            .as_ref()
+            .filter(|maintenance| maintenance.end > now)
+            .map(|maintenance| MaintenanceStatus {
+                start: maintenance.start,
+                end: maintenance.end,
+                reason: maintenance.reason.clone(),
+                active: maintenance.start <= now,
+                silence_id,
+            }))
+    }
+
+    /// Identifies the silences created for this ServiceAlert, through their
+    /// `createdBy` field.
+    fn silence_creator(&self) -> String {
+        format!(
+            "{FINALIZER_NAME}/{0}/{1}",
+            self.namespace().unwrap_or_default(),
+            self.name_any()
+        )
+    }
+
+    /// Returns a copy of this ServiceAlert with its profile, the alerts of its
+    /// template and the defaults of its namespace merged in, see
+    /// [`crate::crd::ServiceAlertSpec::expanded`] and
+    /// [`crate::crd::ServiceAlertSpec::with_defaults`].
+    async fn expanded(
+        &self,
+        client: &Client,
+        namespace: &str,
+    ) -> Result<ServiceAlert, OperationError> {
+        let template = match &self.spec.template {
+            Some(template) => {
+                tracing::debug!(?template, "Resolving template");
+                let alerts = match template.kind {
+                    TemplateKind::ServiceAlertTemplate => {
+                        Api::<ServiceAlertTemplate>::namespaced(client.clone(), namespace)
+                            .get_opt(&template.name)
+                            .await?
+                            .map(|template| template.spec.alerts)
+                    }
+                    TemplateKind::ClusterServiceAlertTemplate => {
+                        Api::<ClusterServiceAlertTemplate>::all(client.clone())
+                            .get_opt(&template.name)
+                            .await?
+                            .map(|template| template.spec.alerts)
+                    }
+                };
+                Some(alerts.ok_or_else(|| OperationError::MissingTemplate(template.name.clone()))?)
+            }
+            None => None,
+        };
+
+        let defaults = Api::<ServiceAlertDefaults>::namespaced(client.clone(), namespace)
+            .get_opt(DEFAULTS_NAME)
+            .await?
+            .map(|defaults| defaults.spec)
+            .unwrap_or_default();
+
+        let mut expanded = self.clone();
+        expanded.spec = self
+            .spec
+            .expanded(template.as_ref())
+            .with_defaults(&defaults);
+
+        if expanded.spec.common_labels.owner.is_empty() {
+            return Err(OperationError::InvalidSpec(
+                "commonLabels.owner is required, on the ServiceAlert or its ServiceAlertDefaults",
+            ));
+        }
+
+        Ok(expanded)
+    }
+
+    /// Whether the workloads alerted on exist. Selectors only ever resolve to
+    /// existing Deployments, named workloads have to be looked up.
+    async fn target_found(
+        &self,
+        client: &Client,
+        namespace: &str,
+        workloads: &[String],
+    ) -> Result<bool, OperationError> {
+        if self.spec.workload_selector.is_some() {
+            return Ok(!workloads.is_empty());
+        }
+
+        let name = self.spec.workload_name();
+        let found = match self.spec.workload_kind() {
+            WorkloadKind::Deployment => Api::<Deployment>::namespaced(client.clone(), namespace)
+                .get_opt(name)
+                .await?
+                .is_some(),
+            WorkloadKind::StatefulSet => Api::<StatefulSet>::namespaced(client.clone(), namespace)
+                .get_opt(name)
+                .await?
+                .is_some(),
+            WorkloadKind::DaemonSet => Api::<DaemonSet>::namespaced(client.clone(), namespace)
+                .get_opt(name)
+                .await?
+                .is_some(),
+            WorkloadKind::Rollout => {
+                let resource = ApiResource::from_gvk(&GroupVersionKind::gvk(
+                    "argoproj.io",
+                    "v1alpha1",
+                    "Rollout",
+                ));
+                Api::<DynamicObject>::namespaced_with(client.clone(), namespace, &resource)
+                    .get_opt(name)
+                    .await?
+                    .is_some()
+            }
+        };
+
+        Ok(found)
+    }
+
+    /// Resolves the workloads this ServiceAlert generates alerts for, either
+    /// the named workload, or every Deployment matching the selector.
+    async fn workloads(
+        &self,
+        client: &Client,
+        namespace: &str,
+    ) -> Result<Vec<String>, OperationError> {
+        let spec = &self.spec;
+        check_workload_target(spec)?;
+
+        let Some(selector) = &spec.workload_selector else {
+            return Ok(vec![spec.workload_name().to_string()]);
+        };
+
+        tracing::debug!("Resolving workload selector");
+        let deployment_api: Api<Deployment> = Api::namespaced(client.clone(), namespace);
+        let deployments = deployment_api
+            .list(&ListParams::default().labels(&selector.label_selector()))
+            .await?;
+
+        let mut workloads: Vec<String> = deployments.iter().map(ResourceExt::name_any).collect();
+        workloads.sort();
+
+        Ok(workloads)
+    }
+
     #[tracing::instrument(skip_all)]
-    pub fn generate_status_patch(&self) -> serde_json::Value {
+    pub fn generate_status_patch(&self, mut status: ServiceAlertStatus) -> serde_json::Value {
+        // Kept up to date by the active alerts task, under a field manager of
+        // its own
+        status.active_alerts = None;
+
         // Ideally this could return a Patch::Apply<ServiceAlertStatus>, but
         // there's an odd interaction with kube.rs here, where `apiVersion` is
         // required and presumably generated from our struct, but not available
@@ -124,14 +644,23 @@ impl ServiceAlert {
         json!({
             "apiVersion": format!("{API_GROUP}/{API_VERSION}"),
             "kind": KIND,
-            "status": ServiceAlertStatus{
-                last_reconciled_at: Some(Utc::now().format("%Y-%m-%dT%H:%M:%S").to_string()),
-                reconciliation_expires_at: Some(
-                    (Utc::now() + chrono::Duration::seconds(SUCCESSFUL_REQUEUE_DURATION as i64))
-                        .format("%Y-%m-%dT%H:%M:%S")
-                        .to_string()
-                ),
-            }
+            "status": status,
         })
     }
 }
+
+/// Checks that a spec names its workloads in exactly one way, through
+/// `deploymentName`, `workloadRef` or `workloadSelector`.
+pub(crate) fn check_workload_target(spec: &ServiceAlertSpec) -> Result<(), OperationError> {
+    let targets = [
+        !spec.deployment_name.is_empty(),
+        spec.workload_ref.is_some(),
+        spec.workload_selector.is_some(),
+    ];
+    match targets.iter().filter(|target| **target).count() {
+        1 => Ok(()),
+        _ => Err(OperationError::InvalidSpec(
+            "exactly one of deploymentName, workloadRef or workloadSelector is required",
+        )),
+    }
+}
@@ -2,13 +2,22 @@ use kube::{Api, ResourceExt};
 use std::{sync::Arc, time::Duration};
 
 use kube::{
-    runtime::{controller::Action, events::Reporter, finalizer::Event},
+    runtime::{
+        controller::Action,
+        events::Reporter,
+        finalizer::{self, Event},
+    },
     Client,
 };
 use thiserror::Error;
 
+use super::active_alerts::GeneratedAlerts;
 use super::operations::OperationError;
-use crate::crd::{ServiceAlert, FINALIZER_NAME};
+use crate::alertmanager::AlertmanagerClient;
+use crate::config::Cluster;
+use crate::crd::{PromDuration, ServiceAlert, FINALIZER_NAME};
+use crate::output::Output;
+use crate::prometheus::api::PrometheusClient;
 
 const FAIL_REQUEUE_DURATION: u64 = 10;
 
@@ -18,6 +27,22 @@ pub struct Context {
     /// Kubernetes client
     pub client: Client,
     pub reporter: Reporter,
+    /// Where generated rules are written to
+    pub output: Output,
+    /// External labels added to generated rules
+    pub cluster: Cluster,
+    /// Silences alerts during maintenance windows, if configured
+    pub alertmanager: Option<AlertmanagerClient>,
+    /// Verifies that generated rules are loaded and reports active alerts,
+    /// if configured
+    pub prometheus: Option<PrometheusClient>,
+    /// Whether generated rules are checked to be loaded by Prometheus
+    pub verify_rules: bool,
+    /// Evaluation window of alerts that don't set one
+    pub window: PromDuration,
+    /// Generated rules to match active alerts against, if active alerts are
+    /// shown in the status
+    pub generated_alerts: Option<GeneratedAlerts>,
 }
 
 #[derive(Debug, Error)]
@@ -56,6 +81,15 @@ pub fn error_policy(
     error: &ReconcilerError,
     _ctx: Arc<Context>,
 ) -> Action {
+    // Retrying an invalid spec gives the same result until someone edits it,
+    // and edits trigger a reconcile anyway.
+    if let ReconcilerError::Finalizer(finalizer::Error::ApplyFailed(error)) = error {
+        if error.is_invalid_spec() {
+            tracing::warn!(%error, "Invalid ServiceAlert, waiting for a change");
+            return Action::await_change();
+        }
+    }
+
     // All of our owned resources are entirely contained within Kubernetes, so
     // if we encounter an error, we can just requeue reconciliation.
     tracing::error!(FAIL_REQUEUE_DURATION, "Reconciliation failed, re-queueing");
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+
+use color_eyre::Result;
+use k8s_openapi::{api::apps::v1::Deployment, apimachinery::pkg::apis::meta::v1::ObjectMeta};
+use pretty_assertions::assert_eq;
+
+use kube::ResourceExt;
+
+use crate::{
+    crd::{
+        ClusterServiceAlert, Profile, ServiceAlert, ServiceAlertSpec, ServiceAlertStatus,
+        WorkloadKind, WorkloadRef, WorkloadSelector, CLUSTER_SERVICE_ALERT_LABEL,
+    },
+    kubernetes::{
+        cluster::child_service_alert,
+        controller::watches_deployment,
+        discovery::{
+            is_owned_by, managed_service_alert, DiscoveryError, MANAGED_LABEL, MANAGED_LABEL_VALUE,
+        },
+        operations::{check_workload_target, OperationError},
+    },
+};
+
+fn deployment(annotations: &[(&str, &str)]) -> Deployment {
+    Deployment {
+        metadata: ObjectMeta {
+            name: Some(String::from("best-service-eu")),
+            namespace: Some(String::from("services")),
+            uid: Some(String::from("3b7c4f1e")),
+            annotations: Some(
+                annotations
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            ..ObjectMeta::default()
+        },
+        ..Deployment::default()
+    }
+}
+
+#[test]
+fn test_managed_service_alert_from_annotations() -> Result<()> {
+    let service_alert = managed_service_alert(&deployment(&[
+        ("cactuar.rs/profile", "http-standard"),
+        ("cactuar.rs/owner", "payments"),
+    ]))?
+    .expect("annotations are present");
+
+    assert_eq!(
+        service_alert.metadata.name.as_deref(),
+        Some("best-service-eu")
+    );
+    assert_eq!(
+        service_alert.metadata.labels,
+        Some(BTreeMap::from([(
+            MANAGED_LABEL.to_string(),
+            MANAGED_LABEL_VALUE.to_string()
+        )]))
+    );
+
+    let owner = &service_alert.metadata.owner_references.unwrap()[0];
+    assert_eq!(owner.kind, "Deployment");
+    assert_eq!(owner.uid, "3b7c4f1e");
+
+    let spec = service_alert.spec;
+    assert_eq!(spec.deployment_name, "best-service-eu");
+    assert_eq!(spec.profile, Some(Profile::HttpStandard));
+    assert_eq!(spec.common_labels.owner, "payments");
+    assert_eq!(spec.common_labels.origin, "cloud");
+
+    Ok(())
+}
+
+#[test]
+fn test_managed_service_alert_requires_both_annotations() -> Result<()> {
+    assert!(managed_service_alert(&deployment(&[]))?.is_none());
+    assert!(managed_service_alert(&deployment(&[("cactuar.rs/owner", "payments")]))?.is_none());
+
+    assert!(matches!(
+        managed_service_alert(&deployment(&[
+            ("cactuar.rs/profile", "kafka-standard"),
+            ("cactuar.rs/owner", "payments"),
+        ])),
+        Err(DiscoveryError::UnknownProfile(profile)) if profile == "kafka-standard"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_cluster_service_alert_generates_labelled_service_alert() -> Result<()> {
+    let mut policy = ClusterServiceAlert::new(
+        "replicas-baseline",
+        serde_yaml::from_str(
+            r#"
+namespaceSelector:
+  matchLabels:
+    tier: production
+workloadSelector: {}
+commonLabels:
+  owner: platform
+  origin: cloud
+profile: batch
+"#,
+        )?,
+    );
+    policy.metadata.uid = Some(String::from("9d2e81aa"));
+
+    let service_alert = child_service_alert(&policy, "payments")?;
+
+    assert_eq!(
+        service_alert.metadata.name.as_deref(),
+        Some("replicas-baseline")
+    );
+    assert_eq!(
+        service_alert.metadata.namespace.as_deref(),
+        Some("payments")
+    );
+    assert_eq!(
+        service_alert.labels().get(CLUSTER_SERVICE_ALERT_LABEL),
+        Some(&String::from("replicas-baseline"))
+    );
+    assert!(is_owned_by(&service_alert, "9d2e81aa"));
+
+    let spec = service_alert.spec;
+    assert_eq!(spec.profile, Some(Profile::Batch));
+    assert_eq!(spec.common_labels.owner, "platform");
+    assert_eq!(
+        spec.workload_selector
+            .map(|selector| selector.label_selector()),
+        Some(String::new())
+    );
+    assert_eq!(
+        policy.spec.namespace_selector.label_selector(),
+        "tier=production"
+    );
+
+    Ok(())
+}
+
+fn managed_spec() -> Result<ServiceAlertSpec> {
+    let service_alert = managed_service_alert(&deployment(&[
+        ("cactuar.rs/profile", "http-standard"),
+        ("cactuar.rs/owner", "foo"),
+    ]))?
+    .expect("annotated deployment");
+    Ok(service_alert.spec)
+}
+
+#[test]
+fn test_workload_target_accepts_exactly_one() -> Result<()> {
+    let workload_ref = Some(WorkloadRef {
+        kind: WorkloadKind::StatefulSet,
+        name: "best-service-eu".into(),
+    });
+    let workload_selector = Some(WorkloadSelector {
+        match_labels: BTreeMap::from([("tier".into(), "backend".into())]),
+        aggregate: false,
+    });
+
+    let by_name = managed_spec()?;
+    assert!(check_workload_target(&by_name).is_ok());
+
+    let by_ref = ServiceAlertSpec {
+        deployment_name: String::new(),
+        workload_ref: workload_ref.clone(),
+        ..managed_spec()?
+    };
+    assert!(check_workload_target(&by_ref).is_ok());
+
+    let by_selector = ServiceAlertSpec {
+        deployment_name: String::new(),
+        workload_selector: workload_selector.clone(),
+        ..managed_spec()?
+    };
+    assert!(check_workload_target(&by_selector).is_ok());
+
+    let invalid = [
+        ServiceAlertSpec {
+            deployment_name: String::new(),
+            ..managed_spec()?
+        },
+        ServiceAlertSpec {
+            workload_ref: workload_ref.clone(),
+            ..managed_spec()?
+        },
+        ServiceAlertSpec {
+            workload_selector: workload_selector.clone(),
+            ..managed_spec()?
+        },
+        ServiceAlertSpec {
+            deployment_name: String::new(),
+            workload_ref,
+            workload_selector,
+            ..managed_spec()?
+        },
+    ];
+    for spec in invalid {
+        assert!(matches!(
+            check_workload_target(&spec),
+            Err(OperationError::InvalidSpec(_))
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_deployments_map_to_selecting_service_alerts() -> Result<()> {
+    let mut service_alert = ServiceAlert::new(
+        "backends",
+        ServiceAlertSpec {
+            deployment_name: String::new(),
+            workload_selector: Some(WorkloadSelector {
+                match_labels: BTreeMap::from([("tier".into(), "backend".into())]),
+                aggregate: false,
+            }),
+            ..managed_spec()?
+        },
+    );
+    service_alert.metadata.namespace = Some(String::from("services"));
+
+    let mut backend = deployment(&[]);
+    backend
+        .labels_mut()
+        .insert(String::from("tier"), String::from("backend"));
+    assert!(watches_deployment(&service_alert, &backend));
+
+    // Other namespaces are never selected
+    let mut elsewhere = backend.clone();
+    elsewhere.metadata.namespace = Some(String::from("other"));
+    assert!(!watches_deployment(&service_alert, &elsewhere));
+
+    // A Deployment that stopped matching is still mapped while it is listed
+    // as a workload, so that its alerts are dropped
+    let frontend = deployment(&[]);
+    assert!(!watches_deployment(&service_alert, &frontend));
+    service_alert.status = Some(ServiceAlertStatus {
+        workloads: Some(vec![String::from("best-service-eu")]),
+        ..ServiceAlertStatus::default()
+    });
+    assert!(watches_deployment(&service_alert, &frontend));
+
+    Ok(())
+}
@@ -47,6 +47,9 @@
 //! Kubernetes controller for creating Prometheus alerts using standard metrics
 //! emitted by an Istio sidecar container.
 
+/// Alertmanager silences for maintenance windows of service alerts.
+pub mod alertmanager;
+
 /// Configuration management and default config values for Cactuar.
 pub mod config;
 
@@ -56,6 +59,9 @@ pub mod kubernetes;
 /// Cactuar's representation of service alerts and associated logic.
 pub mod crd;
 
+/// Grafana dashboards generated for service alerts.
+pub mod grafana;
+
 /// HTTP router and handlers for exposing readiness and Prometheus metrics.
 pub mod http;
 
@@ -63,6 +69,9 @@ pub mod http;
 /// logging.
 pub mod logging;
 
+/// Destinations other than `ConfigMaps` that generated rules can be written to.
+pub mod output;
+
 /// Types and logic for representing alerts in their native Prometheus format
 /// and structure.
 pub mod prometheus;
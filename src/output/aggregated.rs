@@ -0,0 +1,270 @@
+//! # Aggregated ConfigMaps
+//!
+//! Rather than one `ConfigMap` per `ServiceAlert`, every `ServiceAlert` in a
+//! namespace writes its rule file into one of a few shared `ConfigMaps`,
+//! chosen by hashing its name.
+//!
+//! Each `ServiceAlert` server-side applies its own rule file key and owner
+//! reference with a field manager of its own. Kubernetes merges these, so
+//! updating or removing one `ServiceAlert` never touches the keys of another.
+//! Once every owner is gone, the shared `ConfigMap` is garbage collected.
+//!
+//! Each `ServiceAlert` keeps a hash of its rule file in an annotation of its
+//! own, see [`content_hash_annotation`], so that unchanged rule files are not
+//! rewritten.
+//!
+//! Changing the number of shards moves `ServiceAlerts` to other `ConfigMaps`.
+//! Their rule files are removed from the old `ConfigMaps` on the next
+//! reconcile, and `ConfigMaps` left without any `ServiceAlerts` are deleted.
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
+use kube::{
+    api::{Api, DeleteParams, ListParams, Patch, PatchParams},
+    Client, Resource, ResourceExt,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    crd::{ServiceAlert, FINALIZER_NAME},
+    prometheus::alert::PromAlerts,
+};
+
+use super::{
+    apply_if_changed_as, content_hash, group_prefix, namespace, rule_file_key, OutputError,
+    CONTENT_HASH_ANNOTATION,
+};
+
+/// Name shared by the aggregated `ConfigMaps`, suffixed with the shard number.
+pub const AGGREGATED_NAME: &str = "cactuar-rules";
+
+/// Returns the name of the shared `ConfigMap` a [`ServiceAlert`] writes to.
+pub fn shard_name(service_alert: &ServiceAlert, shards: u32) -> String {
+    let digest = Sha256::digest(service_alert.name_any());
+    let hash = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+
+    format!("{AGGREGATED_NAME}-{0}", hash % shards.max(1))
+}
+
+/// Annotation holding the hash of the rule file a [`ServiceAlert`] contributes
+/// to a shared `ConfigMap`. Annotation names are limited to 63 characters, so
+/// the `ServiceAlert` is identified by a hash of its name.
+pub fn content_hash_annotation(service_alert: &ServiceAlert) -> String {
+    let digest = Sha256::digest(service_alert.name_any());
+    let id: String = digest[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    format!("{CONTENT_HASH_ANNOTATION}-{id}")
+}
+
+/// Generates the part of a shared rule `ConfigMap` that a [`ServiceAlert`]
+/// owns. Passing [`None`] for `alerts` leaves out the rule file, which removes
+/// it when applied.
+pub fn config_map(
+    service_alert: &ServiceAlert,
+    alerts: Option<PromAlerts>,
+    shards: u32,
+) -> Result<ConfigMap, OutputError> {
+    let name = service_alert.name_any();
+    let namespace = namespace(service_alert)?;
+
+    // The ConfigMap outlives any single ServiceAlert, so none of them control
+    // it.
+    let owner_reference = service_alert
+        .controller_owner_ref(&())
+        .map(|owner| OwnerReference {
+            controller: None,
+            ..owner
+        })
+        .ok_or(OutputError::MissingObjectKey("owner_references"))?;
+
+    // Groups from every ServiceAlert end up in the same Prometheus rule
+    // namespace, so their names need to be unique.
+    let data = alerts
+        .map(|mut alerts| -> Result<_, OutputError> {
+            let prefix = group_prefix(&namespace, &name);
+            for group in &mut alerts.groups {
+                group.name = format!("{prefix}{0}", group.name);
+            }
+
+            Ok(BTreeMap::from([(
+                rule_file_key(&namespace, &name),
+                serde_yaml::to_string(&alerts).map_err(color_eyre::Report::from)?,
+            )]))
+        })
+        .transpose()?;
+
+    // The owner is part of the hash, so that a recreated ServiceAlert still
+    // gets its owner reference added
+    let annotations = data
+        .as_ref()
+        .map(|data| -> Result<_, OutputError> {
+            Ok(BTreeMap::from([(
+                content_hash_annotation(service_alert),
+                content_hash(&(data, &owner_reference.uid))?,
+            )]))
+        })
+        .transpose()?;
+
+    Ok(ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(shard_name(service_alert, shards)),
+            namespace: Some(namespace),
+            // This label is what allows prometheus to pick up the configMap
+            labels: Some(BTreeMap::from([("rules".into(), "prom-rule".into())])),
+            annotations,
+            owner_references: Some(vec![owner_reference]),
+            ..ObjectMeta::default()
+        },
+        data,
+        ..Default::default()
+    })
+}
+
+/// Each [`ServiceAlert`] manages its own fields of the shared `ConfigMap`.
+fn field_manager(service_alert: &ServiceAlert) -> String {
+    format!("{FINALIZER_NAME}/{0}", service_alert.name_any())
+}
+
+/// What to do with a shared `ConfigMap` that a [`ServiceAlert`] no longer
+/// writes to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StaleShard {
+    /// Nothing but the `ServiceAlert` is left in it.
+    Delete,
+    /// Other `ServiceAlerts` still use it, so only the fields of this one are
+    /// given up, by applying an object without them.
+    Release(Box<ConfigMap>),
+}
+
+/// Decides what to do with a shared `ConfigMap` other than the current shard
+/// of a [`ServiceAlert`], or [`None`] if the `ServiceAlert` has no part in it.
+pub fn stale_shard(
+    existing: &ConfigMap,
+    service_alert: &ServiceAlert,
+) -> Result<Option<StaleShard>, OutputError> {
+    let key = rule_file_key(&namespace(service_alert)?, &service_alert.name_any());
+    let uid = service_alert
+        .uid()
+        .ok_or(OutputError::MissingObjectKey("uid"))?;
+
+    let data = existing.data.clone().unwrap_or_default();
+    let owners = existing.owner_references();
+    let contributes = data.contains_key(&key) || owners.iter().any(|owner| owner.uid == uid);
+    if !contributes {
+        return Ok(None);
+    }
+
+    let others =
+        data.keys().any(|other| other != &key) || owners.iter().any(|owner| owner.uid != uid);
+    if !others {
+        return Ok(Some(StaleShard::Delete));
+    }
+
+    Ok(Some(StaleShard::Release(Box::new(ConfigMap {
+        metadata: ObjectMeta {
+            name: existing.metadata.name.clone(),
+            namespace: existing.metadata.namespace.clone(),
+            ..ObjectMeta::default()
+        },
+        ..Default::default()
+    }))))
+}
+
+/// Removes the rule file of a [`ServiceAlert`] from every shared `ConfigMap`
+/// other than `current`, e.g. after the number of shards changed.
+async fn clean_stale_shards(
+    api: &Api<ConfigMap>,
+    service_alert: &ServiceAlert,
+    current: &str,
+) -> Result<(), OutputError> {
+    let prefix = format!("{AGGREGATED_NAME}-");
+    let shards = api
+        .list(&ListParams::default().labels("rules=prom-rule"))
+        .await?;
+
+    for existing in shards
+        .into_iter()
+        .filter(|cm| cm.name_any().starts_with(&prefix) && cm.name_any() != current)
+    {
+        let name = existing.name_any();
+        match stale_shard(&existing, service_alert)? {
+            Some(StaleShard::Delete) => {
+                tracing::debug!(name, "Deleting stale aggregated ConfigMap");
+                api.delete(&name, &DeleteParams::default()).await?;
+            }
+            Some(StaleShard::Release(cm)) => {
+                tracing::debug!(name, "Removing rule file from stale aggregated ConfigMap");
+                api.patch(
+                    &name,
+                    &PatchParams::apply(&field_manager(service_alert)),
+                    &Patch::Apply(cm.as_ref()),
+                )
+                .await?;
+            }
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+async fn patch(
+    client: &Client,
+    service_alert: &ServiceAlert,
+    cm: ConfigMap,
+) -> Result<(), OutputError> {
+    let config_map_api: Api<ConfigMap> =
+        Api::namespaced(client.clone(), &namespace(service_alert)?);
+
+    apply_if_changed_as(
+        &config_map_api,
+        &cm,
+        &content_hash_annotation(service_alert),
+        &field_manager(service_alert),
+    )
+    .await?;
+
+    clean_stale_shards(
+        &config_map_api,
+        service_alert,
+        cm.metadata.name.as_deref().unwrap_or_default(),
+    )
+    .await
+}
+
+pub async fn apply(
+    client: &Client,
+    service_alert: &ServiceAlert,
+    alerts: PromAlerts,
+    shards: u32,
+) -> Result<(), OutputError> {
+    tracing::debug!("Patching aggregated ConfigMap");
+    patch(
+        client,
+        service_alert,
+        config_map(service_alert, Some(alerts), shards)?,
+    )
+    .await
+}
+
+/// Removes the rule file of a [`ServiceAlert`] from its shared `ConfigMap`.
+/// The owner reference is kept, so that the garbage collector can delete the
+/// `ConfigMap` once the last `ServiceAlert` is gone.
+pub async fn delete(
+    client: &Client,
+    service_alert: &ServiceAlert,
+    shards: u32,
+) -> Result<(), OutputError> {
+    tracing::debug!("Removing rule file from aggregated ConfigMap");
+    patch(
+        client,
+        service_alert,
+        config_map(service_alert, None, shards)?,
+    )
+    .await
+}
@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{api::Api, Client, Resource, ResourceExt};
+
+use crate::{crd::ServiceAlert, prometheus::alert::PromAlerts};
+
+use super::{
+    apply_if_changed, content_hash, inherited_labels, namespace, rule_file_key, OutputError,
+    CONTENT_HASH_ANNOTATION,
+};
+
+/// Generates the rule `ConfigMap` for a [`ServiceAlert`]. The `ConfigMap` shares
+/// its name with the `ServiceAlert`, which owns it.
+pub fn config_map(
+    service_alert: &ServiceAlert,
+    alerts: PromAlerts,
+) -> Result<ConfigMap, OutputError> {
+    let owner_references = service_alert
+        .controller_owner_ref(&())
+        .ok_or(OutputError::MissingObjectKey("owner_references"))?;
+    let data = BTreeMap::from([(
+        rule_file_key(&namespace(service_alert)?, &service_alert.name_any()),
+        serde_yaml::to_string(&alerts).map_err(color_eyre::Report::from)?,
+    )]);
+
+    Ok(ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(service_alert.name_any()),
+            namespace: service_alert.namespace(),
+            // This label is what allows prometheus to pick up the configMap
+            labels: Some(
+                inherited_labels(service_alert)
+                    .into_iter()
+                    .chain([("rules".into(), "prom-rule".into())])
+                    .collect(),
+            ),
+            annotations: Some(BTreeMap::from([(
+                CONTENT_HASH_ANNOTATION.into(),
+                content_hash(&data)?,
+            )])),
+            owner_references: Some(vec![owner_references]),
+            ..ObjectMeta::default()
+        },
+        data: Some(data),
+        ..Default::default()
+    })
+}
+
+pub async fn apply(
+    client: &Client,
+    service_alert: &ServiceAlert,
+    alerts: PromAlerts,
+) -> Result<(), OutputError> {
+    let config_map_api: Api<ConfigMap> =
+        Api::namespaced(client.clone(), &namespace(service_alert)?);
+
+    tracing::debug!("Generating ConfigMap");
+    let cm = config_map(service_alert, alerts)?;
+
+    tracing::debug!("Patching ConfigMap");
+    apply_if_changed(&config_map_api, &cm).await
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{api::Api, Client, Resource, ResourceExt};
+
+use crate::{
+    config::GrafanaAlerting,
+    crd::ServiceAlert,
+    prometheus::{alert::PromAlerts, grafana_alerts::GrafanaProvisioning},
+};
+
+use super::{
+    apply_if_changed, content_hash, group_prefix, inherited_labels, namespace, rule_file_key,
+    OutputError, CONTENT_HASH_ANNOTATION,
+};
+
+/// Label the Grafana sidecar (as deployed by `kube-prometheus-stack`) watches
+/// for when discovering alert provisioning `ConfigMaps`.
+pub const ALERT_LABEL: &str = "grafana_alert";
+pub const ALERT_LABEL_VALUE: &str = "1";
+
+/// Returns the name of the provisioning `ConfigMap` of a [`ServiceAlert`].
+pub fn config_map_name(service_alert: &ServiceAlert) -> String {
+    format!("{0}-grafana-alerts", service_alert.name_any())
+}
+
+/// Generates the Grafana alert provisioning `ConfigMap` for a [`ServiceAlert`],
+/// which owns it.
+pub fn config_map(
+    service_alert: &ServiceAlert,
+    alerts: PromAlerts,
+    options: &GrafanaAlerting,
+) -> Result<ConfigMap, OutputError> {
+    let name = service_alert.name_any();
+    let namespace = namespace(service_alert)?;
+    let owner_references = service_alert
+        .controller_owner_ref(&())
+        .ok_or(OutputError::MissingObjectKey("owner_references"))?;
+
+    let provisioning = GrafanaProvisioning::new(&alerts, options, &group_prefix(&namespace, &name));
+
+    // The sidecar copies every key into a single provisioning directory, so
+    // keys have to be unique across namespaces.
+    let data = BTreeMap::from([(
+        rule_file_key(&namespace, &name),
+        serde_yaml::to_string(&provisioning).map_err(color_eyre::Report::from)?,
+    )]);
+
+    Ok(ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(config_map_name(service_alert)),
+            namespace: Some(namespace),
+            // This label is what allows the Grafana sidecar to pick up the
+            // configMap
+            labels: Some(
+                inherited_labels(service_alert)
+                    .into_iter()
+                    .chain([(ALERT_LABEL.into(), ALERT_LABEL_VALUE.into())])
+                    .collect(),
+            ),
+            annotations: Some(BTreeMap::from([(
+                CONTENT_HASH_ANNOTATION.into(),
+                content_hash(&data)?,
+            )])),
+            owner_references: Some(vec![owner_references]),
+            ..ObjectMeta::default()
+        },
+        data: Some(data),
+        ..Default::default()
+    })
+}
+
+pub async fn apply(
+    client: &Client,
+    service_alert: &ServiceAlert,
+    alerts: PromAlerts,
+    options: &GrafanaAlerting,
+) -> Result<(), OutputError> {
+    let config_map_api: Api<ConfigMap> =
+        Api::namespaced(client.clone(), &namespace(service_alert)?);
+
+    tracing::debug!("Generating Grafana alerts ConfigMap");
+    let cm = config_map(service_alert, alerts, options)?;
+
+    tracing::debug!("Patching Grafana alerts ConfigMap");
+    apply_if_changed(&config_map_api, &cm).await
+}
@@ -0,0 +1,326 @@
+//! # Output
+//!
+//! Generated rules can be written to several destinations, depending on what
+//! evaluates alerts in a cluster. The destination is chosen per cluster with
+//! the `output.backend` config value, see [`crate::config`].
+//!
+//! - [`config_map`]: a `ConfigMap` picked up by an in-cluster Prometheus
+//! - [`aggregated`]: `ConfigMaps` shared by every `ServiceAlert` in a
+//!   namespace, for namespaces with too many `ServiceAlerts` for one each
+//! - [`vm_rule`]: a `VMRule` picked up by the VictoriaMetrics operator
+//! - [`ruler`]: rule groups pushed to a Mimir or Cortex ruler's HTTP API
+//! - [`grafana_alerting`]: Grafana-managed alert rules, provisioned from a
+//!   `ConfigMap` picked up by the Grafana sidecar
+//!
+//! Kubernetes objects are annotated with a hash of their generated content, see
+//! [`CONTENT_HASH_ANNOTATION`], and are only written when that hash changes or
+//! their content was edited by hand.
+//! Objects generated for a single `ServiceAlert` also carry its
+//! [`inherited_labels`].
+
+use std::{collections::BTreeMap, fmt::Debug};
+
+use kube::{
+    api::{Api, Patch, PatchParams},
+    Client, Resource, ResourceExt,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::{
+    config::{CactuarConfig, GrafanaAlerting, OutputBackend},
+    crd::{ServiceAlert, CLUSTER_SERVICE_ALERT_LABEL, FINALIZER_NAME},
+    prometheus::{alert::PromAlerts, api::RuleLocation},
+};
+
+use self::ruler::{RulerClient, RulerError};
+
+pub mod aggregated;
+pub mod config_map;
+pub mod grafana_alerting;
+pub mod ruler;
+pub mod vm_rule;
+
+#[derive(Debug, Error)]
+pub enum OutputError {
+    #[error("MissingObjectKey: {0}")]
+    MissingObjectKey(&'static str),
+    #[error("Aggregated output is not supported by the {0:?} backend")]
+    AggregateUnsupported(OutputBackend),
+    #[error(transparent)]
+    Kube(#[from] kube::Error),
+    #[error(transparent)]
+    Ruler(#[from] RulerError),
+    #[error(transparent)]
+    Other(#[from] color_eyre::Report),
+}
+
+/// Destination that generated rules are written to.
+#[derive(Clone, Debug)]
+pub enum Output {
+    ConfigMap,
+    AggregatedConfigMap { shards: u32 },
+    VMRule,
+    Ruler(Box<RulerClient>),
+    Grafana(GrafanaAlerting),
+}
+
+impl Output {
+    /// Creates the [`Output`] selected by the Cactuar config.
+    pub fn new(config: &CactuarConfig) -> Result<Self, OutputError> {
+        if config.output.aggregate && config.output.backend != OutputBackend::ConfigMap {
+            return Err(OutputError::AggregateUnsupported(config.output.backend));
+        }
+
+        Ok(match config.output.backend {
+            OutputBackend::ConfigMap if config.output.aggregate => Output::AggregatedConfigMap {
+                shards: config.output.shards.max(1),
+            },
+            OutputBackend::ConfigMap => Output::ConfigMap,
+            OutputBackend::VMRule => Output::VMRule,
+            OutputBackend::Ruler => Output::Ruler(Box::new(RulerClient::new(&config.ruler)?)),
+            OutputBackend::Grafana => Output::Grafana(config.grafana.clone()),
+        })
+    }
+
+    /// Writes the rules generated for a [`ServiceAlert`], replacing whatever
+    /// was previously written for it.
+    pub async fn apply(
+        &self,
+        client: &Client,
+        service_alert: &ServiceAlert,
+        alerts: PromAlerts,
+    ) -> Result<(), OutputError> {
+        match self {
+            Output::ConfigMap => config_map::apply(client, service_alert, alerts).await,
+            Output::AggregatedConfigMap { shards } => {
+                aggregated::apply(client, service_alert, alerts, *shards).await
+            }
+            Output::VMRule => vm_rule::apply(client, service_alert, alerts).await,
+            Output::Grafana(options) => {
+                grafana_alerting::apply(client, service_alert, alerts, options).await
+            }
+            Output::Ruler(ruler) => {
+                let prefix = group_prefix(&namespace(service_alert)?, &service_alert.name_any());
+                Ok(ruler.sync(&prefix, &alerts).await?)
+            }
+        }
+    }
+
+    /// Describes where the rules of a [`ServiceAlert`] are written to, e.g.
+    /// `ConfigMap/example`, for its status.
+    pub fn object_name(&self, service_alert: &ServiceAlert) -> Result<String, OutputError> {
+        let name = service_alert.name_any();
+        Ok(match self {
+            Output::ConfigMap => format!("ConfigMap/{name}"),
+            Output::AggregatedConfigMap { shards } => {
+                format!(
+                    "ConfigMap/{0}",
+                    aggregated::shard_name(service_alert, *shards)
+                )
+            }
+            Output::VMRule => format!("VMRule/{name}"),
+            Output::Grafana(_) => {
+                format!(
+                    "ConfigMap/{0}",
+                    grafana_alerting::config_map_name(service_alert)
+                )
+            }
+            Output::Ruler(ruler) => format!(
+                "{0}/{1}*",
+                ruler.namespace(),
+                group_prefix(&namespace(service_alert)?, &name)
+            ),
+        })
+    }
+
+    /// Returns where Prometheus loads the rule groups of a [`ServiceAlert`]
+    /// from, or [`None`] if the rules are not evaluated by Prometheus at all.
+    pub fn loaded_location(
+        &self,
+        service_alert: &ServiceAlert,
+    ) -> Result<Option<RuleLocation>, OutputError> {
+        let namespace = namespace(service_alert)?;
+        let name = service_alert.name_any();
+        Ok(match self {
+            // Group names are the same for every ServiceAlert here, so only
+            // the rule file tells them apart
+            Output::ConfigMap => Some(RuleLocation {
+                group_prefix: String::new(),
+                file_suffix: Some(format!("/{0}", rule_file_key(&namespace, &name))),
+            }),
+            // The VictoriaMetrics operator names rule files after the VMRule
+            Output::VMRule => Some(RuleLocation {
+                group_prefix: String::new(),
+                file_suffix: Some(format!("/{namespace}-{name}.yaml")),
+            }),
+            Output::AggregatedConfigMap { .. } | Output::Ruler(_) => Some(RuleLocation {
+                group_prefix: group_prefix(&namespace, &name),
+                file_suffix: None,
+            }),
+            Output::Grafana(_) => None,
+        })
+    }
+
+    /// Removes the rules written for a deleted [`ServiceAlert`]. Kubernetes
+    /// objects owned by the `ServiceAlert` alone are garbage collected, so only
+    /// shared objects and the ruler need cleaning up by hand.
+    pub async fn delete(
+        &self,
+        client: &Client,
+        service_alert: &ServiceAlert,
+    ) -> Result<(), OutputError> {
+        match self {
+            Output::ConfigMap | Output::VMRule | Output::Grafana(_) => Ok(()),
+            Output::AggregatedConfigMap { shards } => {
+                aggregated::delete(client, service_alert, *shards).await
+            }
+            Output::Ruler(ruler) => {
+                let prefix = group_prefix(&namespace(service_alert)?, &service_alert.name_any());
+                Ok(ruler.delete(&prefix).await?)
+            }
+        }
+    }
+}
+
+/// Rule groups from every `ServiceAlert` share a single namespace in rulers and
+/// Grafana folders, so group names are prefixed to keep them unique. Kubernetes
+/// names cannot contain colons, so no prefix can be the start of another.
+pub fn group_prefix(namespace: &str, name: &str) -> String {
+    format!("{namespace}:{name}:")
+}
+
+/// Annotation holding a hash of the content Cactuar generated for an object.
+pub const CONTENT_HASH_ANNOTATION: &str = "cactuar.rs/content-hash";
+
+/// Hashes generated content, for use as the [`CONTENT_HASH_ANNOTATION`].
+pub fn content_hash<T: Serialize>(content: &T) -> Result<String, OutputError> {
+    let json = serde_json::to_vec(content).map_err(color_eyre::Report::from)?;
+    let digest = Sha256::digest(json);
+
+    Ok(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// Labels of a [`ServiceAlert`] that are passed on to the objects generated for
+/// it, so that objects generated for a `ClusterServiceAlert` can be traced
+/// back to the policy.
+pub fn inherited_labels(service_alert: &ServiceAlert) -> BTreeMap<String, String> {
+    service_alert
+        .labels()
+        .iter()
+        .filter(|(key, _)| key.as_str() == CLUSTER_SERVICE_ALERT_LABEL)
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Server-side applies `object`, unless the existing object already carries the
+/// same [`CONTENT_HASH_ANNOTATION`], labels and content, in which case there is
+/// nothing to change.
+pub async fn apply_if_changed<K>(api: &Api<K>, object: &K) -> Result<(), OutputError>
+where
+    K: Resource + Clone + Debug + Serialize + DeserializeOwned,
+{
+    apply_if_changed_as(api, object, CONTENT_HASH_ANNOTATION, FINALIZER_NAME).await
+}
+
+/// Like [`apply_if_changed`], for objects written to by several field
+/// managers, each of which keeps the hash of its own content under
+/// `hash_annotation`.
+pub async fn apply_if_changed_as<K>(
+    api: &Api<K>,
+    object: &K,
+    hash_annotation: &str,
+    field_manager: &str,
+) -> Result<(), OutputError>
+where
+    K: Resource + Clone + Debug + Serialize + DeserializeOwned,
+{
+    let name = object
+        .meta()
+        .name
+        .as_deref()
+        .ok_or(OutputError::MissingObjectKey("name"))?;
+
+    if let Some(existing) = api.get_opt(name).await? {
+        if is_unchanged(&existing, object, hash_annotation)? {
+            tracing::debug!("Content unchanged, skipping patch");
+            return Ok(());
+        }
+    }
+
+    api.patch(
+        name,
+        &PatchParams::apply(field_manager),
+        &Patch::Apply(object),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Whether `existing` already holds what applying `object` would write. The
+/// hash annotation alone would leave hand edits in place until the generated
+/// content changes, so the content itself is compared too.
+pub(crate) fn is_unchanged<K>(
+    existing: &K,
+    object: &K,
+    hash_annotation: &str,
+) -> Result<bool, OutputError>
+where
+    K: Resource + Serialize,
+{
+    let hash = object.annotations().get(hash_annotation);
+    if hash.is_none() || existing.annotations().get(hash_annotation) != hash {
+        return Ok(false);
+    }
+
+    let labelled = object
+        .labels()
+        .iter()
+        .all(|(key, value)| existing.labels().get(key) == Some(value));
+
+    Ok(labelled && contains(&content(existing)?, &content(object)?))
+}
+
+/// Everything but the metadata and status of an object.
+fn content<K: Serialize>(object: &K) -> Result<Value, OutputError> {
+    let mut value = serde_json::to_value(object).map_err(color_eyre::Report::from)?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("metadata");
+        fields.remove("status");
+    }
+
+    Ok(value)
+}
+
+/// Whether `existing` holds every field of `desired`. Fields set by other
+/// field managers, e.g. other `ServiceAlerts` in an aggregated `ConfigMap`,
+/// or by Kubernetes defaulting, are not ours to compare.
+fn contains(existing: &Value, desired: &Value) -> bool {
+    match (existing, desired) {
+        (Value::Object(existing), Value::Object(desired)) => desired.iter().all(|(key, value)| {
+            existing
+                .get(key)
+                .is_some_and(|existing| contains(existing, value))
+        }),
+        _ => existing == desired,
+    }
+}
+
+/// Key of the rule file generated for a `ServiceAlert`, in outputs that store
+/// rule files from many `ServiceAlert`s side by side. Kubernetes names cannot
+/// contain underscores, so keys from different objects never collide.
+pub fn rule_file_key(namespace: &str, name: &str) -> String {
+    format!("{namespace}_{name}.yaml")
+}
+
+fn namespace(service_alert: &ServiceAlert) -> Result<String, OutputError> {
+    service_alert
+        .namespace()
+        .ok_or(OutputError::MissingObjectKey("namespace"))
+}
+
+#[cfg(test)]
+mod tests;
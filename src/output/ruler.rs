@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use hyper::{
+    body, client::HttpConnector, header::CONTENT_TYPE, Body, Client, Method, Request, StatusCode,
+};
+use hyper_openssl::HttpsConnector;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use thiserror::Error;
+
+use crate::{
+    config::Ruler,
+    crd::PromDuration,
+    prometheus::alert::{AlertGroup, PromAlerts},
+};
+
+/// Header used by Mimir and Cortex to select the tenant a request applies to.
+const TENANT_HEADER: &str = "X-Scope-OrgID";
+
+#[derive(Debug, Error)]
+pub enum RulerError {
+    #[error("Ruler URL is not configured")]
+    MissingUrl,
+    #[error("Failed to set up TLS for ruler client: {0}")]
+    Tls(#[from] openssl::error::ErrorStack),
+    #[error("Failed to build ruler request: {0}")]
+    Request(#[from] hyper::http::Error),
+    #[error("Ruler request failed: {0}")]
+    Http(#[from] hyper::Error),
+    #[error("Ruler responded with {status}: {body}")]
+    Status { status: StatusCode, body: String },
+    #[error("Ruler request timed out after {0}")]
+    Timeout(PromDuration),
+    #[error("Failed to (de)serialise rule groups: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+}
+
+/// Client for the rules API of a Mimir or Cortex ruler.
+///
+/// All rule groups are stored under a single ruler namespace, so each
+/// `ServiceAlert` prefixes its groups with a unique identifier, see
+/// [`RulerClient::sync`].
+#[derive(Clone, Debug)]
+pub struct RulerClient {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: String,
+    tenant: Option<String>,
+    namespace: String,
+    timeout: PromDuration,
+}
+
+impl RulerClient {
+    /// Creates a new [`RulerClient`] from the `ruler` section of the Cactuar
+    /// config. Returns [`RulerError::MissingUrl`] if no URL is configured.
+    pub fn new(config: &Ruler) -> Result<Self, RulerError> {
+        let url = config.url.as_ref().ok_or(RulerError::MissingUrl)?;
+
+        Ok(Self {
+            client: Client::builder().build(HttpsConnector::new()?),
+            url: url.trim_end_matches('/').to_string(),
+            tenant: config.tenant.clone(),
+            namespace: config.namespace.clone(),
+            timeout: config.timeout,
+        })
+    }
+
+    /// Ruler namespace that all rule groups are stored under.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Makes the ruler hold exactly the groups in `alerts` for the given
+    /// `prefix`. Groups are renamed to `<prefix><group name>`, groups that have
+    /// drifted from the desired state are replaced, and groups with the prefix
+    /// that are no longer generated are deleted.
+    pub async fn sync(&self, prefix: &str, alerts: &PromAlerts) -> Result<(), RulerError> {
+        let mut existing = self.owned_groups(prefix).await?;
+
+        for group in &alerts.groups {
+            let desired = AlertGroup {
+                name: format!("{prefix}{0}", group.name),
+                ..group.clone()
+            };
+
+            if existing.remove(&desired.name).as_ref() != Some(&desired) {
+                tracing::debug!(group = %desired.name, "Setting ruler rule group");
+                self.set_group(&desired).await?;
+            }
+        }
+
+        for name in existing.keys() {
+            tracing::debug!(group = %name, "Deleting stale ruler rule group");
+            self.delete_group(name).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes every rule group with the given `prefix`.
+    pub async fn delete(&self, prefix: &str) -> Result<(), RulerError> {
+        for name in self.owned_groups(prefix).await?.keys() {
+            self.delete_group(name).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the rule groups in the ruler namespace that start with `prefix`,
+    /// keyed by name.
+    async fn owned_groups(&self, prefix: &str) -> Result<BTreeMap<String, AlertGroup>, RulerError> {
+        let request = self
+            .request(Method::GET, self.namespace_url())
+            .body(Body::empty())?;
+        let body = match self.send(request).await {
+            Ok(body) => body,
+            // The ruler responds with a 404 if the namespace has no rule groups.
+            Err(RulerError::Status {
+                status: StatusCode::NOT_FOUND,
+                ..
+            }) => return Ok(BTreeMap::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut namespaces: BTreeMap<String, Vec<AlertGroup>> = serde_yaml::from_slice(&body)?;
+        Ok(namespaces
+            .remove(&self.namespace)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|group| group.name.starts_with(prefix))
+            .map(|group| (group.name.clone(), group))
+            .collect())
+    }
+
+    async fn set_group(&self, group: &AlertGroup) -> Result<(), RulerError> {
+        let request = self
+            .request(Method::POST, self.namespace_url())
+            .header(CONTENT_TYPE, "application/yaml")
+            .body(Body::from(serde_yaml::to_string(group)?))?;
+
+        self.send(request).await?;
+        Ok(())
+    }
+
+    async fn delete_group(&self, name: &str) -> Result<(), RulerError> {
+        let url = format!(
+            "{0}/{1}",
+            self.namespace_url(),
+            utf8_percent_encode(name, NON_ALPHANUMERIC)
+        );
+        let request = self.request(Method::DELETE, url).body(Body::empty())?;
+
+        self.send(request).await?;
+        Ok(())
+    }
+
+    /// Sends `request` and reads the response body, giving up after the
+    /// configured timeout.
+    async fn send(&self, request: Request<Body>) -> Result<body::Bytes, RulerError> {
+        let response = async { success_body(self.client.request(request).await?).await };
+
+        tokio::time::timeout(self.timeout.as_duration(), response)
+            .await
+            .map_err(|_| RulerError::Timeout(self.timeout))?
+    }
+
+    fn namespace_url(&self) -> String {
+        format!(
+            "{0}/config/v1/rules/{1}",
+            self.url,
+            utf8_percent_encode(&self.namespace, NON_ALPHANUMERIC)
+        )
+    }
+
+    fn request(&self, method: Method, url: String) -> hyper::http::request::Builder {
+        let builder = Request::builder().method(method).uri(url);
+        match &self.tenant {
+            Some(tenant) => builder.header(TENANT_HEADER, tenant),
+            None => builder,
+        }
+    }
+}
+
+/// Reads the response body, turning any non-2xx response into an error.
+async fn success_body(response: hyper::Response<Body>) -> Result<body::Bytes, RulerError> {
+    let status = response.status();
+    let body = body::to_bytes(response.into_body()).await?;
+
+    if !status.is_success() {
+        return Err(RulerError::Status {
+            status,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        });
+    }
+
+    Ok(body)
+}
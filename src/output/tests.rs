@@ -0,0 +1,760 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    routing, Router,
+};
+use color_eyre::Result;
+use kube::ResourceExt;
+use pretty_assertions::assert_eq;
+
+use crate::{
+    config::{CactuarConfig, OutputBackend, Ruler},
+    crd::{
+        Alerts, CommonLabels, ServiceAlert, ServiceAlertSpec, CLUSTER_SERVICE_ALERT_LABEL,
+        CLUSTER_SERVICE_ALERT_RULE_LABEL,
+    },
+    output::{
+        aggregated,
+        config_map::config_map,
+        grafana_alerting, is_unchanged,
+        ruler::{RulerClient, RulerError},
+        vm_rule::vm_rule,
+        Output, CONTENT_HASH_ANNOTATION,
+    },
+    prometheus::{
+        alert::{AlertGroup, AlertRules, Annotations, Labels, PromAlerts, PrometheusSeverity},
+        grafana_alerts::GrafanaProvisioning,
+    },
+};
+
+/// In-memory stand-in for the Mimir ruler API, storing rule groups by ruler
+/// namespace and group name.
+#[derive(Clone, Default)]
+struct StandIn {
+    namespaces: Arc<Mutex<BTreeMap<String, BTreeMap<String, AlertGroup>>>>,
+    tenants: Arc<Mutex<Vec<String>>>,
+}
+
+impl StandIn {
+    fn record_tenant(&self, headers: &HeaderMap) {
+        if let Some(tenant) = headers.get("X-Scope-OrgID") {
+            let tenant = tenant.to_str().unwrap_or_default().to_string();
+            self.tenants.lock().unwrap().push(tenant);
+        }
+    }
+
+    fn group_names(&self, namespace: &str) -> Vec<String> {
+        self.namespaces
+            .lock()
+            .unwrap()
+            .get(namespace)
+            .map(|groups| groups.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+async fn list_groups(
+    State(stand_in): State<StandIn>,
+    headers: HeaderMap,
+    Path(namespace): Path<String>,
+) -> Result<String, StatusCode> {
+    stand_in.record_tenant(&headers);
+
+    let namespaces = stand_in.namespaces.lock().unwrap();
+    let groups = namespaces
+        .get(&namespace)
+        .filter(|groups| !groups.is_empty())
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let response = BTreeMap::from([(namespace, groups.values().collect::<Vec<_>>())]);
+    serde_yaml::to_string(&response).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn set_group(
+    State(stand_in): State<StandIn>,
+    headers: HeaderMap,
+    Path(namespace): Path<String>,
+    body: String,
+) -> StatusCode {
+    stand_in.record_tenant(&headers);
+
+    match serde_yaml::from_str::<AlertGroup>(&body) {
+        Ok(group) => {
+            let mut namespaces = stand_in.namespaces.lock().unwrap();
+            namespaces
+                .entry(namespace)
+                .or_default()
+                .insert(group.name.clone(), group);
+            StatusCode::ACCEPTED
+        }
+        Err(_) => StatusCode::BAD_REQUEST,
+    }
+}
+
+async fn delete_group(
+    State(stand_in): State<StandIn>,
+    headers: HeaderMap,
+    Path((namespace, group)): Path<(String, String)>,
+) -> StatusCode {
+    stand_in.record_tenant(&headers);
+
+    let mut namespaces = stand_in.namespaces.lock().unwrap();
+    match namespaces
+        .get_mut(&namespace)
+        .and_then(|groups| groups.remove(&group))
+    {
+        Some(_) => StatusCode::ACCEPTED,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+/// Serves the stand-in on a random local port, returning a client for it.
+fn serve(stand_in: StandIn) -> Result<RulerClient> {
+    let router = Router::new()
+        .route(
+            "/prometheus/config/v1/rules/:namespace",
+            routing::get(list_groups).post(set_group),
+        )
+        .route(
+            "/prometheus/config/v1/rules/:namespace/:group",
+            routing::delete(delete_group),
+        )
+        .with_state(stand_in);
+
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(router.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    Ok(RulerClient::new(&Ruler {
+        url: Some(format!("http://{addr}/prometheus")),
+        tenant: Some(String::from("platform")),
+        namespace: String::from("cactuar"),
+        ..Ruler::default()
+    })?)
+}
+
+fn group(name: &str, threshold: u32) -> AlertGroup {
+    AlertGroup {
+        name: name.into(),
+        interval: None,
+        limit: None,
+        rules: vec![AlertRules {
+            alert: format!("{name} rule"),
+            expr: format!("vector(1) > {threshold}"),
+            for_: "5m".into(),
+            keep_firing_for: None,
+            labels: Labels {
+                severity: PrometheusSeverity::Warning,
+                source: "cloud".into(),
+                owner: "foo".into(),
+                extra: BTreeMap::new(),
+            },
+            annotations: Annotations {
+                summary: "summary".into(),
+                description: "description".into(),
+                extra: BTreeMap::new(),
+            },
+        }],
+    }
+}
+
+#[tokio::test]
+async fn test_ruler_sync_creates_groups_with_prefix() -> Result<()> {
+    let stand_in = StandIn::default();
+    let ruler = serve(stand_in.clone())?;
+
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1), group("HTTP Alerts", 1)],
+    };
+    ruler.sync("services:example:", &alerts).await?;
+
+    assert_eq!(
+        stand_in.group_names("cactuar"),
+        vec![
+            String::from("services:example:HTTP Alerts"),
+            String::from("services:example:Replica Alerts"),
+        ]
+    );
+    assert!(stand_in
+        .tenants
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|t| t == "platform"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ruler_sync_reconciles_drift() -> Result<()> {
+    let stand_in = StandIn::default();
+    let ruler = serve(stand_in.clone())?;
+
+    ruler
+        .sync(
+            "services:example:",
+            &PromAlerts {
+                groups: vec![group("Replica Alerts", 1), group("HTTP Alerts", 1)],
+            },
+        )
+        .await?;
+    ruler
+        .sync(
+            "services:other:",
+            &PromAlerts {
+                groups: vec![group("Replica Alerts", 1)],
+            },
+        )
+        .await?;
+
+    // Someone edits a group by hand, and the HTTP alerts are removed from the
+    // ServiceAlert.
+    stand_in
+        .namespaces
+        .lock()
+        .unwrap()
+        .get_mut("cactuar")
+        .unwrap()
+        .insert(
+            "services:example:Replica Alerts".into(),
+            AlertGroup {
+                name: "services:example:Replica Alerts".into(),
+                ..group("Replica Alerts", 99)
+            },
+        );
+
+    ruler
+        .sync(
+            "services:example:",
+            &PromAlerts {
+                groups: vec![group("Replica Alerts", 1)],
+            },
+        )
+        .await?;
+
+    let namespaces = stand_in.namespaces.lock().unwrap();
+    let groups = &namespaces["cactuar"];
+    assert_eq!(
+        groups.keys().cloned().collect::<Vec<_>>(),
+        vec![
+            String::from("services:example:Replica Alerts"),
+            String::from("services:other:Replica Alerts"),
+        ]
+    );
+    assert_eq!(
+        groups["services:example:Replica Alerts"].rules,
+        group("Replica Alerts", 1).rules
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ruler_delete_only_removes_prefixed_groups() -> Result<()> {
+    let stand_in = StandIn::default();
+    let ruler = serve(stand_in.clone())?;
+
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    };
+    ruler.sync("services:example:", &alerts).await?;
+    ruler.sync("services:other:", &alerts).await?;
+
+    ruler.delete("services:example:").await?;
+    assert_eq!(
+        stand_in.group_names("cactuar"),
+        vec![String::from("services:other:Replica Alerts")]
+    );
+
+    // Deleting again finds nothing to delete, rather than failing.
+    ruler.delete("services:example:").await?;
+    ruler.delete("services:other:").await?;
+    assert!(stand_in.group_names("cactuar").is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ruler_gives_up_on_slow_responses() -> Result<()> {
+    let router = Router::new().route(
+        "/prometheus/config/v1/rules/:namespace",
+        routing::get(|| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            StatusCode::NOT_FOUND
+        }),
+    );
+    let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(router.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    let ruler = RulerClient::new(&Ruler {
+        url: Some(format!("http://{addr}/prometheus")),
+        timeout: Duration::from_millis(100).into(),
+        ..Ruler::default()
+    })?;
+
+    assert!(matches!(
+        ruler.delete("services:example:").await,
+        Err(RulerError::Timeout(_))
+    ));
+
+    Ok(())
+}
+
+fn test_service_alert() -> ServiceAlert {
+    let mut service_alert = ServiceAlert::new(
+        "example-service-alert",
+        ServiceAlertSpec {
+            common_labels: CommonLabels {
+                owner: "foo".into(),
+                origin: "cloud".into(),
+                extra: BTreeMap::new(),
+            },
+            deployment_name: "best-service-eu".into(),
+            workload_ref: None,
+            workload_selector: None,
+            maintenance: None,
+            profile: None,
+            template: None,
+            annotations: BTreeMap::new(),
+            interval: None,
+            limit: None,
+            alerts: Alerts {
+                grpc: None,
+                rest: None,
+                replica: None,
+                custom: None,
+            },
+        },
+    );
+    service_alert.metadata.namespace = Some("services".into());
+    service_alert.metadata.uid = Some("a8f7c3f2-1d2e-4b7a-9c1f-6f1e2d3c4b5a".into());
+
+    service_alert
+}
+
+#[test]
+fn test_vm_rule_is_owned_by_service_alert() -> Result<()> {
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    };
+    let rule = vm_rule(&test_service_alert(), alerts.clone())?;
+
+    assert_eq!(rule.metadata.name.as_deref(), Some("example-service-alert"));
+    assert_eq!(rule.metadata.namespace.as_deref(), Some("services"));
+    assert_eq!(rule.spec.groups, alerts.groups);
+
+    let owner = &rule.metadata.owner_references.unwrap()[0];
+    assert_eq!(owner.kind, "ServiceAlert");
+    assert_eq!(owner.controller, Some(true));
+
+    Ok(())
+}
+
+#[test]
+fn test_outputs_traced_to_cluster_service_alert() -> Result<()> {
+    let mut service_alert = test_service_alert();
+    service_alert.metadata.labels = Some(BTreeMap::from([
+        (
+            CLUSTER_SERVICE_ALERT_LABEL.into(),
+            "platform-baseline".into(),
+        ),
+        ("team".into(), "payments".into()),
+    ]));
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    }
+    .with_policy_label(&service_alert);
+    let traced = |labels: Option<BTreeMap<String, String>>| {
+        labels.unwrap_or_default()[CLUSTER_SERVICE_ALERT_LABEL] == "platform-baseline"
+    };
+
+    // Rules carry the policy too, as ruler groups have no labels of their own
+    let rule = &alerts.groups[0].rules[0];
+    assert_eq!(
+        rule.labels.extra[CLUSTER_SERVICE_ALERT_RULE_LABEL],
+        "platform-baseline"
+    );
+
+    let cm = config_map(&service_alert, alerts.clone())?;
+    assert!(traced(cm.metadata.labels.clone()));
+    assert_eq!(cm.metadata.labels.unwrap()["rules"], "prom-rule");
+    assert!(traced(
+        vm_rule(&service_alert, alerts.clone())?.metadata.labels
+    ));
+    let options = CactuarConfig::default().grafana;
+    let grafana = grafana_alerting::config_map(&service_alert, alerts, &options)?;
+    assert!(traced(grafana.metadata.labels.clone()));
+    // Other labels of the ServiceAlert are not passed on
+    assert!(!grafana.metadata.labels.unwrap().contains_key("team"));
+
+    // ServiceAlerts written by hand are left as they were
+    let plain = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    };
+    assert_eq!(
+        plain.clone().with_policy_label(&test_service_alert()),
+        plain
+    );
+    assert_eq!(vm_rule(&test_service_alert(), plain)?.metadata.labels, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_config_map_and_vm_rule_render_same_groups() -> Result<()> {
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    };
+
+    let cm = config_map(&test_service_alert(), alerts.clone())?;
+    let rule = vm_rule(&test_service_alert(), alerts)?;
+
+    let rendered: PromAlerts =
+        serde_yaml::from_str(&cm.data.unwrap()["services_example-service-alert.yaml"])?;
+    assert_eq!(rendered.groups, rule.spec.groups);
+
+    Ok(())
+}
+
+#[test]
+fn test_rule_file_key_unique_per_service_alert() -> Result<()> {
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    };
+
+    // Same owner, different ServiceAlerts
+    let mut other = test_service_alert();
+    other.metadata.name = Some("other-service-alert".into());
+
+    let first = config_map(&test_service_alert(), alerts.clone())?
+        .data
+        .unwrap();
+    let second = config_map(&other, alerts)?.data.unwrap();
+    assert_eq!(
+        first.keys().chain(second.keys()).collect::<Vec<_>>(),
+        vec![
+            "services_example-service-alert.yaml",
+            "services_other-service-alert.yaml"
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_empty_spec_renders_empty_rule_file() -> Result<()> {
+    let service_alert = test_service_alert();
+    let alerts = PromAlerts::try_from(service_alert.spec.clone())?;
+    assert!(alerts.groups.is_empty());
+
+    let cm = config_map(&service_alert, alerts.clone())?;
+    let rendered: PromAlerts =
+        serde_yaml::from_str(&cm.data.unwrap()["services_example-service-alert.yaml"])?;
+    assert_eq!(rendered, alerts);
+
+    assert!(vm_rule(&service_alert, alerts)?.spec.groups.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_content_hash_follows_generated_content() -> Result<()> {
+    let hash = |threshold: u32| -> Result<String> {
+        let alerts = PromAlerts {
+            groups: vec![group("Replica Alerts", threshold)],
+        };
+        let cm = config_map(&test_service_alert(), alerts.clone())?;
+        let rule = vm_rule(&test_service_alert(), alerts)?;
+
+        let cm_hash = &cm.metadata.annotations.unwrap()[CONTENT_HASH_ANNOTATION];
+        let rule_hash = &rule.metadata.annotations.unwrap()[CONTENT_HASH_ANNOTATION];
+        assert_eq!(cm_hash.len(), 64);
+        assert_eq!(rule_hash.len(), 64);
+
+        Ok(cm_hash.clone())
+    };
+
+    assert_eq!(hash(1)?, hash(1)?);
+    assert_ne!(hash(1)?, hash(2)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_output_selected_from_config() -> Result<()> {
+    let mut config = CactuarConfig::default();
+    assert!(matches!(Output::new(&config)?, Output::ConfigMap));
+
+    config.output.backend = OutputBackend::VMRule;
+    assert!(matches!(Output::new(&config)?, Output::VMRule));
+
+    // The ruler backend can't do anything without a URL to push rules to
+    config.output.backend = OutputBackend::Ruler;
+    assert!(Output::new(&config).is_err());
+
+    config.ruler.url = Some("http://mimir/prometheus".into());
+    assert!(matches!(Output::new(&config)?, Output::Ruler(_)));
+
+    config.output.backend = OutputBackend::Grafana;
+    assert!(matches!(Output::new(&config)?, Output::Grafana(_)));
+
+    // Only ConfigMaps can be shared between ServiceAlerts
+    config.output.aggregate = true;
+    assert!(Output::new(&config).is_err());
+
+    config.output.backend = OutputBackend::ConfigMap;
+    config.output.shards = 0;
+    assert!(matches!(
+        Output::new(&config)?,
+        Output::AggregatedConfigMap { shards: 1 }
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_grafana_alerts_provisioned_per_service_alert() -> Result<()> {
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1), group("HTTP Alerts", 2)],
+    };
+    let options = CactuarConfig::default().grafana;
+    let cm = grafana_alerting::config_map(&test_service_alert(), alerts, &options)?;
+
+    assert_eq!(
+        cm.metadata.name.as_deref(),
+        Some("example-service-alert-grafana-alerts")
+    );
+    assert_eq!(
+        cm.metadata.labels,
+        Some(BTreeMap::from([("grafana_alert".into(), "1".into())]))
+    );
+
+    let data = cm.data.unwrap();
+    let provisioning: GrafanaProvisioning =
+        serde_yaml::from_str(&data["services_example-service-alert.yaml"])?;
+    assert_eq!(provisioning.api_version, 1);
+
+    let replica = &provisioning.groups[0];
+    assert_eq!(
+        replica.name,
+        "services:example-service-alert:Replica Alerts"
+    );
+    assert_eq!(replica.folder, "Cactuar");
+    assert_eq!(replica.interval, "1m");
+
+    let rule = &replica.rules[0];
+    assert_eq!(rule.title, "Replica Alerts rule");
+    assert_eq!(rule.for_, "5m");
+    assert_eq!(rule.condition, "B");
+    assert_eq!(rule.data[0].datasource_uid, "prometheus");
+    assert_eq!(rule.data[0].model["expr"], "vector(1) > 1");
+    assert_eq!(rule.labels["severity"], "warning");
+    assert_eq!(rule.labels["owner"], "foo");
+    assert_eq!(rule.annotations["summary"], "summary");
+
+    // UIDs must be stable between reconciliations, unique, and short enough
+    // for Grafana to accept.
+    let uids: Vec<_> = provisioning
+        .groups
+        .iter()
+        .flat_map(|group| group.rules.iter().map(|rule| rule.uid.clone()))
+        .collect();
+    assert!(uids.iter().all(|uid| uid.len() <= 40));
+    assert_ne!(uids[0], uids[1]);
+
+    let again = grafana_alerting::config_map(
+        &test_service_alert(),
+        PromAlerts {
+            groups: vec![group("Replica Alerts", 1)],
+        },
+        &options,
+    )?;
+    let again: GrafanaProvisioning =
+        serde_yaml::from_str(&again.data.unwrap()["services_example-service-alert.yaml"])?;
+    assert_eq!(again.groups[0].rules[0].uid, uids[0]);
+
+    Ok(())
+}
+
+#[test]
+fn test_grafana_rules_keep_extra_annotations_and_keep_firing_for() -> Result<()> {
+    let mut alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    };
+    let rule = &mut alerts.groups[0].rules[0];
+    rule.keep_firing_for = Some("10m".into());
+    rule.annotations
+        .extra
+        .insert("runbook_url".into(), "https://runbooks/replicas".into());
+
+    let options = CactuarConfig::default().grafana;
+    let cm = grafana_alerting::config_map(&test_service_alert(), alerts, &options)?;
+    let rendered = &cm.data.unwrap()["services_example-service-alert.yaml"];
+    assert!(rendered.contains("keepFiringFor: 10m"));
+
+    let provisioning: GrafanaProvisioning = serde_yaml::from_str(rendered)?;
+    let rule = &provisioning.groups[0].rules[0];
+    assert_eq!(rule.keep_firing_for.as_deref(), Some("10m"));
+    assert_eq!(rule.annotations["summary"], "summary");
+    assert_eq!(rule.annotations["runbook_url"], "https://runbooks/replicas");
+
+    Ok(())
+}
+
+#[test]
+fn test_hand_edited_content_is_not_unchanged() -> Result<()> {
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    };
+    let cm = config_map(&test_service_alert(), alerts)?;
+    assert!(is_unchanged(&cm, &cm, CONTENT_HASH_ANNOTATION)?);
+
+    // Someone edits the rules by hand, leaving the hash in place
+    let mut edited = cm.clone();
+    for rules in edited.data.iter_mut().flat_map(|data| data.values_mut()) {
+        *rules = rules.replace("vector(1) > 1", "vector(1) > 99");
+    }
+    assert!(!is_unchanged(&edited, &cm, CONTENT_HASH_ANNOTATION)?);
+
+    // Keys written by others are left alone
+    let mut shared = cm.clone();
+    shared
+        .data
+        .get_or_insert_with(BTreeMap::new)
+        .insert("services_other.yaml".into(), "groups: []".into());
+    assert!(is_unchanged(&shared, &cm, CONTENT_HASH_ANNOTATION)?);
+
+    let mut rehashed = cm.clone();
+    rehashed
+        .annotations_mut()
+        .insert(CONTENT_HASH_ANNOTATION.into(), "stale".into());
+    assert!(!is_unchanged(&rehashed, &cm, CONTENT_HASH_ANNOTATION)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_aggregated_config_map_contributes_prefixed_groups() -> Result<()> {
+    let service_alert = test_service_alert();
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    };
+
+    let cm = aggregated::config_map(&service_alert, Some(alerts), 1)?;
+    assert_eq!(cm.metadata.name.as_deref(), Some("cactuar-rules-0"));
+
+    // The ConfigMap is shared, so no single ServiceAlert may control it
+    let owner = &cm.metadata.owner_references.unwrap()[0];
+    assert_eq!(owner.kind, "ServiceAlert");
+    assert_eq!(owner.controller, None);
+
+    let rendered: PromAlerts =
+        serde_yaml::from_str(&cm.data.unwrap()["services_example-service-alert.yaml"])?;
+    assert_eq!(
+        rendered.groups[0].name,
+        "services:example-service-alert:Replica Alerts"
+    );
+
+    // Every ServiceAlert hashes its own rule file under an annotation of its own
+    let annotation = aggregated::content_hash_annotation(&service_alert);
+    assert!(annotation.starts_with(CONTENT_HASH_ANNOTATION));
+    assert!(annotation.len() - "cactuar.rs/".len() <= 63);
+    assert!(cm.metadata.annotations.unwrap().contains_key(&annotation));
+
+    // Removing the ServiceAlert applies the same object without its rule file
+    let removed = aggregated::config_map(&service_alert, None, 1)?;
+    assert_eq!(removed.metadata.name.as_deref(), Some("cactuar-rules-0"));
+    assert!(removed.metadata.owner_references.is_some());
+    assert!(removed.data.is_none());
+    assert!(removed.metadata.annotations.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_aggregated_shards_are_stable() -> Result<()> {
+    let shard = |name: &str| {
+        let mut service_alert = test_service_alert();
+        service_alert.metadata.name = Some(name.into());
+        aggregated::shard_name(&service_alert, 4)
+    };
+
+    let names: Vec<String> = (0..32).map(|i| shard(&format!("service-{i}"))).collect();
+    assert!(names.iter().all(|name| {
+        [
+            "cactuar-rules-0",
+            "cactuar-rules-1",
+            "cactuar-rules-2",
+            "cactuar-rules-3",
+        ]
+        .contains(&name.as_str())
+    }));
+    assert_eq!(shard("service-0"), names[0]);
+
+    // Not every ServiceAlert ends up in the same shard
+    assert!(names.iter().any(|name| name != &names[0]));
+
+    Ok(())
+}
+
+#[test]
+fn test_aggregated_stale_shards_are_released() -> Result<()> {
+    let service_alert = test_service_alert();
+    let alerts = PromAlerts {
+        groups: vec![group("Replica Alerts", 1)],
+    };
+    let mut stale = aggregated::config_map(&service_alert, Some(alerts), 1)?;
+    stale.metadata.name = Some("cactuar-rules-3".into());
+
+    // Nothing but this ServiceAlert left in it
+    assert_eq!(
+        aggregated::stale_shard(&stale, &service_alert)?,
+        Some(aggregated::StaleShard::Delete)
+    );
+
+    // Shared with another ServiceAlert, so only this one's fields are given up
+    let mut other = test_service_alert();
+    other.metadata.name = Some("other-service-alert".into());
+    other.metadata.uid = Some("0b5e4c1a-7d3f-4e2b-8a6c-9f1d2e3c4b5a".into());
+    let other_cm = aggregated::config_map(
+        &other,
+        Some(PromAlerts {
+            groups: vec![group("Replica Alerts", 2)],
+        }),
+        1,
+    )?;
+    stale.data.as_mut().unwrap().extend(other_cm.data.unwrap());
+    stale
+        .metadata
+        .owner_references
+        .as_mut()
+        .unwrap()
+        .extend(other_cm.metadata.owner_references.unwrap());
+
+    match aggregated::stale_shard(&stale, &service_alert)? {
+        Some(aggregated::StaleShard::Release(release)) => {
+            assert_eq!(release.metadata.name.as_deref(), Some("cactuar-rules-3"));
+            assert!(release.data.is_none());
+            assert!(release.metadata.owner_references.is_none());
+        }
+        action => panic!("expected the shard to be released, got {action:?}"),
+    }
+
+    // Shards the ServiceAlert never wrote to are left alone
+    let mut unrelated = test_service_alert();
+    unrelated.metadata.name = Some("unrelated".into());
+    unrelated.metadata.uid = Some("unrelated-uid".into());
+    assert_eq!(aggregated::stale_shard(&stale, &unrelated)?, None);
+
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::{api::Api, Client, CustomResource, Resource, ResourceExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crd::ServiceAlert,
+    prometheus::alert::{AlertGroup, PromAlerts},
+};
+
+use super::{
+    apply_if_changed, content_hash, inherited_labels, OutputError, CONTENT_HASH_ANNOTATION,
+};
+
+/// Minimal definition of the VictoriaMetrics operator's `VMRule` resource.
+/// Cactuar only ever writes these, and VictoriaMetrics rule groups share their
+/// structure with Prometheus, so [`AlertGroup`] is reused as-is.
+///
+/// The schema is disabled as the CRD is installed by the VictoriaMetrics
+/// operator, not Cactuar.
+#[derive(CustomResource, Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[kube(
+    group = "operator.victoriametrics.com",
+    version = "v1beta1",
+    kind = "VMRule",
+    namespaced,
+    schema = "disabled"
+)]
+pub struct VMRuleSpec {
+    pub groups: Vec<AlertGroup>,
+}
+
+/// Generates the `VMRule` for a [`ServiceAlert`]. The `VMRule` shares its name
+/// with the `ServiceAlert`, which owns it.
+pub fn vm_rule(service_alert: &ServiceAlert, alerts: PromAlerts) -> Result<VMRule, OutputError> {
+    let owner_references = service_alert
+        .controller_owner_ref(&())
+        .ok_or(OutputError::MissingObjectKey("owner_references"))?;
+    let spec = VMRuleSpec {
+        groups: alerts.groups,
+    };
+
+    Ok(VMRule {
+        metadata: ObjectMeta {
+            name: Some(service_alert.name_any()),
+            namespace: service_alert.namespace(),
+            labels: Some(inherited_labels(service_alert)).filter(|labels| !labels.is_empty()),
+            annotations: Some(BTreeMap::from([(
+                CONTENT_HASH_ANNOTATION.into(),
+                content_hash(&spec)?,
+            )])),
+            owner_references: Some(vec![owner_references]),
+            ..ObjectMeta::default()
+        },
+        spec,
+    })
+}
+
+pub async fn apply(
+    client: &Client,
+    service_alert: &ServiceAlert,
+    alerts: PromAlerts,
+) -> Result<(), OutputError> {
+    let namespace = service_alert
+        .namespace()
+        .ok_or(OutputError::MissingObjectKey("namespace"))?;
+    let vm_rule_api: Api<VMRule> = Api::namespaced(client.clone(), &namespace);
+
+    tracing::debug!("Generating VMRule");
+    let rule = vm_rule(service_alert, alerts)?;
+
+    tracing::debug!("Patching VMRule");
+    apply_if_changed(&vm_rule_api, &rule).await
+}
@@ -1,37 +1,59 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 
 use color_eyre::{eyre::eyre, Result};
 use serde::{Deserialize, Serialize};
 
+use kube::ResourceExt;
+
 use crate::{
-    crd::{ReplicaAlert, ServiceAlertSpec},
+    crd::{
+        ReplicaAlert, ServiceAlert, ServiceAlertSpec, CLUSTER_SERVICE_ALERT_LABEL,
+        CLUSTER_SERVICE_ALERT_RULE_LABEL, SERVICE_ALERT_RULE_LABEL,
+    },
     prometheus::{
-        grpc_alerts::grpc_alert_rules, http_alerts::http_rules, replica_alerts::replica_count_rules,
+        custom_alerts::{custom_alert_rules, CUSTOM_GROUP_NAME},
+        grpc_alerts::grpc_alert_rules,
+        http_alerts::http_rules,
+        replica_alerts::replica_count_rules,
     },
 };
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct PromAlerts {
     pub groups: Vec<AlertGroup>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct AlertGroup {
     pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
     pub rules: Vec<AlertRules>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct AlertRules {
     pub alert: String,
     pub expr: String,
     #[serde(rename = "for")]
     pub for_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_firing_for: Option<String>,
     pub labels: Labels,
     pub annotations: Annotations,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+impl AlertGroup {
+    /// Whether the group holds custom alerts, whose expressions are written by
+    /// hand rather than generated, so that later steps leave them as they are.
+    pub fn is_custom(&self) -> bool {
+        self.name == CUSTOM_GROUP_NAME
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum PrometheusSeverity {
     Warning,
@@ -39,36 +61,125 @@ pub enum PrometheusSeverity {
     Page,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Labels {
     pub severity: PrometheusSeverity,
     pub source: String,
     pub owner: String,
+    /// Additional labels, set by the `commonLabels` of the spec, custom alerts
+    /// and the external labels of the cluster.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+impl Labels {
+    /// Labels every rule generated for `spec` carries: the severity, and the
+    /// common labels of the spec, including any extra ones.
+    pub fn for_spec(severity: PrometheusSeverity, spec: &ServiceAlertSpec) -> Self {
+        let extra = spec
+            .common_labels
+            .extra
+            .iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "severity" | "source" | "owner"))
+            .map(|(key, val)| (key.clone(), val.clone()))
+            .collect();
+
+        Labels {
+            severity,
+            source: spec.common_labels.origin.clone(),
+            owner: spec.common_labels.owner.clone(),
+            extra,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Annotations {
     pub summary: String,
     pub description: String,
+    /// Additional annotations from the `annotations` of the spec, e.g. a
+    /// runbook link templated by Prometheus.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, String>,
 }
 
-impl TryFrom<PromAlerts> for BTreeMap<String, String> {
-    type Error = color_eyre::Report;
+impl PromAlerts {
+    /// Labels every rule with the ServiceAlert it was generated for, as
+    /// `namespace/name`. Rule names alone are shared between ServiceAlerts,
+    /// e.g. every gRPC error alert with the same threshold, so this is what
+    /// tells their alerts apart in silences and the status.
+    pub fn with_service_alert_label(self, service_alert: &ServiceAlert) -> Self {
+        let identity = format!(
+            "{0}/{1}",
+            service_alert.namespace().unwrap_or_default(),
+            service_alert.name_any()
+        );
+        self.with_label(SERVICE_ALERT_RULE_LABEL, &identity)
+    }
 
-    fn try_from(value: PromAlerts) -> Result<Self, Self::Error> {
-        // owner should be a unique identifier, so at least for now we can use
-        // it as the key for our `BTreeMap`
-        let identifier = match value.groups.first() {
-            Some(group) => match group.rules.first() {
-                Some(rule) => rule.labels.owner.clone(),
-                None => return Err(eyre!("No rules defined in alert group.")),
-            },
-            None => return Err(eyre!("No alert rule groups defined.")),
-        };
+    /// Labels every rule with the `ClusterServiceAlert` the ServiceAlert was
+    /// generated for, if any, so that alerts and ruler groups can be traced
+    /// back to the policy.
+    pub fn with_policy_label(self, service_alert: &ServiceAlert) -> Self {
+        match service_alert.labels().get(CLUSTER_SERVICE_ALERT_LABEL) {
+            Some(policy) => self.with_label(CLUSTER_SERVICE_ALERT_RULE_LABEL, policy),
+            None => self,
+        }
+    }
 
-        let yaml_string = serde_yaml::to_string(&value)?;
+    fn with_label(mut self, name: &str, value: &str) -> Self {
+        for rule in self
+            .groups
+            .iter_mut()
+            .flat_map(|group| group.rules.iter_mut())
+        {
+            rule.labels.extra.insert(name.into(), value.into());
+        }
 
-        Ok(BTreeMap::from([(identifier, yaml_string)]))
+        self
+    }
+
+    /// Renders the rules of a [`ServiceAlertSpec`] for every workload it
+    /// resolved to. Unless the `workloadSelector` asks for them to be
+    /// aggregated, each workload gets rule groups of its own.
+    ///
+    /// Custom alerts do not depend on the workload, so they are only rendered
+    /// once, into a group of their own after the generated ones.
+    pub fn for_workloads(spec: &ServiceAlertSpec, workloads: &[String]) -> Result<Self> {
+        let per_workload = spec
+            .workload_selector
+            .as_ref()
+            .is_some_and(|selector| !selector.aggregate);
+
+        let mut groups: Vec<AlertGroup> = Vec::new();
+        let mut custom: Option<AlertGroup> = None;
+        for workload in workloads {
+            for mut group in PromAlerts::try_from(spec.for_workload(workload))?.groups {
+                if group.is_custom() {
+                    custom.get_or_insert(group);
+                    continue;
+                }
+                if per_workload {
+                    group.name = format!("{0} ({workload})", group.name);
+                }
+
+                group
+                    .rules
+                    .retain(|rule| !groups.iter().any(|existing| existing.rules.contains(rule)));
+
+                match groups
+                    .iter_mut()
+                    .find(|existing| existing.name == group.name)
+                {
+                    Some(existing) => existing.rules.append(&mut group.rules),
+                    None => groups.push(group),
+                }
+            }
+        }
+        groups.extend(custom);
+        groups.retain(|group| !group.rules.is_empty());
+
+        Ok(PromAlerts { groups })
     }
 }
 
@@ -82,6 +193,18 @@ impl TryFrom<ServiceAlertSpec> for PromAlerts {
     fn try_from(spec: ServiceAlertSpec) -> Result<Self, Self::Error> {
         use crate::prometheus::alert::*;
 
+        // An empty list would render `day_of_week()` guards matching no day
+        // at all, which isn't even valid PromQL.
+        if spec
+            .alerts
+            .schedules()
+            .any(|schedule| schedule.weekdays.as_ref().is_some_and(Vec::is_empty))
+        {
+            return Err(eyre!(
+                "Alert schedules need at least one weekday, leave `weekdays` out to alert on every day."
+            ));
+        }
+
         let mut alerts = PromAlerts { groups: Vec::new() };
 
         if let Some(replica_alerts) = &spec.alerts.replica {
@@ -100,19 +223,42 @@ impl TryFrom<ServiceAlertSpec> for PromAlerts {
                 .for_each(|(key, val)| alerts.groups.push(grpc_alert_rules(key, val, &spec)));
         }
 
+        if let Some(custom_alerts) = &spec.alerts.custom {
+            alerts
+                .groups
+                .push(custom_alert_rules(custom_alerts, &spec)?);
+        }
+
+        // Prometheus accepts a rule file without groups, so a spec without any
+        // alerts still renders, but empty groups would only be noise.
+        alerts.groups.retain(|group| !group.rules.is_empty());
+
+        // Generated summaries and descriptions are specific to each rule, so
+        // they are kept.
+        let annotations = spec
+            .annotations
+            .iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "summary" | "description"));
+        for rule in alerts.groups.iter_mut().flat_map(|group| &mut group.rules) {
+            rule.annotations.extra.extend(
+                annotations
+                    .clone()
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            );
+        }
+
         Ok(alerts)
     }
 }
 
-/// CRD Severities are currently part of a HashMap, so we need to grab them from
-/// that structure. Since we don't need to modify or consume the HashMap, I
+/// CRD Severities are currently part of a BTreeMap, so we need to grab them from
+/// that structure. Since we don't need to modify or consume the BTreeMap, I
 /// borrow it here.
-impl From<&HashMap<String, String>> for PrometheusSeverity {
-    fn from(value: &HashMap<String, String>) -> Self {
-        match value.get("severity").unwrap().as_str() {
-            "warning" => PrometheusSeverity::Warning,
-            "critical" => PrometheusSeverity::Critical,
-            "page" => PrometheusSeverity::Page,
+impl From<&BTreeMap<String, String>> for PrometheusSeverity {
+    fn from(value: &BTreeMap<String, String>) -> Self {
+        match value.get("severity").map(String::as_str) {
+            Some("critical") => PrometheusSeverity::Critical,
+            Some("page") => PrometheusSeverity::Page,
             _ => PrometheusSeverity::Warning,
         }
     }
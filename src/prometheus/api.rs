@@ -0,0 +1,307 @@
+//! # Prometheus API
+//!
+//! Writing rules is not the same as Prometheus evaluating them: a `ConfigMap`
+//! may not have been reloaded yet, or a rule may fail at evaluation time. This
+//! module reads back the rules Prometheus has loaded through its
+//! `/api/v1/rules` endpoint, which Mimir, Cortex and vmalert serve as well.
+//!
+//! The active alerts of a ServiceAlert are read from `/api/v1/alerts`, which
+//! unlike Alertmanager also knows about pending alerts.
+
+use std::collections::BTreeMap;
+
+use hyper::{body, client::HttpConnector, Body, Client, Request, StatusCode};
+use hyper_openssl::HttpsConnector;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    config::Prometheus,
+    crd::{ActiveAlertStatus, ActiveAlertsStatus, PromDuration, ACTIVE_ALERTS_LISTED},
+};
+
+use super::alert::PromAlerts;
+
+#[derive(Debug, Error)]
+pub enum PrometheusError {
+    #[error("Failed to set up TLS for Prometheus client: {0}")]
+    Tls(#[from] openssl::error::ErrorStack),
+    #[error("Failed to build Prometheus request: {0}")]
+    Request(#[from] hyper::http::Error),
+    #[error("Prometheus request failed: {0}")]
+    Http(#[from] hyper::Error),
+    #[error("Prometheus responded with {status}: {body}")]
+    Status { status: StatusCode, body: String },
+    #[error("Prometheus request timed out after {0}")]
+    Timeout(PromDuration),
+    #[error("Failed to deserialise Prometheus response: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Client for the read API of Prometheus, or anything compatible with it.
+#[derive(Clone, Debug)]
+pub struct PrometheusClient {
+    client: Client<HttpsConnector<HttpConnector>>,
+    url: String,
+    timeout: PromDuration,
+}
+
+#[derive(Deserialize, Debug)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Deserialize, Debug)]
+struct RulesData {
+    groups: Vec<LoadedGroup>,
+}
+
+/// A rule group as loaded by Prometheus.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LoadedGroup {
+    pub name: String,
+    /// Rule file the group was loaded from, or the rule namespace for rulers.
+    #[serde(default)]
+    pub file: String,
+    pub rules: Vec<LoadedRule>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadedRule {
+    pub name: String,
+    /// `ok`, `err` or `unknown` if the rule has not been evaluated yet.
+    pub health: String,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AlertsData {
+    alerts: Vec<ActiveAlert>,
+}
+
+/// A pending or firing alert, as reported by Prometheus.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveAlert {
+    pub labels: BTreeMap<String, String>,
+    /// `pending` or `firing`.
+    pub state: String,
+    #[serde(default)]
+    pub active_at: Option<String>,
+}
+
+/// Where the rule groups of a ServiceAlert show up once Prometheus loaded them.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RuleLocation {
+    /// Prepended to group names, for outputs that share a rule namespace
+    /// between ServiceAlerts.
+    pub group_prefix: String,
+    /// End of the path of the rule file the groups are loaded from, for
+    /// outputs where group names alone are not unique between ServiceAlerts.
+    pub file_suffix: Option<String>,
+}
+
+impl RuleLocation {
+    fn contains(&self, name: &str, loaded: &LoadedGroup) -> bool {
+        loaded.name == name
+            && self
+                .file_suffix
+                .as_ref()
+                .is_none_or(|suffix| loaded.file.ends_with(suffix.as_str()))
+    }
+}
+
+/// Outcome of comparing generated rules with the rules Prometheus loaded.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RuleVerification {
+    pub missing_groups: Vec<String>,
+    pub missing_rules: Vec<String>,
+    /// Rules that failed to evaluate, along with their error.
+    pub unhealthy_rules: Vec<(String, String)>,
+}
+
+impl RuleVerification {
+    /// Checks that every group and alert in `generated` is loaded and healthy
+    /// at `location`. Without a file to tell them apart, every loaded group
+    /// with a matching name is searched for the generated rules.
+    pub fn new(generated: &PromAlerts, location: &RuleLocation, loaded: &[LoadedGroup]) -> Self {
+        let mut verification = RuleVerification::default();
+
+        for group in &generated.groups {
+            let name = format!("{0}{1}", location.group_prefix, group.name);
+            let candidates: Vec<_> = loaded
+                .iter()
+                .filter(|loaded| location.contains(&name, loaded))
+                .collect();
+            if candidates.is_empty() {
+                verification.missing_groups.push(name);
+                continue;
+            }
+
+            for rule in &group.rules {
+                let found = candidates
+                    .iter()
+                    .flat_map(|loaded| &loaded.rules)
+                    .find(|loaded| loaded.name == rule.alert);
+                match found {
+                    None => verification.missing_rules.push(rule.alert.clone()),
+                    Some(loaded) if loaded.health == "err" => verification.unhealthy_rules.push((
+                        rule.alert.clone(),
+                        loaded.last_error.clone().unwrap_or_default(),
+                    )),
+                    Some(_) => {}
+                }
+            }
+        }
+
+        verification
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.missing_groups.is_empty()
+            && self.missing_rules.is_empty()
+            && self.unhealthy_rules.is_empty()
+    }
+
+    /// One line summary, for status conditions and events.
+    pub fn summary(&self) -> String {
+        if self.is_ok() {
+            return String::from("All generated rules are loaded and healthy");
+        }
+
+        let mut problems = Vec::new();
+        if !self.missing_groups.is_empty() {
+            problems.push(format!(
+                "groups not loaded: {0}",
+                self.missing_groups.join(", ")
+            ));
+        }
+        if !self.missing_rules.is_empty() {
+            problems.push(format!(
+                "rules not loaded: {0}",
+                self.missing_rules.join(", ")
+            ));
+        }
+        for (rule, error) in &self.unhealthy_rules {
+            problems.push(format!("{rule} is failing: {error}"));
+        }
+
+        problems.join("; ")
+    }
+}
+
+impl PrometheusClient {
+    /// Creates a new [`PrometheusClient`] from the `prometheus` section of the
+    /// Cactuar config, or [`None`] if no URL is configured.
+    pub fn new(config: &Prometheus) -> Result<Option<Self>, PrometheusError> {
+        let Some(url) = &config.url else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            client: Client::builder().build(HttpsConnector::new()?),
+            url: url.trim_end_matches('/').to_string(),
+            timeout: config.timeout,
+        }))
+    }
+
+    /// Returns every alerting rule group Prometheus has loaded.
+    pub async fn rules(&self) -> Result<Vec<LoadedGroup>, PrometheusError> {
+        let response: Response<RulesData> = self.get("api/v1/rules?type=alert").await?;
+        Ok(response.data.groups)
+    }
+
+    /// Returns every pending and firing alert.
+    pub async fn alerts(&self) -> Result<Vec<ActiveAlert>, PrometheusError> {
+        let response: Response<AlertsData> = self.get("api/v1/alerts").await?;
+        Ok(response.data.alerts)
+    }
+
+    /// Gets `path` and deserialises the response body, giving up after the
+    /// configured timeout.
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, PrometheusError> {
+        let request = Request::get(format!("{0}/{path}", self.url)).body(Body::empty())?;
+        let response = async {
+            let response = self.client.request(request).await?;
+
+            let status = response.status();
+            let body = body::to_bytes(response.into_body()).await?;
+            if !status.is_success() {
+                return Err(PrometheusError::Status {
+                    status,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                });
+            }
+
+            Ok(body)
+        };
+
+        let body = tokio::time::timeout(self.timeout.as_duration(), response)
+            .await
+            .map_err(|_| PrometheusError::Timeout(self.timeout))??;
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+/// Summarises the `active` alerts that were generated from `generated`. An
+/// alert belongs to a generated rule if it has the rule's name and carries
+/// every label the rule sets.
+pub fn active_alerts_status(
+    generated: &PromAlerts,
+    active: &[ActiveAlert],
+    changed_at: String,
+) -> ActiveAlertsStatus {
+    let rules: Vec<_> = generated
+        .groups
+        .iter()
+        .flat_map(|group| &group.rules)
+        .collect();
+
+    let mut matched: Vec<&ActiveAlert> = active
+        .iter()
+        .filter(|alert| {
+            rules.iter().any(|rule| {
+                let Ok(serde_json::Value::Object(labels)) = serde_json::to_value(&rule.labels)
+                else {
+                    return false;
+                };
+
+                alert.labels.get("alertname") == Some(&rule.alert)
+                    && labels.iter().all(|(key, value)| {
+                        alert.labels.get(key).map(String::as_str) == value.as_str()
+                    })
+            })
+        })
+        .collect();
+
+    // Firing first, then the alerts that have been active the longest
+    matched.sort_by(|a, b| {
+        (b.state == "firing")
+            .cmp(&(a.state == "firing"))
+            .then_with(|| a.active_at.cmp(&b.active_at))
+    });
+
+    ActiveAlertsStatus {
+        pending: matched
+            .iter()
+            .filter(|alert| alert.state == "pending")
+            .count() as u32,
+        firing: matched
+            .iter()
+            .filter(|alert| alert.state == "firing")
+            .count() as u32,
+        alerts: matched
+            .iter()
+            .take(ACTIVE_ALERTS_LISTED)
+            .map(|alert| ActiveAlertStatus {
+                alert: alert.labels.get("alertname").cloned().unwrap_or_default(),
+                state: alert.state.clone(),
+                severity: alert.labels.get("severity").cloned(),
+                active_since: alert.active_at.clone(),
+            })
+            .collect(),
+        changed_at,
+    }
+}
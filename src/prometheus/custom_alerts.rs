@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use color_eyre::{eyre::eyre, Result};
+
+use crate::crd::{CustomAlert, ServiceAlertSpec};
+
+use super::alert::{AlertGroup, AlertRules, Annotations, Labels, PrometheusSeverity};
+
+/// Name of the group holding the custom alerts of a ServiceAlert. Groups are
+/// told apart by name, see [`AlertGroup::is_custom`].
+pub const CUSTOM_GROUP_NAME: &str = "Custom Alerts";
+
+/// Generates an [`AlertGroup`] for a list of custom alerts. Unlike other alert
+/// types, the PromQL expression is supplied by the user, so the only thing we
+/// add is the common labels from the [`ServiceAlertSpec`].
+pub fn custom_alert_rules(
+    custom_alerts: &[CustomAlert],
+    spec: &ServiceAlertSpec,
+) -> Result<AlertGroup> {
+    let custom_rules = custom_alerts
+        .iter()
+        .map(|custom| custom_rule(custom, spec))
+        .collect::<Result<Vec<AlertRules>>>()?;
+
+    Ok(AlertGroup {
+        name: String::from(CUSTOM_GROUP_NAME),
+        interval: spec.interval.map(|interval| interval.to_string()),
+        limit: spec.limit,
+        rules: custom_rules,
+    })
+}
+
+/// Validates a single [`CustomAlert`] and converts it into [`AlertRules`].
+/// Like generated rules, custom alerts without a `severity` label are warnings.
+///
+/// Any labels besides `severity` are passed through to Prometheus, taking
+/// precedence over the extra `commonLabels` of the spec, but may not override
+/// the common labels that Cactuar sets on every rule.
+fn custom_rule(custom: &CustomAlert, spec: &ServiceAlertSpec) -> Result<AlertRules> {
+    if custom.alert.trim().is_empty() {
+        return Err(eyre!("Custom alert is missing an alert name."));
+    }
+    if custom.expr.trim().is_empty() {
+        return Err(eyre!(
+            "Custom alert `{}` has an empty expression.",
+            custom.alert
+        ));
+    }
+
+    let mut labels = Labels::for_spec(PrometheusSeverity::from(&custom.with_labels), spec);
+    labels.extra.extend(
+        custom
+            .with_labels
+            .iter()
+            .filter(|(key, _)| !matches!(key.as_str(), "severity" | "source" | "owner"))
+            .map(|(key, val)| (key.clone(), val.clone())),
+    );
+
+    Ok(AlertRules {
+        alert: custom.alert.clone(),
+        expr: custom.expr.clone(),
+        for_: custom.for_.to_string(),
+        keep_firing_for: custom
+            .keep_firing_for
+            .map(|keep_firing_for| keep_firing_for.to_string()),
+        labels,
+        annotations: Annotations {
+            summary: custom.annotations.summary.clone(),
+            description: custom.annotations.description.clone(),
+            extra: BTreeMap::new(),
+        },
+    })
+}
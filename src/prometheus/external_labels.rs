@@ -0,0 +1,115 @@
+//! # External labels
+//!
+//! Alerts from several clusters often end up in one Alertmanager, so every
+//! generated rule can be labelled with the cluster it came from, along with any
+//! other external labels from the [`Cluster`] config.
+//!
+//! When one Prometheus, or a Thanos querier, sees series from several clusters,
+//! the same labels also have to be matched by the generated selectors, or one
+//! cluster's rules would alert on another cluster's workloads. Custom alert
+//! expressions are written by hand, so they are left as they are.
+//!
+//! Labels a `ServiceAlert` sets itself, through its `commonLabels` or the
+//! `withLabels` of an alert, take precedence over external labels of the same
+//! name, just as Prometheus never overrides alert labels with its own external
+//! labels.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    config::Cluster,
+    crd::{CLUSTER_SERVICE_ALERT_RULE_LABEL, SERVICE_ALERT_RULE_LABEL},
+};
+
+use super::alert::PromAlerts;
+
+/// Labels that Cactuar sets on every rule itself, and that external labels may
+/// not override.
+pub const RESERVED_LABELS: [&str; 5] = [
+    "severity",
+    "source",
+    "owner",
+    SERVICE_ALERT_RULE_LABEL,
+    CLUSTER_SERVICE_ALERT_RULE_LABEL,
+];
+
+/// Whether `name` is a valid Prometheus label name, i.e. matches
+/// `[a-zA-Z_][a-zA-Z0-9_]*`.
+pub fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl PromAlerts {
+    /// Adds the external labels of `cluster` to every rule, and to the
+    /// selectors of every generated expression if `cluster.selectors` is set.
+    pub fn with_external_labels(mut self, cluster: &Cluster) -> Self {
+        let labels = external_labels(cluster);
+        if labels.is_empty() {
+            return self;
+        }
+
+        for group in &mut self.groups {
+            let custom = group.is_custom();
+            for rule in &mut group.rules {
+                for (key, value) in &labels {
+                    rule.labels
+                        .extra
+                        .entry(key.clone())
+                        .or_insert_with(|| value.clone());
+                }
+
+                if cluster.selectors && !custom {
+                    rule.expr = add_matchers(&rule.expr, &labels);
+                }
+            }
+        }
+
+        self
+    }
+}
+
+/// Collects the cluster name and external labels, leaving out any that would
+/// clash with the labels Cactuar sets itself.
+fn external_labels(cluster: &Cluster) -> BTreeMap<String, String> {
+    let mut labels = cluster.labels.clone();
+    if let Some(name) = &cluster.name {
+        labels.insert(cluster.label.clone(), name.clone());
+    }
+
+    labels.retain(|key, _| !RESERVED_LABELS.contains(&key.as_str()));
+    labels
+}
+
+/// Adds equality matchers for `labels` to every selector in `expr`. Generated
+/// expressions always select series with braces, so every opening brace
+/// starts a list of matchers.
+fn add_matchers(expr: &str, labels: &BTreeMap<String, String>) -> String {
+    let matchers = labels
+        .iter()
+        .map(|(key, value)| format!(r#"{key}="{0}""#, escape_label_value(value)))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut output = String::with_capacity(expr.len());
+    let mut chars = expr.chars().peekable();
+    while let Some(c) = chars.next() {
+        output.push(c);
+        if c == '{' {
+            output.push_str(&matchers);
+            if chars.peek() != Some(&'}') {
+                output.push_str(", ");
+            }
+        }
+    }
+
+    output
+}
+
+/// Escapes a label value for use in a double quoted PromQL string.
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', r#"\""#)
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::GrafanaAlerting;
+
+use super::alert::{AlertRules, PromAlerts};
+
+/// Grafana only supports version 1 of its provisioning file format.
+const PROVISIONING_API_VERSION: u32 = 1;
+
+/// Grafana evaluates rule groups every minute unless told otherwise.
+const DEFAULT_INTERVAL: &str = "1m";
+
+/// Grafana UIDs may be at most 40 characters long.
+const MAX_UID_LENGTH: usize = 40;
+
+/// Range of data, in seconds, Grafana fetches when evaluating a query. The
+/// PromQL expressions carry their own ranges, so this just has to cover them.
+const RELATIVE_TIME_RANGE: u64 = 600;
+
+/// Reference IDs of the two steps making up each Grafana rule: the PromQL
+/// query, and the expression deciding whether it fired.
+const QUERY_REF_ID: &str = "A";
+const CONDITION_REF_ID: &str = "B";
+
+/// Grafana's datasource UID for server side expressions.
+const EXPRESSION_DATASOURCE_UID: &str = "__expr__";
+
+/// Prometheus fires an alert for every series an expression returns, whatever
+/// its value. Grafana only fires for non-zero values, so the condition turns
+/// every returned sample into a one.
+const CONDITION_EXPRESSION: &str = "is_number($A) || is_nan($A) || is_inf($A)";
+
+/// Grafana alert rule provisioning file, as read by Grafana from its
+/// `provisioning/alerting` directory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GrafanaProvisioning {
+    pub api_version: u32,
+    pub groups: Vec<GrafanaRuleGroup>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GrafanaRuleGroup {
+    pub org_id: i64,
+    pub name: String,
+    pub folder: String,
+    pub interval: String,
+    pub rules: Vec<GrafanaRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GrafanaRule {
+    pub uid: String,
+    pub title: String,
+    pub condition: String,
+    pub data: Vec<GrafanaQuery>,
+    pub no_data_state: String,
+    pub exec_err_state: String,
+    #[serde(rename = "for")]
+    pub for_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_firing_for: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GrafanaQuery {
+    pub ref_id: String,
+    pub datasource_uid: String,
+    pub relative_time_range: RelativeTimeRange,
+    pub model: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RelativeTimeRange {
+    pub from: u64,
+    pub to: u64,
+}
+
+impl GrafanaProvisioning {
+    /// Renders [`PromAlerts`] as Grafana-managed alert rules. Group names are
+    /// prefixed with `prefix`, as every `ServiceAlert` shares the same folder.
+    ///
+    /// Grafana has no equivalent of the `limit` of a rule group, so it is
+    /// dropped with a warning.
+    pub fn new(alerts: &PromAlerts, options: &GrafanaAlerting, prefix: &str) -> Self {
+        let groups = alerts
+            .groups
+            .iter()
+            .map(|group| {
+                let name = format!("{prefix}{0}", group.name);
+                if let Some(limit) = group.limit {
+                    tracing::warn!(
+                        group = %name,
+                        limit,
+                        "Grafana-managed rules can't limit their number of alerts, ignoring the limit"
+                    );
+                }
+                let rules = group
+                    .rules
+                    .iter()
+                    .enumerate()
+                    .map(|(i, rule)| grafana_rule(rule, options, &rule_uid(&name, i)))
+                    .collect();
+
+                GrafanaRuleGroup {
+                    org_id: options.org,
+                    folder: options.folder.clone(),
+                    interval: group
+                        .interval
+                        .clone()
+                        .unwrap_or_else(|| String::from(DEFAULT_INTERVAL)),
+                    name,
+                    rules,
+                }
+            })
+            .collect();
+
+        GrafanaProvisioning {
+            api_version: PROVISIONING_API_VERSION,
+            groups,
+        }
+    }
+}
+
+fn grafana_rule(rule: &AlertRules, options: &GrafanaAlerting, uid: &str) -> GrafanaRule {
+    let time_range = || RelativeTimeRange {
+        from: RELATIVE_TIME_RANGE,
+        to: 0,
+    };
+
+    GrafanaRule {
+        uid: uid.to_string(),
+        title: rule.alert.clone(),
+        condition: String::from(CONDITION_REF_ID),
+        data: vec![
+            GrafanaQuery {
+                ref_id: String::from(QUERY_REF_ID),
+                datasource_uid: options.datasource.clone(),
+                relative_time_range: time_range(),
+                model: serde_json::json!({
+                    "refId": QUERY_REF_ID,
+                    "expr": rule.expr,
+                    "instant": true,
+                    "range": false,
+                }),
+            },
+            GrafanaQuery {
+                ref_id: String::from(CONDITION_REF_ID),
+                datasource_uid: String::from(EXPRESSION_DATASOURCE_UID),
+                relative_time_range: time_range(),
+                model: serde_json::json!({
+                    "refId": CONDITION_REF_ID,
+                    "type": "math",
+                    "expression": CONDITION_EXPRESSION,
+                }),
+            },
+        ],
+        // An expression that returns nothing means nothing crossed its
+        // threshold, just like in Prometheus.
+        no_data_state: String::from("OK"),
+        exec_err_state: String::from("Error"),
+        for_: rule.for_.clone(),
+        keep_firing_for: rule.keep_firing_for.clone(),
+        labels: serde_json::to_value(&rule.labels)
+            .and_then(serde_json::from_value)
+            .unwrap_or_default(),
+        // Grafana templates annotations much like Prometheus does, so runbook
+        // links and the like carry over as they are
+        annotations: serde_json::to_value(&rule.annotations)
+            .and_then(serde_json::from_value)
+            .unwrap_or_default(),
+    }
+}
+
+/// Rule UIDs must be stable across reconciliations, and unique within a
+/// Grafana organisation, but may only be 40 characters long. Hashing the group
+/// name and the position of the rule within it satisfies both, as generated
+/// alert names are not always unique within a group.
+fn rule_uid(group: &str, index: usize) -> String {
+    let digest = Sha256::new()
+        .chain_update(group)
+        .chain_update(index.to_be_bytes())
+        .finalize();
+
+    digest
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>()
+        .chars()
+        .take(MAX_UID_LENGTH)
+        .collect()
+}
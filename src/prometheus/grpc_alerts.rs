@@ -1,4 +1,10 @@
-use super::alert::{AlertGroup, AlertRules, Annotations, Labels, PrometheusSeverity};
+use std::collections::BTreeMap;
+
+use super::{
+    alert::{AlertGroup, AlertRules, Annotations, Labels, PrometheusSeverity},
+    network_signals::grpc_signal,
+    schedule::scheduled,
+};
 use crate::crd::{AlertConfig, NetworkAlert, ServiceAlertSpec};
 
 pub fn grpc_alert_rules(
@@ -10,82 +16,55 @@ pub fn grpc_alert_rules(
         .iter()
         .enumerate()
         .map(|(i, conf)| AlertRules {
-            alert: format!("{0} {1} {2}", network_alert, conf.operation, conf.value),
+            alert: grpc_rule_name(network_alert, conf),
             expr: grpc_promql(network_alert, conf, spec),
-            for_: conf.for_.clone(),
-            labels: Labels {
-                severity: PrometheusSeverity::from(&conf.with_labels),
-                source: spec.common_labels.origin.clone(),
-                owner: spec.common_labels.owner.clone(),
-            },
+            for_: conf.for_.to_string(),
+            keep_firing_for: conf
+                .keep_firing_for
+                .map(|keep_firing_for| keep_firing_for.to_string()),
+            labels: Labels::for_spec(PrometheusSeverity::from(&conf.with_labels), spec),
             annotations: Annotations {
                 summary: grpc_summary(network_alert, &alert_configs[i]),
                 description: grpc_description(network_alert, &alert_configs[i]),
+                extra: BTreeMap::new(),
             },
         })
         .collect();
 
     AlertGroup {
-        name: String::from("gRPC Alerts"),
+        name: format!("gRPC {network_alert} Alerts"),
+        interval: spec.interval.map(|interval| interval.to_string()),
+        limit: spec.limit,
         rules: grpc_rules,
     }
 }
 
+/// Returns the name of the gRPC alert rule generated for an [`AlertConfig`].
+pub fn grpc_rule_name(network_alert: &NetworkAlert, alert_config: &AlertConfig) -> String {
+    format!(
+        "{0} {1} {2}",
+        network_alert, alert_config.operation, alert_config.value
+    )
+}
+
 fn grpc_promql(
     network_alert: &NetworkAlert,
     alert_config: &AlertConfig,
-    _spec: &ServiceAlertSpec,
+    spec: &ServiceAlertSpec,
 ) -> String {
-    match network_alert {
-        NetworkAlert::ErrorPercent => {
-            format!("(sum by (destination_workload) (rate(grpc_server_handled_total{{grpc_code=~\"Unknown|ResourceExhausted|Internal|Unavailable|DataLoss|DeadlineExceeded\", destination_workload=\"{0}\"}}[{1}]))/  sum by (destination_workload) (rate(grpc_server_started_total{{destination_workload=\"{0}\"}}[{1}])) * 100 > {2})",
-                _spec.deployment_name,
-                alert_config.for_,
-                alert_config.value
-            )
-        }
-        NetworkAlert::TrafficPerSecond => "this is a placeholder value.".to_string(),
-        NetworkAlert::LatencyMillisecondsP50 => {
-            format!(
-                "histogram_quantile(0.50, istio_request_duration_milliseconds{{destination_workload={0}}}[{1}]) {2} {3}",
-                _spec.deployment_name,
-                alert_config.for_,
-                alert_config.operation,
-                alert_config.value
-            )
-        }
-        NetworkAlert::LatencyMillisecondsP90 => {
-            format!(
-                "histogram_quantile(0.90, istio_request_duration_milliseconds{{destination_workload={0}}}[{1}]) {2} {3}",
-                _spec.deployment_name,
-                alert_config.for_,
-                alert_config.operation,
-                alert_config.value
-            )
-        }
-        NetworkAlert::LatencyMillisecondsP95 => {
-            format!(
-                "histogram_quantile(0.95, istio_request_duration_milliseconds{{destination_workload={0}}}[{1}]) {2} {3}",
-                _spec.deployment_name,
-                alert_config.for_,
-                alert_config.operation,
-                alert_config.value
-            )
-        }
-        NetworkAlert::LatencyMillisecondsP99 => {
-            format!(
-                "histogram_quantile(0.99, istio_request_duration_milliseconds{{destination_workload={0}}}[{1}]) {2} {3}",
-                _spec.deployment_name,
-                alert_config.for_,
-                alert_config.operation,
-                alert_config.value
-            )
-        }
-    }
+    scheduled(
+        format!(
+            "{0} {1} {2}",
+            grpc_signal(network_alert, spec, &alert_config.window().to_string()),
+            alert_config.operation,
+            alert_config.value
+        ),
+        alert_config.schedule.as_ref(),
+    )
 }
 
 fn grpc_summary(network_alert: &NetworkAlert, alert_config: &AlertConfig) -> String {
-    match { network_alert } {
+    match network_alert {
         NetworkAlert::ErrorPercent => format!(
             "error rate {0} {1}% for {2}",
             alert_config.operation, alert_config.value, alert_config.for_
@@ -1,6 +1,12 @@
+use std::collections::BTreeMap;
+
 use crate::crd::{AlertConfig, NetworkAlert, Operation, ServiceAlertSpec};
 
-use super::alert::{AlertGroup, AlertRules, Annotations, Labels, PrometheusSeverity};
+use super::{
+    alert::{AlertGroup, AlertRules, Annotations, Labels, PrometheusSeverity},
+    network_signals::http_signal,
+    schedule::scheduled,
+};
 
 pub fn http_rules(spec: &ServiceAlertSpec) -> AlertGroup {
     let mut rules: Vec<AlertRules> = vec![];
@@ -11,40 +17,70 @@ pub fn http_rules(spec: &ServiceAlertSpec) -> AlertGroup {
             NetworkAlert::TrafficPerSecond => {
                 rules.append(&mut traffic_per_second_alerts(spec, val))
             }
-            NetworkAlert::LatencyMillisecondsP50 => {
-                rules.append(&mut latency_percentile_alerts(spec, 50, val))
-            }
-            NetworkAlert::LatencyMillisecondsP90 => {
-                rules.append(&mut latency_percentile_alerts(spec, 90, val))
-            }
-            NetworkAlert::LatencyMillisecondsP95 => {
-                rules.append(&mut latency_percentile_alerts(spec, 95, val))
-            }
-            NetworkAlert::LatencyMillisecondsP99 => {
-                rules.append(&mut latency_percentile_alerts(spec, 99, val))
+            NetworkAlert::LatencyMillisecondsP50
+            | NetworkAlert::LatencyMillisecondsP90
+            | NetworkAlert::LatencyMillisecondsP95
+            | NetworkAlert::LatencyMillisecondsP99 => {
+                rules.append(&mut latency_percentile_alerts(spec, key, val))
             }
         })
     }
 
     AlertGroup {
         name: String::from("HTTP Alerts"),
+        interval: spec.interval.map(|interval| interval.to_string()),
+        limit: spec.limit,
         rules,
     }
 }
 
+/// Returns the name of the HTTP alert rule generated for the `index`th
+/// [`AlertConfig`] of a [`NetworkAlert`]. Latency percentiles share a name, so
+/// only the index tells their rules apart.
+pub fn http_rule_name(
+    network_alert: &NetworkAlert,
+    spec: &ServiceAlertSpec,
+    index: usize,
+) -> String {
+    let kind = match network_alert {
+        NetworkAlert::ErrorPercent => "HTTPErrorPercentRule",
+        NetworkAlert::TrafficPerSecond => "HTTPTrafficPerSecondRule",
+        NetworkAlert::LatencyMillisecondsP50
+        | NetworkAlert::LatencyMillisecondsP90
+        | NetworkAlert::LatencyMillisecondsP95
+        | NetworkAlert::LatencyMillisecondsP99 => "HTTPLatencyPercentileRule",
+    };
+    format!("{kind}-{0}-{index}", spec.workload_name())
+}
+
+fn http_promql(
+    network_alert: &NetworkAlert,
+    alert_config: &AlertConfig,
+    spec: &ServiceAlertSpec,
+) -> String {
+    scheduled(
+        format!(
+            "{0} {1} {2}",
+            http_signal(network_alert, spec, &alert_config.window().to_string()),
+            alert_config.operation,
+            alert_config.value
+        ),
+        alert_config.schedule.as_ref(),
+    )
+}
+
 fn error_percent_alerts(spec: &ServiceAlertSpec, alert_configs: &[AlertConfig]) -> Vec<AlertRules> {
     alert_configs
         .iter()
         .enumerate()
         .map(|(i, conf)| AlertRules {
-            alert: format!("HTTPErrorPercentRule-{0}-{1}", spec.deployment_name, i),
-            expr: format!(r#"error percent {} {}"#, conf.operation, i),
-            for_: conf.for_.clone(),
-            labels: Labels {
-                severity: PrometheusSeverity::from(&conf.with_labels),
-                source: spec.common_labels.origin.clone(),
-                owner: spec.common_labels.owner.clone(),
-            },
+            alert: http_rule_name(&NetworkAlert::ErrorPercent, spec, i),
+            expr: http_promql(&NetworkAlert::ErrorPercent, conf, spec),
+            for_: conf.for_.to_string(),
+            keep_firing_for: conf
+                .keep_firing_for
+                .map(|keep_firing_for| keep_firing_for.to_string()),
+            labels: Labels::for_spec(PrometheusSeverity::from(&conf.with_labels), spec),
             annotations: error_percent_annotations(conf),
         })
         .collect()
@@ -58,6 +94,7 @@ fn error_percent_annotations(alert_config: &AlertConfig) -> Annotations {
                 "Current error percentage is exactly {}%",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
         Operation::LessThan => Annotations {
             summary: String::from("Request errors percentage is less than alert boundary"),
@@ -65,6 +102,7 @@ fn error_percent_annotations(alert_config: &AlertConfig) -> Annotations {
                 "Current error percentage is {{{{ $value }}}}%, boundary is {}",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
         Operation::MoreThan => Annotations {
             summary: String::from("Request errors percentage is higher than alert boundary"),
@@ -72,33 +110,27 @@ fn error_percent_annotations(alert_config: &AlertConfig) -> Annotations {
                 "Current error percentage is {{{{ $value }}}}%, boundary is {}",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
     }
 }
 
 fn latency_percentile_alerts(
     spec: &ServiceAlertSpec,
-    _percentile: i8,
+    network_alert: &NetworkAlert,
     alert_configs: &[AlertConfig],
 ) -> Vec<AlertRules> {
     alert_configs
         .iter()
         .enumerate()
         .map(|(i, conf)| AlertRules {
-            alert: format!("HTTPLatencyPercentileRule-{0}-{1}", spec.deployment_name, i),
-            expr: format!(
-                "histogram_quantile({3}, istio_requests_total{{destination_workload={0}}}[{1}]) {2} {3}",
-                spec.deployment_name,
-                conf.for_,
-                conf.operation,
-                conf.value
-            ),
-            for_: conf.for_.clone(),
-            labels: Labels {
-                severity: PrometheusSeverity::from(&conf.with_labels),
-                source: spec.common_labels.origin.clone(),
-                owner: spec.common_labels.owner.clone(),
-            },
+            alert: http_rule_name(network_alert, spec, i),
+            expr: http_promql(network_alert, conf, spec),
+            for_: conf.for_.to_string(),
+            keep_firing_for: conf
+                .keep_firing_for
+                .map(|keep_firing_for| keep_firing_for.to_string()),
+            labels: Labels::for_spec(PrometheusSeverity::from(&conf.with_labels), spec),
             annotations: latency_percentile_annotations(conf),
         })
         .collect()
@@ -112,6 +144,7 @@ fn latency_percentile_annotations(alert_config: &AlertConfig) -> Annotations {
                 "Current request latency is exactly {}ms",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
         Operation::LessThan => Annotations {
             summary: String::from("Average request latency is less than alert boundary"),
@@ -119,6 +152,7 @@ fn latency_percentile_annotations(alert_config: &AlertConfig) -> Annotations {
                 "Current request latency is {{{{ $value }}}}ms, boundary is {}ms",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
         Operation::MoreThan => Annotations {
             summary: String::from("Average request latency is higher than alert boundary"),
@@ -126,6 +160,7 @@ fn latency_percentile_annotations(alert_config: &AlertConfig) -> Annotations {
                 "Current request latency is {{{{ $value }}}}ms, boundary is {}ms",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
     }
 }
@@ -138,14 +173,13 @@ fn traffic_per_second_alerts(
         .iter()
         .enumerate()
         .map(|(i, conf)| AlertRules {
-            alert: format!("HTTPTrafficPerSecondRule-{0}-{1}", spec.deployment_name, i),
-            expr: format!(r#"traffic per second {} {}"#, conf.operation, i),
-            for_: conf.for_.clone(),
-            labels: Labels {
-                severity: PrometheusSeverity::from(&conf.with_labels),
-                source: spec.common_labels.origin.clone(),
-                owner: spec.common_labels.owner.clone(),
-            },
+            alert: http_rule_name(&NetworkAlert::TrafficPerSecond, spec, i),
+            expr: http_promql(&NetworkAlert::TrafficPerSecond, conf, spec),
+            for_: conf.for_.to_string(),
+            keep_firing_for: conf
+                .keep_firing_for
+                .map(|keep_firing_for| keep_firing_for.to_string()),
+            labels: Labels::for_spec(PrometheusSeverity::from(&conf.with_labels), spec),
             annotations: traffic_per_second_annotations(conf),
         })
         .collect()
@@ -156,6 +190,7 @@ fn traffic_per_second_annotations(alert_config: &AlertConfig) -> Annotations {
         Operation::EqualTo => Annotations {
             summary: String::from("HTTP requests per second reached alert boundary"),
             description: format!("Requests per second is exactly {}/s", alert_config.value),
+            extra: BTreeMap::new(),
         },
         Operation::LessThan => Annotations {
             summary: String::from("HTTP requests per second is less than alert boundary"),
@@ -163,6 +198,7 @@ fn traffic_per_second_annotations(alert_config: &AlertConfig) -> Annotations {
                 "Requests per second is {{{{ $value }}}}/s, boundary is {}/s",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
         Operation::MoreThan => Annotations {
             summary: String::from("HTTP requests per second is higher than alert boundary"),
@@ -170,6 +206,7 @@ fn traffic_per_second_annotations(alert_config: &AlertConfig) -> Annotations {
                 "Requests per second is {{{{ $value }}}}/s, boundary is {}/s",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
     }
 }
@@ -6,9 +6,16 @@
 //! Prometheus alert that Cactuar can produce as a Kubernetes `ConfigMap`.
 
 pub mod alert;
+pub mod api;
+pub mod custom_alerts;
+pub mod external_labels;
+pub mod grafana_alerts;
 pub mod grpc_alerts;
 pub mod http_alerts;
+pub mod network_signals;
+pub mod promtool;
 pub mod replica_alerts;
+pub mod schedule;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,70 @@
+//! # Network signals
+//!
+//! The PromQL behind HTTP and gRPC alerts, keyed by `destination_workload`.
+//! Alerts compare these signals against their thresholds, and dashboards plot
+//! them.
+//!
+//! These replaced the placeholder expressions HTTP and gRPC alerts used to
+//! render, such as `error percent > 0`, which Prometheus could not load. Rule
+//! files of ServiceAlerts with HTTP or gRPC alerts change accordingly.
+//!
+//! - HTTP error percentages divide the rate of `5xx` responses by the rate of
+//!   all responses in `istio_requests_total`.
+//! - gRPC error percentages divide the rate of calls that ended with a code
+//!   pointing at the server by the rate of started calls, from the
+//!   `grpc_server_*` metrics.
+//! - Traffic is the rate of requests, or of started gRPC calls.
+//! - Latency percentiles are taken from the
+//!   `istio_request_duration_milliseconds` histogram, per protocol.
+
+use crate::crd::{NetworkAlert, ServiceAlertSpec};
+
+/// Returns the PromQL expression for the signal a gRPC alert compares against
+/// its threshold, evaluated over `window`. Dashboards reuse this with
+/// Grafana's `$__rate_interval` as the window.
+pub fn grpc_signal(network_alert: &NetworkAlert, spec: &ServiceAlertSpec, window: &str) -> String {
+    let workload = spec.workload_name();
+    match network_alert {
+        NetworkAlert::ErrorPercent => format!(
+            r#"sum by (destination_workload) (rate(grpc_server_handled_total{{grpc_code=~"Unknown|ResourceExhausted|Internal|Unavailable|DataLoss|DeadlineExceeded", destination_workload="{workload}"}}[{window}])) / sum by (destination_workload) (rate(grpc_server_started_total{{destination_workload="{workload}"}}[{window}])) * 100"#
+        ),
+        NetworkAlert::TrafficPerSecond => format!(
+            r#"sum by (destination_workload) (rate(grpc_server_started_total{{destination_workload="{workload}"}}[{window}]))"#
+        ),
+        NetworkAlert::LatencyMillisecondsP50 => grpc_latency_signal(0.50, workload, window),
+        NetworkAlert::LatencyMillisecondsP90 => grpc_latency_signal(0.90, workload, window),
+        NetworkAlert::LatencyMillisecondsP95 => grpc_latency_signal(0.95, workload, window),
+        NetworkAlert::LatencyMillisecondsP99 => grpc_latency_signal(0.99, workload, window),
+    }
+}
+
+fn grpc_latency_signal(quantile: f32, workload: &str, window: &str) -> String {
+    format!(
+        r#"histogram_quantile({quantile}, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{{request_protocol="grpc", destination_workload="{workload}"}}[{window}])))"#
+    )
+}
+
+/// Returns the PromQL expression for the signal an HTTP alert compares against
+/// its threshold, evaluated over `window`. Dashboards reuse this with
+/// Grafana's `$__rate_interval` as the window.
+pub fn http_signal(network_alert: &NetworkAlert, spec: &ServiceAlertSpec, window: &str) -> String {
+    let workload = spec.workload_name();
+    match network_alert {
+        NetworkAlert::ErrorPercent => format!(
+            r#"sum by (destination_workload) (rate(istio_requests_total{{request_protocol="http", response_code=~"5..", destination_workload="{workload}"}}[{window}])) / sum by (destination_workload) (rate(istio_requests_total{{request_protocol="http", destination_workload="{workload}"}}[{window}])) * 100"#
+        ),
+        NetworkAlert::TrafficPerSecond => format!(
+            r#"sum by (destination_workload) (rate(istio_requests_total{{request_protocol="http", destination_workload="{workload}"}}[{window}]))"#
+        ),
+        NetworkAlert::LatencyMillisecondsP50 => http_latency_signal(0.50, workload, window),
+        NetworkAlert::LatencyMillisecondsP90 => http_latency_signal(0.90, workload, window),
+        NetworkAlert::LatencyMillisecondsP95 => http_latency_signal(0.95, workload, window),
+        NetworkAlert::LatencyMillisecondsP99 => http_latency_signal(0.99, workload, window),
+    }
+}
+
+fn http_latency_signal(quantile: f32, workload: &str, window: &str) -> String {
+    format!(
+        r#"histogram_quantile({quantile}, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{{request_protocol="http", destination_workload="{workload}"}}[{window}])))"#
+    )
+}
@@ -0,0 +1,571 @@
+//! # promtool
+//!
+//! Generates a `promtool test rules` document for a [`ServiceAlertSpec`], so
+//! that CI can prove the generated rules fire when, and only when, their
+//! thresholds are crossed.
+//!
+//! Input series are synthesised so that every signal takes an exact value,
+//! which keeps `EqualTo` alerts testable and lets `$value` be expanded in the
+//! expected annotations:
+//!
+//! - replica counts are a gauge holding the value;
+//! - error percentages split a fixed request rate between failing and
+//!   succeeding requests, so they can only be 0, 50 or 100;
+//! - traffic is a counter increasing by the wanted rate every second;
+//! - latency percentiles come from a histogram whose only observations fall in
+//!   the `+Inf` bucket, for which `histogram_quantile` returns the upper bound
+//!   of the bucket below, the wanted latency.
+//!
+//! Alerts that can't be tested are listed by [`PromToolTests::unsupported`]
+//! rather than skipped silently. Custom alerts are opaque, and promtool
+//! evaluates rules at the Unix epoch, which rules out alerts with a schedule.
+//! Neither can alerts sharing their rule name with another rule, nor alerts no
+//! exact value of their signal crosses, or stays clear of.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use color_eyre::{eyre::eyre, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::crd::{
+    AlertConfig, NetworkAlert, Operation, PromDuration, ReplicaAlert, ServiceAlertSpec,
+};
+
+use super::{
+    alert::{AlertRules, PromAlerts},
+    grpc_alerts::grpc_rule_name,
+    http_alerts::http_rule_name,
+    replica_alerts::{replica_rule_name, replica_series},
+};
+
+/// Resolution of the synthetic input series, and how often promtool evaluates
+/// the rules. Kept short so that short evaluation windows still contain enough
+/// samples for `rate()`.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Extra time allowed on top of `for` and the evaluation window before
+/// checking whether an alert fired.
+const EVALUATION_MARGIN: Duration = Duration::from_secs(60);
+
+/// gRPC code counted as an error by the gRPC error percentage alert, see
+/// [`super::network_signals`].
+const GRPC_ERROR_CODE: &str = "Internal";
+
+/// Requests per sample behind error percentages. Splitting a power of two in
+/// half keeps the ratio of the two rates exact.
+const REQUESTS_PER_SAMPLE: f64 = 64_f64;
+
+/// Error percentages that can be produced exactly, see [`REQUESTS_PER_SAMPLE`].
+const ERROR_PERCENTAGES: [f64; 3] = [0_f64, 50_f64, 100_f64];
+
+/// Go's `%v`, which Prometheus renders `$value` with, switches to exponent
+/// notation outside of this range, while Rust never does.
+const PLAIN_VALUES: std::ops::Range<f64> = 1e-4..1e6;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PromToolTests {
+    pub rule_files: Vec<String>,
+    pub evaluation_interval: String,
+    pub tests: Vec<TestGroup>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TestGroup {
+    pub name: String,
+    pub interval: String,
+    pub input_series: Vec<InputSeries>,
+    pub alert_rule_test: Vec<AlertRuleTest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct InputSeries {
+    pub series: String,
+    pub values: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AlertRuleTest {
+    pub eval_time: String,
+    pub alertname: String,
+    pub exp_alerts: Vec<ExpectedAlert>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ExpectedAlert {
+    pub exp_labels: BTreeMap<String, String>,
+    pub exp_annotations: BTreeMap<String, String>,
+}
+
+/// Whether a test group expects its alert to fire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scenario {
+    Firing,
+    Quiet,
+}
+
+impl Scenario {
+    fn describe(&self) -> &'static str {
+        match self {
+            Scenario::Firing => "fires",
+            Scenario::Quiet => "stays quiet",
+        }
+    }
+
+    fn make(&self) -> &'static str {
+        match self {
+            Scenario::Firing => "fire",
+            Scenario::Quiet => "stay quiet",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Http,
+    Grpc,
+}
+
+impl Protocol {
+    fn request_protocol(&self) -> &'static str {
+        match self {
+            Protocol::Http => "http",
+            Protocol::Grpc => "grpc",
+        }
+    }
+}
+
+/// The signal an alert compares against its threshold, which decides the input
+/// series of its tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    Replicas,
+    ErrorPercent(Protocol),
+    Traffic(Protocol),
+    Latency(Protocol),
+}
+
+impl Signal {
+    fn network(network_alert: &NetworkAlert, protocol: Protocol) -> Self {
+        match network_alert {
+            NetworkAlert::ErrorPercent => Signal::ErrorPercent(protocol),
+            NetworkAlert::TrafficPerSecond => Signal::Traffic(protocol),
+            NetworkAlert::LatencyMillisecondsP50
+            | NetworkAlert::LatencyMillisecondsP90
+            | NetworkAlert::LatencyMillisecondsP95
+            | NetworkAlert::LatencyMillisecondsP99 => Signal::Latency(protocol),
+        }
+    }
+
+    /// Values the signal can be set to exactly. Signals that can take any
+    /// value are set a step away from the threshold, or onto it for `EqualTo`.
+    fn candidates(&self, conf: &AlertConfig) -> Vec<f64> {
+        let threshold = threshold(conf);
+        let candidates = match (self, &conf.operation) {
+            (Signal::ErrorPercent(_), _) => ERROR_PERCENTAGES.to_vec(),
+            (_, Operation::EqualTo) => vec![threshold + 1_f64, threshold - 1_f64, threshold],
+            _ => vec![threshold + 1_f64, threshold - 1_f64],
+        };
+
+        candidates
+            .into_iter()
+            .filter(|value| *value >= 0_f64)
+            .filter(|value| match self {
+                // Counters only take whole increments, so that adding them up
+                // stays exact
+                Signal::Traffic(_) => {
+                    let increase = value * SAMPLE_INTERVAL.as_secs_f64();
+                    increase.fract() == 0_f64 && increase / SAMPLE_INTERVAL.as_secs_f64() == *value
+                }
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Rates are only exact when the evaluation window starts on a sample.
+    fn needs_aligned_window(&self) -> bool {
+        !matches!(self, Signal::Replicas)
+    }
+}
+
+/// Tests generated for a single alert, or the reason there are none.
+struct AlertTests {
+    alert: String,
+    tests: std::result::Result<Vec<TestGroup>, String>,
+}
+
+impl PromToolTests {
+    /// Builds test groups for every supported alert in `spec`. `rule_file` is
+    /// the path promtool should load the rendered [`PromAlerts`] from, relative
+    /// to the test document.
+    pub fn generate(spec: &ServiceAlertSpec, rule_file: impl Into<String>) -> Result<Self> {
+        let tests = alert_tests(spec)?
+            .into_iter()
+            .filter_map(|alert_tests| alert_tests.tests.ok())
+            .flatten()
+            .collect();
+
+        Ok(PromToolTests {
+            rule_files: vec![rule_file.into()],
+            evaluation_interval: PromDuration::from(SAMPLE_INTERVAL).to_string(),
+            tests,
+        })
+    }
+
+    /// Lists the alerts of `spec` that [`PromToolTests::generate`] has no tests
+    /// for, along with the reason.
+    pub fn unsupported(spec: &ServiceAlertSpec) -> Result<Vec<String>> {
+        Ok(alert_tests(spec)?
+            .into_iter()
+            .filter_map(|alert_tests| {
+                let reason = alert_tests.tests.err()?;
+                Some(format!("`{0}`: {reason}", alert_tests.alert))
+            })
+            .collect())
+    }
+}
+
+fn alert_tests(spec: &ServiceAlertSpec) -> Result<Vec<AlertTests>> {
+    let alerts = PromAlerts::try_from(spec.clone())?;
+    let mut rule_names: BTreeMap<&str, usize> = BTreeMap::new();
+    alerts
+        .groups
+        .iter()
+        .flat_map(|group| group.rules.iter())
+        .for_each(|rule| *rule_names.entry(rule.alert.as_str()).or_default() += 1);
+
+    let mut cases: Vec<(String, &AlertConfig, Signal)> = Vec::new();
+
+    if let Some(replica_alerts) = &spec.alerts.replica {
+        for (key, configs) in replica_alerts {
+            match key {
+                ReplicaAlert::Count => configs.iter().enumerate().for_each(|(i, conf)| {
+                    cases.push((replica_rule_name(spec, i), conf, Signal::Replicas))
+                }),
+            }
+        }
+    }
+
+    if let Some(rest_alerts) = &spec.alerts.rest {
+        for (key, configs) in rest_alerts {
+            configs.iter().enumerate().for_each(|(i, conf)| {
+                cases.push((
+                    http_rule_name(key, spec, i),
+                    conf,
+                    Signal::network(key, Protocol::Http),
+                ))
+            });
+        }
+    }
+
+    if let Some(grpc_alerts) = &spec.alerts.grpc {
+        for (key, configs) in grpc_alerts {
+            configs.iter().for_each(|conf| {
+                cases.push((
+                    grpc_rule_name(key, conf),
+                    conf,
+                    Signal::network(key, Protocol::Grpc),
+                ))
+            });
+        }
+    }
+
+    let mut planned = cases
+        .into_iter()
+        .map(|(name, conf, signal)| {
+            let tests = if rule_names.get(name.as_str()).copied().unwrap_or_default() > 1 {
+                Err(String::from(
+                    "shares its name with another rule, so promtool can't tell them apart",
+                ))
+            } else {
+                find_rule(&alerts, &name).map(|rule| signal_tests(spec, conf, rule, signal))?
+            };
+            Ok(AlertTests { alert: name, tests })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(custom_alerts) = &spec.alerts.custom {
+        planned.extend(custom_alerts.iter().map(|custom| AlertTests {
+            alert: custom.alert.clone(),
+            tests: Err(String::from("custom expressions are opaque")),
+        }));
+    }
+
+    Ok(planned)
+}
+
+fn find_rule<'a>(alerts: &'a PromAlerts, name: &str) -> Result<&'a AlertRules> {
+    alerts
+        .groups
+        .iter()
+        .flat_map(|group| group.rules.iter())
+        .find(|rule| rule.alert == name)
+        .ok_or_else(|| eyre!("No generated rule named `{name}`."))
+}
+
+/// Builds a test group in which the alert fires, and one in which it stays
+/// quiet.
+fn signal_tests(
+    spec: &ServiceAlertSpec,
+    conf: &AlertConfig,
+    rule: &AlertRules,
+    signal: Signal,
+) -> std::result::Result<Vec<TestGroup>, String> {
+    if conf.schedule.is_some() {
+        return Err(String::from(
+            "has a schedule, and promtool evaluates rules at the Unix epoch",
+        ));
+    }
+
+    let window = conf.window().as_duration();
+    if signal.needs_aligned_window()
+        && (window.is_zero()
+            || !window
+                .as_millis()
+                .is_multiple_of(SAMPLE_INTERVAL.as_millis()))
+    {
+        return Err(format!(
+            "window {0} isn't a multiple of the {1} sample interval",
+            conf.window(),
+            PromDuration::from(SAMPLE_INTERVAL)
+        ));
+    }
+
+    let expands_value = rule.annotations.summary.contains("$value")
+        || rule.annotations.description.contains("$value");
+
+    [Scenario::Firing, Scenario::Quiet]
+        .into_iter()
+        .map(|scenario| {
+            let value = scenario_value(scenario, conf, signal).ok_or_else(|| {
+                format!("no exact {0:?} value makes it {1}", signal, scenario.make())
+            })?;
+            if expands_value && value != 0_f64 && !PLAIN_VALUES.contains(&value) {
+                return Err(format!(
+                    "Prometheus would render `$value` {value} in exponent notation"
+                ));
+            }
+
+            let (input_series, output_labels) = input_series(spec, conf, signal, value);
+            Ok(test_group(
+                scenario,
+                conf,
+                rule,
+                input_series,
+                &output_labels,
+                value,
+            ))
+        })
+        .collect()
+}
+
+/// Series making `signal` take `value`, and the labels left on the signal
+/// after aggregation.
+fn input_series(
+    spec: &ServiceAlertSpec,
+    conf: &AlertConfig,
+    signal: Signal,
+    value: f64,
+) -> (Vec<InputSeries>, BTreeMap<String, String>) {
+    let workload = spec.workload_name();
+    let destination =
+        BTreeMap::from([(String::from("destination_workload"), workload.to_string())]);
+
+    match signal {
+        // Replica alerts sum the available replica series of a workload, so a
+        // single gauge holding the wanted replica count is enough
+        Signal::Replicas => {
+            let (metric, label) = replica_series(spec);
+            (
+                vec![InputSeries {
+                    series: format!(r#"{metric}{{{label}="{workload}"}}"#),
+                    values: gauge_values(value, conf),
+                }],
+                BTreeMap::from([(label.to_string(), workload.to_string())]),
+            )
+        }
+        Signal::ErrorPercent(protocol) => {
+            let errors = REQUESTS_PER_SAMPLE * value / 100_f64;
+            let series = match protocol {
+                Protocol::Http => vec![
+                    InputSeries {
+                        series: format!(
+                            r#"istio_requests_total{{request_protocol="http", response_code="500", destination_workload="{workload}"}}"#
+                        ),
+                        values: counter_values(errors, conf),
+                    },
+                    InputSeries {
+                        series: format!(
+                            r#"istio_requests_total{{request_protocol="http", response_code="200", destination_workload="{workload}"}}"#
+                        ),
+                        values: counter_values(REQUESTS_PER_SAMPLE - errors, conf),
+                    },
+                ],
+                Protocol::Grpc => vec![
+                    InputSeries {
+                        series: format!(
+                            r#"grpc_server_started_total{{destination_workload="{workload}"}}"#
+                        ),
+                        values: counter_values(REQUESTS_PER_SAMPLE, conf),
+                    },
+                    InputSeries {
+                        series: format!(
+                            r#"grpc_server_handled_total{{grpc_code="{GRPC_ERROR_CODE}", destination_workload="{workload}"}}"#
+                        ),
+                        values: counter_values(errors, conf),
+                    },
+                ],
+            };
+            (series, destination)
+        }
+        Signal::Traffic(protocol) => {
+            let series = match protocol {
+                Protocol::Http => format!(
+                    r#"istio_requests_total{{request_protocol="http", destination_workload="{workload}"}}"#
+                ),
+                Protocol::Grpc => {
+                    format!(r#"grpc_server_started_total{{destination_workload="{workload}"}}"#)
+                }
+            };
+            (
+                vec![InputSeries {
+                    series,
+                    values: counter_values(value * SAMPLE_INTERVAL.as_secs_f64(), conf),
+                }],
+                destination,
+            )
+        }
+        // Every observation lands in the `+Inf` bucket, so every percentile
+        // is the upper bound of the bucket below it
+        Signal::Latency(protocol) => {
+            let bucket = |le: String, increase: f64| InputSeries {
+                series: format!(
+                    r#"istio_request_duration_milliseconds_bucket{{request_protocol="{0}", destination_workload="{workload}", le="{le}"}}"#,
+                    protocol.request_protocol()
+                ),
+                values: counter_values(increase, conf),
+            };
+            (
+                vec![
+                    bucket(value.to_string(), 0_f64),
+                    bucket(String::from("+Inf"), 1_f64),
+                ],
+                destination,
+            )
+        }
+    }
+}
+
+fn test_group(
+    scenario: Scenario,
+    conf: &AlertConfig,
+    rule: &AlertRules,
+    input_series: Vec<InputSeries>,
+    output_labels: &BTreeMap<String, String>,
+    value: f64,
+) -> TestGroup {
+    let exp_alerts = match scenario {
+        Scenario::Firing => vec![expected_alert(rule, output_labels, value)],
+        Scenario::Quiet => Vec::new(),
+    };
+
+    TestGroup {
+        name: format!("{0} {1}", rule.alert, scenario.describe()),
+        interval: PromDuration::from(SAMPLE_INTERVAL).to_string(),
+        input_series,
+        alert_rule_test: vec![AlertRuleTest {
+            eval_time: PromDuration::from(evaluation_time(conf)).to_string(),
+            alertname: rule.alert.clone(),
+            exp_alerts,
+        }],
+    }
+}
+
+/// Firing alerts carry the rule labels, the labels left on the series after
+/// aggregation, and annotations with `$value` already expanded.
+fn expected_alert(
+    rule: &AlertRules,
+    output_labels: &BTreeMap<String, String>,
+    value: f64,
+) -> ExpectedAlert {
+    let mut exp_labels = output_labels.clone();
+    exp_labels.extend(rule_labels(rule));
+
+    // Prometheus renders `$value` with Go's `%v`, which matches Rust's float
+    // formatting for the small values used in these tests.
+    let expand = |template: &str| template.replace("{{ $value }}", &value.to_string());
+
+    ExpectedAlert {
+        exp_labels,
+        exp_annotations: BTreeMap::from([
+            (String::from("summary"), expand(&rule.annotations.summary)),
+            (
+                String::from("description"),
+                expand(&rule.annotations.description),
+            ),
+        ]),
+    }
+}
+
+/// Flattens rule labels, including the severity, into plain strings.
+fn rule_labels(rule: &AlertRules) -> BTreeMap<String, String> {
+    serde_json::to_value(&rule.labels)
+        .and_then(serde_json::from_value)
+        .unwrap_or_default()
+}
+
+/// Thresholds are stored as `f32`, but Prometheus parses the rendered value
+/// into an `f64`. Going through the rendered string keeps both sides equal.
+fn threshold(conf: &AlertConfig) -> f64 {
+    conf.value.to_string().parse().unwrap_or_default()
+}
+
+/// Returns the value of `signal` nearest to the threshold that either crosses
+/// or stays clear of it, or [`None`] if no value the signal can take exactly
+/// does.
+fn scenario_value(scenario: Scenario, conf: &AlertConfig, signal: Signal) -> Option<f64> {
+    let threshold = threshold(conf);
+    let fires = |value: f64| match conf.operation {
+        Operation::EqualTo => value == threshold,
+        Operation::LessThan => value < threshold,
+        Operation::MoreThan => value > threshold,
+    };
+
+    signal
+        .candidates(conf)
+        .into_iter()
+        .filter(|value| fires(*value) == (scenario == Scenario::Firing))
+        .min_by(|a, b| (a - threshold).abs().total_cmp(&(b - threshold).abs()))
+}
+
+/// Alerts need the evaluation window to fill up, and then the condition to
+/// hold for the `for` duration. Alerts that fired while the window was filling
+/// up need `keep_firing_for` to pass before they resolve. Evaluations happen
+/// on samples, so the result is rounded up to the next one.
+fn evaluation_time(conf: &AlertConfig) -> Duration {
+    let keep_firing_for = conf
+        .keep_firing_for
+        .map(|keep_firing_for| keep_firing_for.as_duration())
+        .unwrap_or_default();
+    let time = conf.for_.as_duration() + conf.window().as_duration() + keep_firing_for;
+    let samples = (time + EVALUATION_MARGIN)
+        .as_millis()
+        .div_ceil(SAMPLE_INTERVAL.as_millis());
+
+    SAMPLE_INTERVAL * samples as u32
+}
+
+/// Number of samples needed to cover the evaluation time, using promtool's
+/// `<start>+<step>x<count>` expanding notation.
+fn sample_count(conf: &AlertConfig) -> u128 {
+    let evaluation_time = evaluation_time(conf).as_millis();
+    let interval = SAMPLE_INTERVAL.as_millis();
+    evaluation_time.div_ceil(interval)
+}
+
+fn gauge_values(value: f64, conf: &AlertConfig) -> String {
+    format!("{value}+0x{0}", sample_count(conf))
+}
+
+fn counter_values(increase: f64, conf: &AlertConfig) -> String {
+    format!("0+{increase}x{0}", sample_count(conf))
+}
@@ -1,6 +1,11 @@
-use crate::crd::{AlertConfig, Operation, ServiceAlertSpec};
+use std::collections::BTreeMap;
 
-use super::alert::{AlertGroup, AlertRules, Annotations, Labels, PrometheusSeverity};
+use crate::crd::{AlertConfig, Operation, ServiceAlertSpec, WorkloadKind};
+
+use super::{
+    alert::{AlertGroup, AlertRules, Annotations, Labels, PrometheusSeverity},
+    schedule::scheduled,
+};
 
 /// Generates an [`AlertGroup`] for a list of defined replica alerts. Caller is
 /// responsible for only passing in a slice of alerts that are actually replica
@@ -13,24 +18,30 @@ pub fn replica_count_rules(alert_configs: &[AlertConfig], spec: &ServiceAlertSpe
         .iter()
         .enumerate()
         .map(|(i, conf)| AlertRules {
-            alert: format!("ReplicaRule-{0}-{1}", spec.deployment_name, i),
+            alert: replica_rule_name(spec, i),
             expr: replicas_promql(conf, spec),
-            for_: conf.for_.clone(),
-            labels: Labels {
-                severity: PrometheusSeverity::from(&conf.with_labels),
-                source: spec.common_labels.origin.clone(),
-                owner: spec.common_labels.owner.clone(),
-            },
+            for_: conf.for_.to_string(),
+            keep_firing_for: conf
+                .keep_firing_for
+                .map(|keep_firing_for| keep_firing_for.to_string()),
+            labels: Labels::for_spec(PrometheusSeverity::from(&conf.with_labels), spec),
             annotations: replicas_annotations(conf),
         })
         .collect();
 
     AlertGroup {
         name: String::from("Replica Alerts"),
+        interval: spec.interval.map(|interval| interval.to_string()),
+        limit: spec.limit,
         rules: replica_rules,
     }
 }
 
+/// Returns the name of the `index`th replica alert rule for a [`ServiceAlertSpec`].
+pub fn replica_rule_name(spec: &ServiceAlertSpec, index: usize) -> String {
+    format!("ReplicaRule-{0}-{1}", spec.workload_name(), index)
+}
+
 // Since the metrics are different for different protocols, we must map each Alerts enum
 // to a different expression string in prometheus land.
 // e.g.
@@ -50,13 +61,45 @@ pub fn replica_count_rules(alert_configs: &[AlertConfig], spec: &ServiceAlertSpe
 /// it is the caller's responsibility to *not* call this function on other alert
 /// types, like HTTP or gRPC alerts.
 fn replicas_promql(alert_config: &AlertConfig, spec: &ServiceAlertSpec) -> String {
-    let operation = &alert_config.operation;
+    scheduled(
+        format!(
+            "{0} {1} {2}",
+            replica_signal(spec),
+            alert_config.operation,
+            alert_config.value
+        ),
+        alert_config.schedule.as_ref(),
+    )
+}
+
+/// Returns the PromQL expression for the number of pod replicas that are up.
+pub fn replica_signal(spec: &ServiceAlertSpec) -> String {
+    let (metric, label) = replica_series(spec);
     format!(
-        r#"sum by (app_kubernetes_io_name) (up{{app_kubernetes_io_name="{0}"}}) {operation} {1}"#,
-        spec.deployment_name, alert_config.value,
+        r#"sum by ({label}) ({metric}{{{label}="{0}"}})"#,
+        spec.workload_name()
     )
 }
 
+/// Returns the metric counting available replicas of the workload, and the
+/// label holding the workload name.
+///
+/// Workloads named by `deploymentName` or found by `workloadSelector` count
+/// scraped pods through `up`, as they always have. A `workloadRef` reads the
+/// series for its kind, which kube-state-metrics covers for everything but
+/// Argo Rollouts. Rollouts are counted by the Argo Rollouts controller itself.
+pub fn replica_series(spec: &ServiceAlertSpec) -> (&'static str, &'static str) {
+    let Some(workload_ref) = &spec.workload_ref else {
+        return ("up", "app_kubernetes_io_name");
+    };
+    match workload_ref.kind {
+        WorkloadKind::Deployment => ("kube_deployment_status_replicas_available", "deployment"),
+        WorkloadKind::StatefulSet => ("kube_statefulset_status_replicas_ready", "statefulset"),
+        WorkloadKind::DaemonSet => ("kube_daemonset_status_number_available", "daemonset"),
+        WorkloadKind::Rollout => ("rollout_info_replicas_available", "name"),
+    }
+}
+
 /// Returns the [`Annotations`] struct for a given [`AlertConfig`].
 fn replicas_annotations(alert_config: &AlertConfig) -> Annotations {
     // Alert annotations and labels for Prometheus can be templated, using two
@@ -69,6 +112,7 @@ fn replicas_annotations(alert_config: &AlertConfig) -> Annotations {
         Operation::EqualTo => Annotations {
             summary: String::from("Replicas reached alert boundary"),
             description: format!("{0} replicas currently up", alert_config.value),
+            extra: BTreeMap::new(),
         },
         Operation::LessThan => Annotations {
             summary: String::from("Replicas less than alert boundary"),
@@ -76,6 +120,7 @@ fn replicas_annotations(alert_config: &AlertConfig) -> Annotations {
                 "{{{{ $value }}}} replicas currently up, expected at least {0}",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
         Operation::MoreThan => Annotations {
             summary: String::from("Replicas more than alert boundary"),
@@ -83,6 +128,7 @@ fn replicas_annotations(alert_config: &AlertConfig) -> Annotations {
                 "{{{{ $value }}}} replicas currently up, expected less than {0}",
                 alert_config.value
             ),
+            extra: BTreeMap::new(),
         },
     }
 }
@@ -0,0 +1,130 @@
+//! # Schedules
+//!
+//! Alerts with an [`ActiveSchedule`] have their expression guarded by PromQL's
+//! time functions, so that Prometheus itself only lets them fire at the
+//! scheduled times.
+//!
+//! PromQL has no notion of timezones, `hour()` and `day_of_week()` always work
+//! in UTC. Instead, the current time is shifted by the UTC offset of the
+//! schedule's timezone at the time the rule is rendered. ServiceAlerts with a
+//! schedule are reconciled again right after the next offset change, see
+//! [`next_offset_change`], so the offset catches up with daylight saving
+//! changes as soon as Prometheus reloads the rules.
+
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Duration, Offset, TimeZone, Utc};
+
+use crate::crd::{ActiveSchedule, HourRange, Weekday};
+
+/// Guards `expr` with the given schedule, if there is one.
+pub fn scheduled(expr: String, schedule: Option<&ActiveSchedule>) -> String {
+    scheduled_at(expr, schedule, Utc::now())
+}
+
+/// Guards `expr` with the given schedule, using the timezone offsets in effect
+/// at `now`.
+pub fn scheduled_at(expr: String, schedule: Option<&ActiveSchedule>, now: DateTime<Utc>) -> String {
+    let Some(schedule) = schedule else {
+        return expr;
+    };
+
+    let offset = utc_offset(schedule, now);
+    let time = match offset {
+        0 => String::from("vector(time())"),
+        offset if offset < 0 => format!("vector(time() - {0})", offset.abs()),
+        offset => format!("vector(time() + {offset})"),
+    };
+
+    let guards: Vec<String> = [
+        schedule.hours.and_then(|hours| hours_guard(hours, &time)),
+        schedule
+            .weekdays
+            .as_deref()
+            .and_then(|weekdays| weekdays_guard(weekdays, &time)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if guards.is_empty() {
+        return expr;
+    }
+
+    format!("({expr}) and on() {0}", guards.join(" and on() "))
+}
+
+/// Returns the first time after `now`, and no later than `now + within`, at
+/// which the timezone of one of the schedules changes its UTC offset.
+pub fn next_offset_change<'a>(
+    schedules: impl IntoIterator<Item = &'a ActiveSchedule>,
+    now: DateTime<Utc>,
+    within: Duration,
+) -> Option<DateTime<Utc>> {
+    schedules
+        .into_iter()
+        .filter(|schedule| utc_offset(schedule, now) != utc_offset(schedule, now + within))
+        .map(|schedule| {
+            // Offsets only change once in a window this short, so the change
+            // can be found by bisecting to the second
+            let offset = utc_offset(schedule, now);
+            let (mut before, mut after) = (0, within.num_seconds());
+            while after - before > 1 {
+                let middle = (before + after) / 2;
+                match utc_offset(schedule, now + Duration::seconds(middle)) == offset {
+                    true => before = middle,
+                    false => after = middle,
+                }
+            }
+            now + Duration::seconds(after)
+        })
+        .min()
+}
+
+/// Seconds the schedule's timezone is ahead of UTC at `now`.
+fn utc_offset(schedule: &ActiveSchedule, now: DateTime<Utc>) -> i32 {
+    schedule
+        .timezone
+        .map(|timezone| {
+            timezone
+                .as_tz()
+                .offset_from_utc_datetime(&now.naive_utc())
+                .fix()
+                .local_minus_utc()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns [`None`] if the range covers the whole day.
+fn hours_guard(hours: HourRange, time: &str) -> Option<String> {
+    let HourRange { start, end } = hours;
+    let end = end.min(24);
+
+    if start == end || (start == 0 && end == 24) {
+        return None;
+    }
+
+    // Comparisons without `bool` filter their left hand side, so they can be
+    // chained to check both bounds.
+    Some(if start < end {
+        format!("(hour({time}) >= {start} < {end})")
+    } else {
+        format!("(hour({time}) >= {start} or hour({time}) < {end})")
+    })
+}
+
+/// Returns [`None`] if every day of the week is listed.
+fn weekdays_guard(weekdays: &[Weekday], time: &str) -> Option<String> {
+    let days: BTreeSet<u8> = weekdays.iter().map(Weekday::number).collect();
+    if days.len() == 7 {
+        return None;
+    }
+
+    let matchers = days
+        .iter()
+        .map(|day| format!("day_of_week({time}) == {day}"))
+        .collect::<Vec<_>>()
+        .join(" or ");
+
+    Some(format!("({matchers})"))
+}
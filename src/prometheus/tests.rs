@@ -1,7 +1,27 @@
+use std::collections::BTreeMap;
+
+use chrono::{TimeZone, Utc};
 use color_eyre::Result;
 use pretty_assertions::assert_eq;
 
-use crate::prometheus::alert::*;
+use crate::{
+    config::{Cluster, Prometheus},
+    crd::{
+        ActiveAlertStatus, ActiveAlertsStatus, ActiveSchedule, AlertConfig, Alerts, CommonLabels,
+        CustomAlert, CustomAnnotations, HourRange, NetworkAlert, Operation, ReplicaAlert,
+        ServiceAlert, ServiceAlertDefaultsSpec, ServiceAlertSpec, Weekday, WorkloadKind,
+        WorkloadRef, WorkloadSelector,
+    },
+    prometheus::{
+        alert::*,
+        api::{
+            active_alerts_status, ActiveAlert, PrometheusClient, PrometheusError, RuleLocation,
+            RuleVerification,
+        },
+        promtool::{ExpectedAlert, PromToolTests},
+        schedule::{next_offset_change, scheduled_at},
+    },
+};
 
 const SERIALIZED_PROM_ALERT: &str = r#"
 groups:
@@ -23,18 +43,23 @@ fn test_serialisation_happy_path() -> Result<()> {
     let rust_repr = PromAlerts {
         groups: vec![AlertGroup {
             name: "example".into(),
+            interval: None,
+            limit: None,
             rules: vec![AlertRules {
                 alert: "HighRequestLatency".into(),
                 expr: r#"job:request_latency_seconds:mean5m{job="myjob"} > 0.5"#.into(),
                 for_: "10m".into(),
+                keep_firing_for: None,
                 labels: Labels {
                     severity: PrometheusSeverity::Page,
                     source: "cloud".into(),
                     owner: "service".into(),
+                    extra: BTreeMap::new(),
                 },
                 annotations: Annotations {
                     summary: "High request latency".into(),
                     description: "Request latency over 9000".into(),
+                    extra: BTreeMap::new(),
                 },
             }],
         }],
@@ -45,3 +70,1366 @@ fn test_serialisation_happy_path() -> Result<()> {
 
     Ok(())
 }
+
+fn test_spec(alerts: Alerts) -> ServiceAlertSpec {
+    ServiceAlertSpec {
+        common_labels: CommonLabels {
+            owner: "foo".into(),
+            origin: "cloud".into(),
+            extra: BTreeMap::new(),
+        },
+        deployment_name: "best-service-eu".into(),
+        workload_ref: None,
+        workload_selector: None,
+        maintenance: None,
+        profile: None,
+        template: None,
+        annotations: BTreeMap::new(),
+        interval: None,
+        limit: None,
+        alerts,
+    }
+}
+
+fn custom_alert_spec(custom: CustomAlert) -> ServiceAlertSpec {
+    test_spec(Alerts {
+        grpc: None,
+        rest: None,
+        replica: None,
+        custom: Some(vec![custom]),
+    })
+}
+
+#[test]
+fn test_custom_alert_receives_common_labels() -> Result<()> {
+    let spec = custom_alert_spec(CustomAlert {
+        alert: "QueueBacklog".into(),
+        expr: r#"sum(queue_depth{app="best-service-eu"}) > 100"#.into(),
+        for_: "10m".parse()?,
+        keep_firing_for: None,
+        with_labels: BTreeMap::from([
+            ("severity".into(), "critical".into()),
+            ("team".into(), "queues".into()),
+            ("owner".into(), "someone-else".into()),
+        ]),
+        annotations: CustomAnnotations {
+            summary: "Queue backlog".into(),
+            description: "Queue depth is {{ $value }}".into(),
+        },
+    });
+
+    let expected = PromAlerts {
+        groups: vec![AlertGroup {
+            name: "Custom Alerts".into(),
+            interval: None,
+            limit: None,
+            rules: vec![AlertRules {
+                alert: "QueueBacklog".into(),
+                expr: r#"sum(queue_depth{app="best-service-eu"}) > 100"#.into(),
+                for_: "10m".into(),
+                keep_firing_for: None,
+                labels: Labels {
+                    severity: PrometheusSeverity::Critical,
+                    source: "cloud".into(),
+                    owner: "foo".into(),
+                    extra: BTreeMap::from([("team".into(), "queues".into())]),
+                },
+                annotations: Annotations {
+                    summary: "Queue backlog".into(),
+                    description: "Queue depth is {{ $value }}".into(),
+                    extra: BTreeMap::new(),
+                },
+            }],
+        }],
+    };
+
+    assert_eq!(PromAlerts::try_from(spec)?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_custom_alert_severity_defaults_to_warning() -> Result<()> {
+    let spec = custom_alert_spec(CustomAlert {
+        alert: "QueueBacklog".into(),
+        expr: "sum(queue_depth) > 100".into(),
+        for_: "10m".parse()?,
+        keep_firing_for: None,
+        with_labels: BTreeMap::from([("team".into(), "queues".into())]),
+        annotations: CustomAnnotations {
+            summary: "Queue backlog".into(),
+            description: "Queue backlog".into(),
+        },
+    });
+
+    let alerts = PromAlerts::try_from(spec)?;
+    let labels = &alerts.groups[0].rules[0].labels;
+    assert_eq!(labels.severity, PrometheusSeverity::Warning);
+    assert_eq!(labels.extra.get("team"), Some(&String::from("queues")));
+
+    Ok(())
+}
+
+#[test]
+fn test_custom_alert_rejects_empty_expression() -> Result<()> {
+    let spec = custom_alert_spec(CustomAlert {
+        alert: "Broken".into(),
+        expr: " ".into(),
+        for_: "1m".parse()?,
+        keep_firing_for: None,
+        with_labels: BTreeMap::from([("severity".into(), "warning".into())]),
+        annotations: CustomAnnotations {
+            summary: "Broken".into(),
+            description: "Broken".into(),
+        },
+    });
+
+    assert!(PromAlerts::try_from(spec).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_window_is_independent_of_for() -> Result<()> {
+    let alert_config = |window: Option<&str>| -> Result<AlertConfig> {
+        Ok(AlertConfig {
+            operation: Operation::MoreThan,
+            value: 10_f32,
+            for_: "1m".parse()?,
+            window: window.map(str::parse).transpose()?,
+            keep_firing_for: None,
+            schedule: None,
+            with_labels: BTreeMap::from([("severity".into(), "warning".into())]),
+        })
+    };
+
+    let spec = test_spec(Alerts {
+        grpc: Some(BTreeMap::from([(
+            NetworkAlert::ErrorPercent,
+            vec![alert_config(Some("10m"))?, alert_config(None)?],
+        )])),
+        rest: None,
+        replica: None,
+        custom: None,
+    });
+
+    let alerts = PromAlerts::try_from(spec)?;
+    let rules = &alerts.groups[0].rules;
+
+    assert_eq!(rules[0].for_, "1m");
+    assert!(rules[0].expr.contains("[10m]"));
+    assert!(!rules[0].expr.contains("[1m]"));
+
+    // no window configured, so fall back to the default
+    assert_eq!(rules[1].for_, "1m");
+    assert!(rules[1].expr.contains("[2m]"));
+
+    Ok(())
+}
+
+#[test]
+fn test_network_alerts_query_istio_signals() -> Result<()> {
+    let network_alerts = || -> Result<BTreeMap<NetworkAlert, Vec<AlertConfig>>> {
+        [
+            NetworkAlert::ErrorPercent,
+            NetworkAlert::TrafficPerSecond,
+            NetworkAlert::LatencyMillisecondsP50,
+            NetworkAlert::LatencyMillisecondsP90,
+            NetworkAlert::LatencyMillisecondsP95,
+            NetworkAlert::LatencyMillisecondsP99,
+        ]
+        .into_iter()
+        .map(|network_alert| -> Result<_> {
+            Ok((
+                network_alert,
+                vec![AlertConfig {
+                    operation: Operation::MoreThan,
+                    value: 10_f32,
+                    for_: "5m".parse()?,
+                    window: None,
+                    keep_firing_for: None,
+                    schedule: None,
+                    with_labels: BTreeMap::from([("severity".into(), "warning".into())]),
+                }],
+            ))
+        })
+        .collect()
+    };
+    let exprs = |alerts: PromAlerts| -> Vec<String> {
+        let mut exprs: Vec<_> = alerts
+            .groups
+            .into_iter()
+            .flat_map(|group| group.rules)
+            .map(|rule| rule.expr)
+            .collect();
+        exprs.sort();
+        exprs
+    };
+
+    let grpc = PromAlerts::try_from(test_spec(Alerts {
+        grpc: Some(network_alerts()?),
+        rest: None,
+        replica: None,
+        custom: None,
+    }))?;
+    assert_eq!(
+        exprs(grpc),
+        vec![
+            r#"histogram_quantile(0.5, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{request_protocol="grpc", destination_workload="best-service-eu"}[2m]))) > 10"#,
+            r#"histogram_quantile(0.9, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{request_protocol="grpc", destination_workload="best-service-eu"}[2m]))) > 10"#,
+            r#"histogram_quantile(0.95, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{request_protocol="grpc", destination_workload="best-service-eu"}[2m]))) > 10"#,
+            r#"histogram_quantile(0.99, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{request_protocol="grpc", destination_workload="best-service-eu"}[2m]))) > 10"#,
+            r#"sum by (destination_workload) (rate(grpc_server_handled_total{grpc_code=~"Unknown|ResourceExhausted|Internal|Unavailable|DataLoss|DeadlineExceeded", destination_workload="best-service-eu"}[2m])) / sum by (destination_workload) (rate(grpc_server_started_total{destination_workload="best-service-eu"}[2m])) * 100 > 10"#,
+            r#"sum by (destination_workload) (rate(grpc_server_started_total{destination_workload="best-service-eu"}[2m])) > 10"#,
+        ]
+    );
+
+    let http = PromAlerts::try_from(test_spec(Alerts {
+        grpc: None,
+        rest: Some(network_alerts()?),
+        replica: None,
+        custom: None,
+    }))?;
+    assert_eq!(
+        exprs(http),
+        vec![
+            r#"histogram_quantile(0.5, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{request_protocol="http", destination_workload="best-service-eu"}[2m]))) > 10"#,
+            r#"histogram_quantile(0.9, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{request_protocol="http", destination_workload="best-service-eu"}[2m]))) > 10"#,
+            r#"histogram_quantile(0.95, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{request_protocol="http", destination_workload="best-service-eu"}[2m]))) > 10"#,
+            r#"histogram_quantile(0.99, sum by (destination_workload, le) (rate(istio_request_duration_milliseconds_bucket{request_protocol="http", destination_workload="best-service-eu"}[2m]))) > 10"#,
+            r#"sum by (destination_workload) (rate(istio_requests_total{request_protocol="http", destination_workload="best-service-eu"}[2m])) > 10"#,
+            r#"sum by (destination_workload) (rate(istio_requests_total{request_protocol="http", response_code=~"5..", destination_workload="best-service-eu"}[2m])) / sum by (destination_workload) (rate(istio_requests_total{request_protocol="http", destination_workload="best-service-eu"}[2m])) * 100 > 10"#,
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_network_alerts_compare_with_configured_operation() -> Result<()> {
+    let alert_config = |operation: Operation, value: f32| -> Result<AlertConfig> {
+        Ok(AlertConfig {
+            operation,
+            value,
+            for_: "5m".parse()?,
+            window: None,
+            keep_firing_for: None,
+            schedule: None,
+            with_labels: BTreeMap::from([("severity".into(), "warning".into())]),
+        })
+    };
+    let network_alerts = || -> Result<BTreeMap<NetworkAlert, Vec<AlertConfig>>> {
+        Ok(BTreeMap::from([(
+            NetworkAlert::TrafficPerSecond,
+            vec![
+                alert_config(Operation::LessThan, 1_f32)?,
+                alert_config(Operation::EqualTo, 0_f32)?,
+                alert_config(Operation::MoreThan, 1000_f32)?,
+            ],
+        )]))
+    };
+
+    for alerts in [
+        Alerts {
+            grpc: Some(network_alerts()?),
+            rest: None,
+            replica: None,
+            custom: None,
+        },
+        Alerts {
+            grpc: None,
+            rest: Some(network_alerts()?),
+            replica: None,
+            custom: None,
+        },
+    ] {
+        let rules = &PromAlerts::try_from(test_spec(alerts))?.groups[0].rules;
+        assert!(rules[0].expr.ends_with(") < 1"), "{0}", rules[0].expr);
+        assert!(rules[1].expr.ends_with(") == 0"), "{0}", rules[1].expr);
+        assert!(rules[2].expr.ends_with(") > 1000"), "{0}", rules[2].expr);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_optional_fields_serialised_only_when_set() -> Result<()> {
+    let custom = |keep_firing_for: Option<&str>| -> Result<CustomAlert> {
+        Ok(CustomAlert {
+            alert: "QueueBacklog".into(),
+            expr: "sum(queue_depth) > 100".into(),
+            for_: "10m".parse()?,
+            keep_firing_for: keep_firing_for.map(str::parse).transpose()?,
+            with_labels: BTreeMap::from([("severity".into(), "warning".into())]),
+            annotations: CustomAnnotations {
+                summary: "Queue backlog".into(),
+                description: "Queue backlog".into(),
+            },
+        })
+    };
+
+    let unset = serde_yaml::to_string(&PromAlerts::try_from(custom_alert_spec(custom(None)?))?)?;
+    assert!(!unset.contains("interval"));
+    assert!(!unset.contains("limit"));
+    assert!(!unset.contains("keep_firing_for"));
+
+    let mut spec = custom_alert_spec(custom(Some("15m"))?);
+    spec.interval = Some("30s".parse()?);
+    spec.limit = Some(5);
+
+    let set = serde_yaml::to_string(&PromAlerts::try_from(spec)?)?;
+    assert!(set.contains("interval: 30s"));
+    assert!(set.contains("limit: 5"));
+    assert!(set.contains("keep_firing_for: 15m"));
+
+    Ok(())
+}
+
+#[test]
+fn test_extra_common_labels_reach_every_rule() -> Result<()> {
+    let example: ServiceAlert =
+        serde_yaml::from_str(include_str!("../../example-custom-resource.yaml"))?;
+    let defaults: ServiceAlertDefaultsSpec = serde_yaml::from_str(
+        r#"
+commonLabels:
+  team: payments
+  owner: ignored
+"#,
+    )?;
+
+    let spec = example.spec.with_defaults(&defaults);
+    let alerts = PromAlerts::try_from(spec)?;
+    let rules: Vec<&AlertRules> = alerts.groups.iter().flat_map(|g| g.rules.iter()).collect();
+
+    assert!(!rules.is_empty());
+    for rule in rules {
+        assert_eq!(
+            rule.labels.extra.get("team"),
+            Some(&String::from("payments"))
+        );
+        assert_eq!(rule.labels.owner, "foo");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_promtool_replica_tests_cross_threshold() -> Result<()> {
+    let spec = test_spec(Alerts {
+        grpc: None,
+        rest: None,
+        replica: Some(BTreeMap::from([(
+            ReplicaAlert::Count,
+            vec![AlertConfig {
+                operation: Operation::LessThan,
+                value: 3_f32,
+                for_: "5m".parse()?,
+                window: None,
+                keep_firing_for: None,
+                schedule: None,
+                with_labels: BTreeMap::from([("severity".into(), "critical".into())]),
+            }],
+        )])),
+        custom: None,
+    });
+
+    let tests = PromToolTests::generate(&spec, "rules.yaml")?;
+    assert_eq!(tests.rule_files, vec![String::from("rules.yaml")]);
+    assert_eq!(tests.tests.len(), 2);
+
+    let firing = &tests.tests[0];
+    assert_eq!(firing.input_series[0].values, "2+0x32");
+    assert_eq!(firing.alert_rule_test[0].eval_time, "8m");
+    assert_eq!(
+        firing.alert_rule_test[0].exp_alerts,
+        vec![ExpectedAlert {
+            exp_labels: BTreeMap::from([
+                ("app_kubernetes_io_name".into(), "best-service-eu".into()),
+                ("owner".into(), "foo".into()),
+                ("severity".into(), "critical".into()),
+                ("source".into(), "cloud".into()),
+            ]),
+            exp_annotations: BTreeMap::from([
+                ("summary".into(), "Replicas less than alert boundary".into()),
+                (
+                    "description".into(),
+                    "2 replicas currently up, expected at least 3".into(),
+                ),
+            ]),
+        }]
+    );
+
+    let quiet = &tests.tests[1];
+    assert_eq!(quiet.input_series[0].values, "4+0x32");
+    assert!(quiet.alert_rule_test[0].exp_alerts.is_empty());
+
+    Ok(())
+}
+
+fn network_config(operation: Operation, value: f32) -> Result<AlertConfig> {
+    Ok(AlertConfig {
+        operation,
+        value,
+        for_: "1m".parse()?,
+        window: None,
+        keep_firing_for: None,
+        schedule: None,
+        with_labels: BTreeMap::from([("severity".into(), "warning".into())]),
+    })
+}
+
+#[test]
+fn test_promtool_http_error_percent_tests_are_exact() -> Result<()> {
+    let spec = test_spec(Alerts {
+        grpc: None,
+        rest: Some(BTreeMap::from([(
+            NetworkAlert::ErrorPercent,
+            vec![network_config(Operation::MoreThan, 5_f32)?],
+        )])),
+        replica: None,
+        custom: None,
+    });
+
+    let tests = PromToolTests::generate(&spec, "rules.yaml")?;
+    assert_eq!(tests.tests.len(), 2);
+
+    // Half of the requests fail, which is the nearest exact percentage
+    let firing = &tests.tests[0];
+    assert_eq!(
+        firing.alert_rule_test[0].alertname,
+        "HTTPErrorPercentRule-best-service-eu-0"
+    );
+    assert_eq!(firing.alert_rule_test[0].eval_time, "4m");
+    assert_eq!(firing.input_series[0].values, "0+32x16");
+    assert_eq!(firing.input_series[1].values, "0+32x16");
+    let alert = &firing.alert_rule_test[0].exp_alerts[0];
+    assert_eq!(
+        alert.exp_labels.get("destination_workload"),
+        Some(&String::from("best-service-eu"))
+    );
+    assert_eq!(
+        alert.exp_annotations.get("description"),
+        Some(&String::from(
+            "Current error percentage is 50%, boundary is 5"
+        ))
+    );
+
+    let quiet = &tests.tests[1];
+    assert_eq!(quiet.input_series[0].values, "0+0x16");
+    assert_eq!(quiet.input_series[1].values, "0+64x16");
+
+    Ok(())
+}
+
+#[test]
+fn test_promtool_latency_and_traffic_tests_hit_thresholds() -> Result<()> {
+    let spec = test_spec(Alerts {
+        grpc: Some(BTreeMap::from([
+            (
+                NetworkAlert::TrafficPerSecond,
+                vec![network_config(Operation::EqualTo, 2_f32)?],
+            ),
+            (
+                NetworkAlert::LatencyMillisecondsP90,
+                vec![network_config(Operation::LessThan, 250_f32)?],
+            ),
+        ])),
+        rest: None,
+        replica: None,
+        custom: None,
+    });
+
+    let tests = PromToolTests::generate(&spec, "rules.yaml")?;
+    assert!(PromToolTests::unsupported(&spec)?.is_empty());
+    assert_eq!(tests.tests.len(), 4);
+
+    // Traffic counters increase by the rate times the sample interval
+    assert_eq!(tests.tests[0].input_series[0].values, "0+30x16");
+    assert_eq!(tests.tests[1].input_series[0].values, "0+45x16");
+
+    // Latency comes from the bucket below `+Inf`
+    let firing = &tests.tests[2].input_series;
+    assert!(firing[0].series.contains(r#"request_protocol="grpc""#));
+    assert!(firing[0].series.contains(r#"le="249""#));
+    assert_eq!(firing[0].values, "0+0x16");
+    assert!(firing[1].series.contains(r#"le="+Inf""#));
+    assert_eq!(firing[1].values, "0+1x16");
+    assert!(tests.tests[3].input_series[0]
+        .series
+        .contains(r#"le="251""#));
+
+    Ok(())
+}
+
+#[test]
+fn test_promtool_reports_untestable_alerts() -> Result<()> {
+    let mut scheduled = network_config(Operation::LessThan, 2_f32)?;
+    scheduled.schedule = Some(ActiveSchedule {
+        hours: None,
+        weekdays: Some(vec![Weekday::Monday]),
+        timezone: None,
+    });
+    let spec = test_spec(Alerts {
+        grpc: Some(BTreeMap::from([(
+            NetworkAlert::ErrorPercent,
+            vec![
+                network_config(Operation::EqualTo, 5_f32)?,
+                network_config(Operation::MoreThan, 100_f32)?,
+            ],
+        )])),
+        rest: Some(BTreeMap::from([
+            (
+                NetworkAlert::LatencyMillisecondsP50,
+                vec![network_config(Operation::MoreThan, 100_f32)?],
+            ),
+            (
+                NetworkAlert::LatencyMillisecondsP99,
+                vec![network_config(Operation::MoreThan, 200_f32)?],
+            ),
+        ])),
+        replica: Some(BTreeMap::from([(ReplicaAlert::Count, vec![scheduled])])),
+        custom: None,
+    });
+
+    assert!(PromToolTests::generate(&spec, "rules.yaml")?
+        .tests
+        .is_empty());
+    assert_eq!(
+        PromToolTests::unsupported(&spec)?,
+        vec![
+            "`ReplicaRule-best-service-eu-0`: has a schedule, and promtool evaluates rules at the Unix epoch",
+            "`HTTPLatencyPercentileRule-best-service-eu-0`: shares its name with another rule, so promtool can't tell them apart",
+            "`HTTPLatencyPercentileRule-best-service-eu-0`: shares its name with another rule, so promtool can't tell them apart",
+            "`Error % == 5`: no exact ErrorPercent(Grpc) value makes it fire",
+            "`Error % > 100`: no exact ErrorPercent(Grpc) value makes it fire",
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_promtool_covers_example_manifest() -> Result<()> {
+    let example: ServiceAlert =
+        serde_yaml::from_str(include_str!("../../example-custom-resource.yaml"))?;
+
+    assert_eq!(
+        PromToolTests::unsupported(&example.spec.expanded(None))?,
+        vec!["`QueueBacklog`: custom expressions are opaque"]
+    );
+
+    Ok(())
+}
+
+const ERROR_PERCENT_ALERT: &str = r#"
+    errorPercent:
+    - operation: MoreThan
+      value: 5
+      for: 1m
+      withLabels:
+        severity: warning"#;
+
+const TRAFFIC_ALERT: &str = r#"
+    trafficPerSecond:
+    - operation: LessThan
+      value: 100
+      for: 1m
+      withLabels:
+        severity: warning"#;
+
+/// Checks the rule file rendered for the example manifest, and runs the
+/// promtool tests generated for it, through a real `promtool`, which has to be
+/// on the `PATH`. CI installs it, locally run `cargo test -- --ignored
+/// promtool`.
+#[test]
+#[ignore = "needs promtool"]
+fn test_promtool_passes_generated_tests() -> Result<()> {
+    let example: ServiceAlert =
+        serde_yaml::from_str(include_str!("../../example-custom-resource.yaml"))?;
+    let spec = example.spec.expanded(None);
+
+    let dir = std::env::temp_dir().join(format!("cactuar-promtool-{0}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let rules = PromAlerts::try_from(spec.clone())?;
+    let tests = PromToolTests::generate(&spec, "example.rules.yaml")?;
+    std::fs::write(
+        dir.join("example.rules.yaml"),
+        serde_yaml::to_string(&rules)?,
+    )?;
+    std::fs::write(
+        dir.join("example.test.yaml"),
+        serde_yaml::to_string(&tests)?,
+    )?;
+
+    let promtool = |args: [&str; 2], file: &str| {
+        std::process::Command::new("promtool")
+            .args(args)
+            .arg(dir.join(file))
+            .output()
+    };
+    let checked = promtool(["check", "rules"], "example.rules.yaml")?;
+    let tested = promtool(["test", "rules"], "example.test.yaml")?;
+    std::fs::remove_dir_all(&dir)?;
+
+    for output in [checked, tested] {
+        assert!(
+            output.status.success(),
+            "{0}{1}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rendering_is_independent_of_declaration_order() -> Result<()> {
+    let render = |first: &str, second: &str| -> Result<String> {
+        let spec: ServiceAlertSpec = serde_yaml::from_str(&format!(
+            r#"
+commonLabels:
+  owner: foo
+  origin: cloud
+deploymentName: best-service-eu
+alerts:
+  gRPC:{first}{second}
+  REST:{second}{first}
+"#
+        ))?;
+        Ok(serde_yaml::to_string(&PromAlerts::try_from(spec)?)?)
+    };
+
+    let rendered = render(ERROR_PERCENT_ALERT, TRAFFIC_ALERT)?;
+    assert_eq!(rendered, render(TRAFFIC_ALERT, ERROR_PERCENT_ALERT)?);
+
+    // Groups follow the declaration order of the alert enums
+    let alerts: PromAlerts = serde_yaml::from_str(&rendered)?;
+    let names: Vec<_> = alerts.groups.iter().map(|g| g.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "HTTP Alerts",
+            "gRPC Error % Alerts",
+            "gRPC Traffic /sec Alerts"
+        ]
+    );
+
+    Ok(())
+}
+
+fn selector_spec(aggregate: bool) -> Result<ServiceAlertSpec> {
+    let mut spec = test_spec(Alerts {
+        grpc: None,
+        rest: None,
+        replica: Some(BTreeMap::from([(
+            ReplicaAlert::Count,
+            vec![AlertConfig {
+                operation: Operation::LessThan,
+                value: 3_f32,
+                for_: "5m".parse()?,
+                window: None,
+                keep_firing_for: None,
+                schedule: None,
+                with_labels: BTreeMap::from([("severity".into(), "critical".into())]),
+            }],
+        )])),
+        custom: Some(vec![CustomAlert {
+            alert: "QueueBacklog".into(),
+            expr: "sum(queue_depth) > 100".into(),
+            for_: "10m".parse()?,
+            keep_firing_for: None,
+            with_labels: BTreeMap::from([("severity".into(), "warning".into())]),
+            annotations: CustomAnnotations {
+                summary: "Queue backlog".into(),
+                description: "Queue backlog".into(),
+            },
+        }]),
+    });
+    spec.deployment_name = String::new();
+    spec.workload_selector = Some(WorkloadSelector {
+        match_labels: BTreeMap::from([("app.kubernetes.io/part-of".into(), "best-service".into())]),
+        aggregate,
+    });
+
+    Ok(spec)
+}
+
+#[test]
+fn test_workload_selector_renders_groups_per_workload() -> Result<()> {
+    let workloads = vec![
+        String::from("best-service-eu"),
+        String::from("best-service-us"),
+    ];
+    let alerts = PromAlerts::for_workloads(&selector_spec(false)?, &workloads)?;
+
+    let groups: Vec<_> = alerts
+        .groups
+        .iter()
+        .map(|group| {
+            let rules: Vec<_> = group.rules.iter().map(|rule| rule.alert.as_str()).collect();
+            (group.name.as_str(), rules)
+        })
+        .collect();
+
+    // Custom alerts don't depend on the workload, so they are only rendered
+    // once, in a group of their own
+    assert_eq!(
+        groups,
+        vec![
+            (
+                "Replica Alerts (best-service-eu)",
+                vec!["ReplicaRule-best-service-eu-0"]
+            ),
+            (
+                "Replica Alerts (best-service-us)",
+                vec!["ReplicaRule-best-service-us-0"]
+            ),
+            ("Custom Alerts", vec!["QueueBacklog"]),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_workload_selector_aggregates_groups() -> Result<()> {
+    let workloads = vec![
+        String::from("best-service-eu"),
+        String::from("best-service-us"),
+    ];
+    let alerts = PromAlerts::for_workloads(&selector_spec(true)?, &workloads)?;
+
+    let names: Vec<_> = alerts.groups.iter().map(|g| g.name.as_str()).collect();
+    assert_eq!(names, vec!["Replica Alerts", "Custom Alerts"]);
+    assert_eq!(alerts.groups[0].rules.len(), 2);
+    assert_eq!(alerts.groups[1].rules.len(), 1);
+
+    // Nothing matched the selector, so there is nothing to alert on
+    assert!(PromAlerts::for_workloads(&selector_spec(true)?, &[])?
+        .groups
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_replica_alerts_follow_workload_kind() -> Result<()> {
+    let mut spec = selector_spec(false)?;
+    spec.workload_selector = None;
+
+    spec.deployment_name = String::new();
+
+    let expression = |kind: WorkloadKind| -> Result<String> {
+        let spec = ServiceAlertSpec {
+            workload_ref: Some(WorkloadRef {
+                kind,
+                name: "best-service-eu".into(),
+            }),
+            ..spec.clone()
+        };
+        let rule = &PromAlerts::try_from(spec)?.groups[0].rules[0];
+
+        // Rule names only need the workload name
+        assert_eq!(rule.alert, "ReplicaRule-best-service-eu-0");
+        Ok(rule.expr.clone())
+    };
+
+    assert_eq!(
+        expression(WorkloadKind::Deployment)?,
+        r#"sum by (deployment) (kube_deployment_status_replicas_available{deployment="best-service-eu"}) < 3"#
+    );
+    assert_eq!(
+        expression(WorkloadKind::StatefulSet)?,
+        r#"sum by (statefulset) (kube_statefulset_status_replicas_ready{statefulset="best-service-eu"}) < 3"#
+    );
+    assert_eq!(
+        expression(WorkloadKind::DaemonSet)?,
+        r#"sum by (daemonset) (kube_daemonset_status_number_available{daemonset="best-service-eu"}) < 3"#
+    );
+    assert_eq!(
+        expression(WorkloadKind::Rollout)?,
+        r#"sum by (name) (rollout_info_replicas_available{name="best-service-eu"}) < 3"#
+    );
+
+    // Deployments named by deploymentName keep counting scraped pods
+    let by_name = spec.for_workload("best-service-eu");
+    assert_eq!(
+        PromAlerts::try_from(by_name)?.groups[0].rules[0].expr,
+        r#"sum by (app_kubernetes_io_name) (up{app_kubernetes_io_name="best-service-eu"}) < 3"#
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_external_labels_added_to_rules_and_selectors() -> Result<()> {
+    let spec = selector_spec(false)?.for_workload("best-service-eu");
+    let cluster = |selectors: bool| Cluster {
+        name: Some("eu-west-1".into()),
+        labels: BTreeMap::from([
+            ("region".into(), "eu".into()),
+            // May not override the labels Cactuar sets itself
+            ("owner".into(), "platform".into()),
+        ]),
+        selectors,
+        ..Cluster::default()
+    };
+
+    let alerts = PromAlerts::try_from(spec.clone())?.with_external_labels(&cluster(false));
+    let replica = &alerts.groups[0].rules[0];
+    assert_eq!(
+        replica.labels.extra,
+        BTreeMap::from([
+            ("cluster".into(), "eu-west-1".into()),
+            ("region".into(), "eu".into()),
+        ])
+    );
+    assert_eq!(replica.labels.owner, "foo");
+    assert!(!replica.expr.contains("eu-west-1"));
+
+    let alerts = PromAlerts::try_from(spec.clone())?.with_external_labels(&cluster(true));
+    assert_eq!(
+        alerts.groups[0].rules[0].expr,
+        r#"sum by (app_kubernetes_io_name) (up{cluster="eu-west-1", region="eu", app_kubernetes_io_name="best-service-eu"}) < 3"#
+    );
+
+    // Custom expressions are left as they were written
+    let custom = &alerts.groups[1].rules[0];
+    assert_eq!(custom.expr, "sum(queue_depth) > 100");
+    assert_eq!(custom.labels.extra["cluster"], "eu-west-1");
+
+    // Without a cluster name or labels, nothing changes
+    let unlabelled = PromAlerts::try_from(spec.clone())?.with_external_labels(&Cluster::default());
+    assert_eq!(unlabelled, PromAlerts::try_from(spec)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_service_alert_labels_take_precedence_over_external_labels() -> Result<()> {
+    let mut spec = selector_spec(false)?.for_workload("best-service-eu");
+    spec.common_labels
+        .extra
+        .insert("region".into(), "us-east".into());
+    let cluster = Cluster {
+        name: Some("eu-west-1".into()),
+        labels: BTreeMap::from([("region".into(), "eu".into())]),
+        ..Cluster::default()
+    };
+
+    let alerts = PromAlerts::try_from(spec)?.with_external_labels(&cluster);
+    let labels = &alerts.groups[0].rules[0].labels.extra;
+    assert_eq!(labels["region"], "us-east");
+    assert_eq!(labels["cluster"], "eu-west-1");
+
+    Ok(())
+}
+
+#[test]
+fn test_cluster_label_names_validated() {
+    let cluster = |label: &str, extra: &str| Cluster {
+        label: label.into(),
+        labels: BTreeMap::from([(extra.into(), "eu".into())]),
+        ..Cluster::default()
+    };
+
+    assert!(cluster("cluster", "region").validate().is_ok());
+    assert!(cluster("_cluster", "region_2").validate().is_ok());
+    for (label, extra) in [
+        ("cluster", "2region"),
+        ("cluster", "region-name"),
+        ("cluster", ""),
+        ("k8s.cluster", "region"),
+        ("cluster", "owner"),
+        ("service_alert", "region"),
+        ("cluster", "cluster_service_alert"),
+    ] {
+        assert!(
+            cluster(label, extra).validate().is_err(),
+            "{label}, {extra} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn test_external_labels_only_change_generated_selectors() -> Result<()> {
+    let mut spec = selector_spec(false)?.for_workload("best-service-eu");
+    let generated = PromAlerts::try_from(spec.clone())?.groups[0].rules[0]
+        .expr
+        .clone();
+    // A custom alert that happens to read exactly like a generated one
+    if let Some(custom) = spec.alerts.custom.as_mut() {
+        custom[0].expr = generated.clone();
+    }
+
+    let cluster = Cluster {
+        name: Some(r#"eu-"west"\1"#.into()),
+        selectors: true,
+        ..Cluster::default()
+    };
+    let alerts = PromAlerts::try_from(spec)?.with_external_labels(&cluster);
+
+    assert_eq!(
+        alerts.groups[0].rules[0].expr,
+        r#"sum by (app_kubernetes_io_name) (up{cluster="eu-\"west\"\\1", app_kubernetes_io_name="best-service-eu"}) < 3"#
+    );
+    assert_eq!(alerts.groups[1].rules[0].expr, generated);
+    assert_eq!(
+        alerts.groups[1].rules[0].labels.extra["cluster"],
+        r#"eu-"west"\1"#
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_schedule_guards_expression() -> Result<()> {
+    let summer = Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap();
+    let winter = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap();
+    let expr = || String::from("up == 0");
+
+    let business_hours = ActiveSchedule {
+        hours: Some(HourRange { start: 9, end: 17 }),
+        weekdays: Some(vec![Weekday::Friday, Weekday::Monday]),
+        timezone: Some("Europe/London".parse()?),
+    };
+    assert_eq!(
+        scheduled_at(expr(), Some(&business_hours), summer),
+        "(up == 0) and on() (hour(vector(time() + 3600)) >= 9 < 17) and on() \
+         (day_of_week(vector(time() + 3600)) == 1 or day_of_week(vector(time() + 3600)) == 5)"
+    );
+
+    // Outside of daylight saving, London is on UTC
+    let overnight = ActiveSchedule {
+        hours: Some(HourRange { start: 22, end: 6 }),
+        weekdays: None,
+        ..business_hours
+    };
+    assert_eq!(
+        scheduled_at(expr(), Some(&overnight), winter),
+        "(up == 0) and on() (hour(vector(time())) >= 22 or hour(vector(time())) < 6)"
+    );
+
+    // Schedules that don't restrict anything leave the expression alone
+    let always = ActiveSchedule {
+        hours: Some(HourRange { start: 0, end: 24 }),
+        weekdays: None,
+        timezone: Some("America/New_York".parse()?),
+    };
+    assert_eq!(scheduled_at(expr(), Some(&always), summer), expr());
+    assert_eq!(scheduled_at(expr(), None, summer), expr());
+
+    Ok(())
+}
+
+#[test]
+fn test_empty_weekdays_rejected() -> Result<()> {
+    let mut conf = network_config(Operation::MoreThan, 5_f32)?;
+    conf.schedule = Some(ActiveSchedule {
+        hours: None,
+        weekdays: Some(Vec::new()),
+        timezone: None,
+    });
+    let spec = test_spec(Alerts {
+        grpc: None,
+        rest: Some(BTreeMap::from([(NetworkAlert::ErrorPercent, vec![conf])])),
+        replica: None,
+        custom: None,
+    });
+
+    let err = PromAlerts::try_from(spec).unwrap_err();
+    assert!(err.to_string().contains("at least one weekday"));
+
+    Ok(())
+}
+
+#[test]
+fn test_next_offset_change_finds_daylight_saving() -> Result<()> {
+    // London moves its clocks forward at 01:00 UTC on the last Sunday of March
+    let change = Utc.with_ymd_and_hms(2023, 3, 26, 1, 0, 0).unwrap();
+    let before = change - chrono::Duration::minutes(2);
+    let within = chrono::Duration::minutes(5);
+
+    let london = ActiveSchedule {
+        hours: Some(HourRange { start: 9, end: 17 }),
+        weekdays: None,
+        timezone: Some("Europe/London".parse()?),
+    };
+    let utc = ActiveSchedule {
+        timezone: None,
+        ..london.clone()
+    };
+
+    assert_eq!(
+        next_offset_change([&utc, &london], before, within),
+        Some(change)
+    );
+    assert_ne!(
+        scheduled_at(String::from("up == 0"), Some(&london), before),
+        scheduled_at(String::from("up == 0"), Some(&london), change)
+    );
+
+    // Nothing changes outside of the window, or without a timezone
+    assert_eq!(next_offset_change([&london], change, within), None);
+    assert_eq!(next_offset_change([&london], before - within, within), None);
+    assert_eq!(next_offset_change([&utc], before, within), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_spec_annotations_added_to_every_rule() -> Result<()> {
+    let mut spec = custom_alert_spec(CustomAlert {
+        alert: "QueueBacklog".into(),
+        expr: "sum(queue_depth) > 100".into(),
+        for_: "10m".parse()?,
+        keep_firing_for: None,
+        with_labels: BTreeMap::from([("severity".into(), "warning".into())]),
+        annotations: CustomAnnotations {
+            summary: "Queue backlog".into(),
+            description: "Queue depth is {{ $value }}".into(),
+        },
+    });
+    spec.annotations = BTreeMap::from([
+        (
+            "runbook_url".into(),
+            "https://runbooks.example.com/{{ $labels.alertname }}".into(),
+        ),
+        ("summary".into(), "Ignored".into()),
+    ]);
+
+    let alerts = PromAlerts::try_from(spec)?;
+    let annotations = &alerts.groups[0].rules[0].annotations;
+
+    assert_eq!(annotations.summary, "Queue backlog");
+    assert_eq!(
+        annotations.extra,
+        BTreeMap::from([(
+            String::from("runbook_url"),
+            String::from("https://runbooks.example.com/{{ $labels.alertname }}")
+        )])
+    );
+
+    let rendered = serde_yaml::to_string(&alerts)?;
+    assert!(rendered.contains("runbook_url: https://runbooks.example.com/"));
+
+    Ok(())
+}
+
+/// Serves a canned response for a Prometheus API `endpoint`, such as `rules`,
+/// on a random local port.
+fn serve(endpoint: &str, response: &'static str) -> Result<PrometheusClient> {
+    let router = axum::Router::new().route(
+        &format!("/prometheus/api/v1/{endpoint}"),
+        axum::routing::get(move || async move { response }),
+    );
+
+    let server = axum::Server::bind(&std::net::SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(router.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    Ok(PrometheusClient::new(&Prometheus {
+        url: Some(format!("http://{addr}/prometheus/")),
+        ..Prometheus::default()
+    })?
+    .expect("url is configured"))
+}
+
+#[tokio::test]
+async fn test_client_gives_up_on_slow_responses() -> Result<()> {
+    let router = axum::Router::new().route(
+        "/api/v1/alerts",
+        axum::routing::get(|| async {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            "{}"
+        }),
+    );
+    let server = axum::Server::bind(&std::net::SocketAddr::from(([127, 0, 0, 1], 0)))
+        .serve(router.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    let client = PrometheusClient::new(&Prometheus {
+        url: Some(format!("http://{addr}")),
+        timeout: std::time::Duration::from_millis(100).into(),
+        ..Prometheus::default()
+    })?
+    .expect("url is configured");
+
+    assert!(matches!(
+        client.alerts().await,
+        Err(PrometheusError::Timeout(_))
+    ));
+
+    Ok(())
+}
+
+const LOADED_RULES: &str = r#"{
+  "status": "success",
+  "data": {
+    "groups": [
+      {
+        "name": "services:example:Custom Alerts",
+        "file": "/etc/prometheus/rules/services_example.yaml",
+        "rules": [
+          {"name": "QueueBacklog", "health": "ok", "type": "alerting", "state": "inactive"},
+          {"name": "QueueStuck", "health": "err", "lastError": "many-to-many matching not allowed", "type": "alerting", "state": "inactive"}
+        ]
+      }
+    ]
+  }
+}"#;
+
+fn custom_alerts(names: &[&str]) -> Result<PromAlerts> {
+    let custom = names
+        .iter()
+        .map(|name| -> Result<CustomAlert> {
+            Ok(CustomAlert {
+                alert: name.to_string(),
+                expr: "sum(queue_depth) > 100".into(),
+                for_: "10m".parse()?,
+                keep_firing_for: None,
+                with_labels: BTreeMap::from([("severity".into(), "warning".into())]),
+                annotations: CustomAnnotations {
+                    summary: "Queue backlog".into(),
+                    description: "Queue backlog".into(),
+                },
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    PromAlerts::try_from(test_spec(Alerts {
+        grpc: None,
+        rest: None,
+        replica: None,
+        custom: Some(custom),
+    }))
+}
+
+#[tokio::test]
+async fn test_rule_verification_against_loaded_rules() -> Result<()> {
+    let prometheus = serve("rules", LOADED_RULES)?;
+    let loaded = prometheus.rules().await?;
+
+    let location = RuleLocation {
+        group_prefix: "services:example:".into(),
+        file_suffix: None,
+    };
+
+    let healthy = RuleVerification::new(&custom_alerts(&["QueueBacklog"])?, &location, &loaded);
+    assert!(healthy.is_ok());
+
+    let verification = RuleVerification::new(
+        &custom_alerts(&["QueueBacklog", "QueueStuck", "QueueGone"])?,
+        &location,
+        &loaded,
+    );
+    assert!(!verification.is_ok());
+    assert_eq!(verification.missing_rules, vec![String::from("QueueGone")]);
+    assert_eq!(
+        verification.unhealthy_rules,
+        vec![(
+            String::from("QueueStuck"),
+            String::from("many-to-many matching not allowed")
+        )]
+    );
+    assert_eq!(
+        verification.summary(),
+        "rules not loaded: QueueGone; QueueStuck is failing: many-to-many matching not allowed"
+    );
+
+    let unprefixed = RuleVerification::new(
+        &custom_alerts(&["QueueBacklog"])?,
+        &RuleLocation::default(),
+        &loaded,
+    );
+    assert_eq!(
+        unprefixed.missing_groups,
+        vec![String::from("Custom Alerts")]
+    );
+
+    Ok(())
+}
+
+const SAME_NAMED_GROUPS: &str = r#"{
+  "status": "success",
+  "data": {
+    "groups": [
+      {
+        "name": "Custom Alerts",
+        "file": "/etc/prometheus/rules/services/other_queue.yaml",
+        "rules": [
+          {"name": "QueueStuck", "health": "ok", "type": "alerting", "state": "inactive"}
+        ]
+      },
+      {
+        "name": "Custom Alerts",
+        "file": "/etc/prometheus/rules/services/services_example.yaml",
+        "rules": [
+          {"name": "QueueBacklog", "health": "ok", "type": "alerting", "state": "inactive"}
+        ]
+      }
+    ]
+  }
+}"#;
+
+#[tokio::test]
+async fn test_rule_verification_tells_same_named_groups_apart() -> Result<()> {
+    let prometheus = serve("rules", SAME_NAMED_GROUPS)?;
+    let loaded = prometheus.rules().await?;
+
+    let example = RuleLocation {
+        group_prefix: String::new(),
+        file_suffix: Some("/services_example.yaml".into()),
+    };
+    assert!(RuleVerification::new(&custom_alerts(&["QueueBacklog"])?, &example, &loaded).is_ok());
+
+    // Loaded, but for another ServiceAlert
+    let verification = RuleVerification::new(&custom_alerts(&["QueueStuck"])?, &example, &loaded);
+    assert_eq!(verification.missing_rules, vec![String::from("QueueStuck")]);
+
+    // Without a file, every group with the name is searched
+    assert!(RuleVerification::new(
+        &custom_alerts(&["QueueBacklog", "QueueStuck"])?,
+        &RuleLocation::default(),
+        &loaded,
+    )
+    .is_ok());
+
+    let elsewhere = RuleLocation {
+        group_prefix: String::new(),
+        file_suffix: Some("/services_missing.yaml".into()),
+    };
+    assert_eq!(
+        RuleVerification::new(&custom_alerts(&["QueueBacklog"])?, &elsewhere, &loaded)
+            .missing_groups,
+        vec![String::from("Custom Alerts")]
+    );
+
+    Ok(())
+}
+
+const ACTIVE_ALERTS: &str = r#"{
+  "status": "success",
+  "data": {
+    "alerts": [
+      {
+        "labels": {"alertname": "QueueBacklog", "severity": "warning", "owner": "foo", "source": "cloud", "queue": "jobs"},
+        "annotations": {"summary": "Queue backlog"},
+        "state": "pending",
+        "activeAt": "2024-01-01T10:05:00Z",
+        "value": "150"
+      },
+      {
+        "labels": {"alertname": "QueueBacklog", "severity": "warning", "owner": "foo", "source": "cloud", "queue": "mail"},
+        "annotations": {"summary": "Queue backlog"},
+        "state": "firing",
+        "activeAt": "2024-01-01T10:00:00Z",
+        "value": "400"
+      },
+      {
+        "labels": {"alertname": "QueueBacklog", "severity": "warning", "owner": "someone-else", "source": "cloud"},
+        "annotations": {"summary": "Queue backlog"},
+        "state": "firing",
+        "activeAt": "2024-01-01T09:00:00Z",
+        "value": "900"
+      },
+      {
+        "labels": {"alertname": "Watchdog", "severity": "none"},
+        "annotations": {},
+        "state": "firing",
+        "activeAt": "2024-01-01T00:00:00Z",
+        "value": "1"
+      }
+    ]
+  }
+}"#;
+
+#[tokio::test]
+async fn test_active_alerts_status_matches_generated_alerts() -> Result<()> {
+    let prometheus = serve("alerts", ACTIVE_ALERTS)?;
+    let active = prometheus.alerts().await?;
+    assert_eq!(active.len(), 4);
+
+    let status = active_alerts_status(
+        &custom_alerts(&["QueueBacklog"])?,
+        &active,
+        "2024-01-01T10:10:00".into(),
+    );
+
+    assert_eq!(
+        status,
+        ActiveAlertsStatus {
+            pending: 1,
+            firing: 1,
+            alerts: vec![
+                ActiveAlertStatus {
+                    alert: "QueueBacklog".into(),
+                    state: "firing".into(),
+                    severity: Some("warning".into()),
+                    active_since: Some("2024-01-01T10:00:00Z".into()),
+                },
+                ActiveAlertStatus {
+                    alert: "QueueBacklog".into(),
+                    state: "pending".into(),
+                    severity: Some("warning".into()),
+                    active_since: Some("2024-01-01T10:05:00Z".into()),
+                },
+            ],
+            changed_at: "2024-01-01T10:10:00".into(),
+        }
+    );
+
+    // Seeing the same alerts later does not count as a change
+    let later = active_alerts_status(
+        &custom_alerts(&["QueueBacklog"])?,
+        &active,
+        "2024-01-01T10:11:00".into(),
+    );
+    assert!(status.same_alerts(&later));
+    let resolved = active_alerts_status(
+        &custom_alerts(&["QueueBacklog"])?,
+        &active[..1],
+        "2024-01-01T10:11:00".into(),
+    );
+    assert!(!status.same_alerts(&resolved));
+
+    Ok(())
+}
+
+#[test]
+fn test_active_alerts_told_apart_by_service_alert() -> Result<()> {
+    // Same owner and rule names, so only the service_alert label differs
+    let generated = |name: &str| -> Result<PromAlerts> {
+        let mut service_alert = ServiceAlert::new(name, test_spec(Alerts::default()));
+        service_alert.metadata.namespace = Some("services".into());
+        Ok(custom_alerts(&["QueueBacklog"])?.with_service_alert_label(&service_alert))
+    };
+    let checkout = generated("checkout")?;
+    let billing = generated("billing")?;
+    assert_eq!(
+        checkout.groups[0].rules[0].labels.extra["service_alert"],
+        "services/checkout"
+    );
+
+    let active = vec![ActiveAlert {
+        labels: BTreeMap::from([
+            ("alertname".into(), "QueueBacklog".into()),
+            ("severity".into(), "warning".into()),
+            ("owner".into(), "foo".into()),
+            ("source".into(), "cloud".into()),
+            ("service_alert".into(), "services/checkout".into()),
+        ]),
+        state: "firing".into(),
+        active_at: Some("2024-01-01T10:00:00Z".into()),
+    }];
+
+    let now = String::from("2024-01-01T10:10:00");
+    assert_eq!(
+        active_alerts_status(&checkout, &active, now.clone()).firing,
+        1
+    );
+    assert_eq!(active_alerts_status(&billing, &active, now).firing, 0);
+
+    Ok(())
+}